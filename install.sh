@@ -1,12 +1,12 @@
 #!/bin/bash
 
 [ "$UID" -ne 0 ] && echo "You should run this script as a root " && exit 1
-[ ! -x dist/web ] || [ ! -x dist/harvester ] && echo "Executables not found in dist/" && exit 1
+[ ! -x dist/web ] || [ ! -x dist/harvester ] || [ ! -x dist/pibq-export ] || [ ! -x dist/pibq-import ] || [ ! -x dist/pibq-admin ] && echo "Executables not found in dist/" && exit 1
 
 mkdir -p /opt/pibq/bin
 
-cp -R web migrations /opt/pibq
-cp dist/web dist/harvester /opt/pibq/bin
+cp -R web /opt/pibq
+cp dist/web dist/harvester dist/pibq-export dist/pibq-import dist/pibq-admin /opt/pibq/bin
 
 chown -R pi /opt/pibq
 
@@ -18,6 +18,9 @@ cp pi_config/pibq_harvester.service /lib/systemd/system/
 
 cp pi_config/pibq.env /etc/default/pibq
 
+mkdir -p /etc/pibq
+[ -f /etc/pibq/pibq.toml ] || cp pi_config/pibq.toml /etc/pibq/pibq.toml
+
 systemctl enable bluetooth_rfcomm
 systemctl enable pibq_harvester
 systemctl enable pibq_web
@@ -0,0 +1,16 @@
+CREATE TABLE devices (
+  id INTEGER PRIMARY KEY NOT NULL,
+  serial_number TEXT NOT NULL,
+  path TEXT,
+  created_at TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX idx_devices_serial ON devices(serial_number);
+
+ALTER TABLE readings ADD COLUMN device_id INTEGER REFERENCES devices(id);
+DROP INDEX idx_readings_time;
+CREATE UNIQUE INDEX idx_readings_time ON readings(device_id, timestamp);
+
+ALTER TABLE connection_statuses ADD COLUMN device_id INTEGER REFERENCES devices(id);
+DROP INDEX idx_conn_sts_time;
+CREATE UNIQUE INDEX idx_conn_sts_time ON connection_statuses(device_id, created_at);
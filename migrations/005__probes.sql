@@ -0,0 +1,73 @@
+CREATE TABLE probes (
+  id INTEGER PRIMARY KEY NOT NULL,
+  device_id INTEGER REFERENCES devices(id),
+  channel INTEGER NOT NULL,
+  name TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX idx_probes_channel ON probes(device_id, channel);
+
+CREATE TABLE probe_readings (
+  reading_id INTEGER NOT NULL REFERENCES readings(id),
+  probe_id INTEGER NOT NULL REFERENCES probes(id),
+  value REAL,
+  PRIMARY KEY (reading_id, probe_id)
+);
+
+CREATE INDEX idx_probe_readings_probe ON probe_readings(probe_id);
+
+CREATE TABLE project_probes (
+  project_id INTEGER NOT NULL REFERENCES projects(id),
+  probe_id INTEGER NOT NULL REFERENCES probes(id),
+  name TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  PRIMARY KEY (project_id, probe_id)
+);
+
+-- one probe per BlueTherm channel, plus a device-less pair for readings recorded before devices existed
+INSERT INTO probes (device_id, channel, name) SELECT id, 1, serial_number || ' #1' FROM devices;
+INSERT INTO probes (device_id, channel, name) SELECT id, 2, serial_number || ' #2' FROM devices;
+INSERT INTO probes (device_id, channel, name) SELECT NULL, 1, 'Probe 1' WHERE EXISTS (SELECT 1 FROM readings WHERE device_id IS NULL);
+INSERT INTO probes (device_id, channel, name) SELECT NULL, 2, 'Probe 2' WHERE EXISTS (SELECT 1 FROM readings WHERE device_id IS NULL);
+
+INSERT INTO probe_readings (reading_id, probe_id, value)
+  SELECT r.id, p.id, r.value1 FROM readings r JOIN probes p ON p.device_id IS r.device_id AND p.channel = 1;
+INSERT INTO probe_readings (reading_id, probe_id, value)
+  SELECT r.id, p.id, r.value2 FROM readings r JOIN probes p ON p.device_id IS r.device_id AND p.channel = 2;
+
+-- each project gets the channels of the devices that recorded during it; one with no readings gets every device's,
+-- as it would have shown before
+INSERT INTO project_probes (project_id, probe_id, name, position)
+  SELECT pr.id, p.id, pr.sensor1_name, 1 FROM projects pr JOIN probes p ON p.channel = 1
+  WHERE EXISTS (SELECT 1 FROM readings r WHERE r.device_id IS p.device_id AND julianday(r.timestamp) BETWEEN julianday(pr.start) AND julianday(pr.end))
+     OR NOT EXISTS (SELECT 1 FROM readings r WHERE julianday(r.timestamp) BETWEEN julianday(pr.start) AND julianday(pr.end));
+INSERT INTO project_probes (project_id, probe_id, name, position)
+  SELECT pr.id, p.id, pr.sensor2_name, 2 FROM projects pr JOIN probes p ON p.channel = 2
+  WHERE EXISTS (SELECT 1 FROM readings r WHERE r.device_id IS p.device_id AND julianday(r.timestamp) BETWEEN julianday(pr.start) AND julianday(pr.end))
+     OR NOT EXISTS (SELECT 1 FROM readings r WHERE julianday(r.timestamp) BETWEEN julianday(pr.start) AND julianday(pr.end));
+
+-- sqlite can't drop columns, so rebuild readings and projects without the fixed sensor columns
+CREATE TABLE readings_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  device_id INTEGER REFERENCES devices(id),
+  timestamp TEXT NOT NULL
+);
+
+INSERT INTO readings_new (id, device_id, timestamp) SELECT id, device_id, timestamp FROM readings;
+DROP TABLE readings;
+ALTER TABLE readings_new RENAME TO readings;
+CREATE UNIQUE INDEX idx_readings_time ON readings(device_id, timestamp);
+
+CREATE TABLE projects_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  start TEXT NOT NULL,
+  end TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+INSERT INTO projects_new (id, name, start, end, created_at, updated_at) SELECT id, name, start, end, created_at, updated_at FROM projects;
+DROP TABLE projects;
+ALTER TABLE projects_new RENAME TO projects;
+CREATE UNIQUE INDEX idx_projects_time ON projects(created_at);
@@ -0,0 +1,68 @@
+-- Times were stored as local-time TEXT with an offset; store them as UTC milliseconds since the epoch instead.
+-- strftime('%s') normalizes the offset, and '%f' supplies the milliseconds it drops.
+
+CREATE TABLE devices_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  serial_number TEXT NOT NULL,
+  path TEXT,
+  created_at INTEGER NOT NULL
+);
+
+INSERT INTO devices_new (id, serial_number, path, created_at)
+  SELECT id, serial_number, path,
+         CAST(strftime('%s', created_at) AS INTEGER) * 1000 + CAST(substr(strftime('%f', created_at), 4) AS INTEGER)
+  FROM devices;
+DROP TABLE devices;
+ALTER TABLE devices_new RENAME TO devices;
+CREATE UNIQUE INDEX idx_devices_serial ON devices(serial_number);
+
+CREATE TABLE connection_statuses_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  device_id INTEGER REFERENCES devices(id),
+  is_connect INTEGER NOT NULL,
+  is_disconnect INTEGER NOT NULL,
+  info TEXT,
+  created_at INTEGER NOT NULL
+);
+
+INSERT INTO connection_statuses_new (id, device_id, is_connect, is_disconnect, info, created_at)
+  SELECT id, device_id, is_connect, is_disconnect, info,
+         CAST(strftime('%s', created_at) AS INTEGER) * 1000 + CAST(substr(strftime('%f', created_at), 4) AS INTEGER)
+  FROM connection_statuses;
+DROP TABLE connection_statuses;
+ALTER TABLE connection_statuses_new RENAME TO connection_statuses;
+CREATE UNIQUE INDEX idx_conn_sts_time ON connection_statuses(device_id, created_at);
+
+CREATE TABLE readings_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  device_id INTEGER REFERENCES devices(id),
+  timestamp INTEGER NOT NULL
+);
+
+INSERT INTO readings_new (id, device_id, timestamp)
+  SELECT id, device_id,
+         CAST(strftime('%s', timestamp) AS INTEGER) * 1000 + CAST(substr(strftime('%f', timestamp), 4) AS INTEGER)
+  FROM readings;
+DROP TABLE readings;
+ALTER TABLE readings_new RENAME TO readings;
+CREATE UNIQUE INDEX idx_readings_time ON readings(device_id, timestamp);
+
+CREATE TABLE projects_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  start INTEGER NOT NULL,
+  end INTEGER NOT NULL,
+  created_at INTEGER NOT NULL,
+  updated_at INTEGER NOT NULL
+);
+
+INSERT INTO projects_new (id, name, start, end, created_at, updated_at)
+  SELECT id, name,
+         CAST(strftime('%s', start) AS INTEGER) * 1000 + CAST(substr(strftime('%f', start), 4) AS INTEGER),
+         CAST(strftime('%s', end) AS INTEGER) * 1000 + CAST(substr(strftime('%f', end), 4) AS INTEGER),
+         CAST(strftime('%s', created_at) AS INTEGER) * 1000 + CAST(substr(strftime('%f', created_at), 4) AS INTEGER),
+         CAST(strftime('%s', updated_at) AS INTEGER) * 1000 + CAST(substr(strftime('%f', updated_at), 4) AS INTEGER)
+  FROM projects;
+DROP TABLE projects;
+ALTER TABLE projects_new RENAME TO projects;
+CREATE UNIQUE INDEX idx_projects_time ON projects(created_at);
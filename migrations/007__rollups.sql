@@ -0,0 +1,29 @@
+-- Per-probe summaries of readings in 1 and 15 minute buckets; bucket is the bucket's start in epoch ms.
+-- count is the number of non-null values in the bucket.
+CREATE TABLE readings_1m (
+  probe_id INTEGER NOT NULL REFERENCES probes(id),
+  bucket INTEGER NOT NULL,
+  count INTEGER NOT NULL,
+  avg REAL,
+  min REAL,
+  max REAL,
+  PRIMARY KEY (probe_id, bucket)
+);
+
+CREATE TABLE readings_15m (
+  probe_id INTEGER NOT NULL REFERENCES probes(id),
+  bucket INTEGER NOT NULL,
+  count INTEGER NOT NULL,
+  avg REAL,
+  min REAL,
+  max REAL,
+  PRIMARY KEY (probe_id, bucket)
+);
+
+CREATE INDEX idx_readings_time_only ON readings(timestamp);
+
+-- The newest reading added to the rollup tables; readings after it are added to their buckets on the next pass,
+-- whenever they were taken. Empty until the first pass.
+CREATE TABLE rollup_progress (
+  reading_id INTEGER NOT NULL
+);
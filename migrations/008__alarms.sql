@@ -0,0 +1,22 @@
+-- threshold is in degrees C, or degrees C per minute for rate rules
+CREATE TABLE alarm_rules (
+  id INTEGER PRIMARY KEY NOT NULL,
+  project_id INTEGER NOT NULL REFERENCES projects(id),
+  probe_id INTEGER NOT NULL REFERENCES probes(id),
+  kind TEXT NOT NULL CHECK (kind IN ('above', 'below', 'target', 'rate')),
+  threshold REAL NOT NULL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_alarm_rules_project ON alarm_rules(project_id);
+
+-- state transitions of each rule; a rule's current state is its newest event, and no events means clear
+CREATE TABLE alarm_events (
+  id INTEGER PRIMARY KEY NOT NULL,
+  rule_id INTEGER NOT NULL REFERENCES alarm_rules(id),
+  state TEXT NOT NULL CHECK (state IN ('triggered', 'acknowledged', 'cleared')),
+  value REAL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_alarm_events_rule ON alarm_events(rule_id, id);
@@ -0,0 +1,11 @@
+-- Where a project's alarms are sent. target is a webhook URL, an email address, or one of the
+-- commands allowed by alerts.commands in the config file
+CREATE TABLE project_notifiers (
+  id INTEGER PRIMARY KEY NOT NULL,
+  project_id INTEGER NOT NULL REFERENCES projects(id),
+  kind TEXT NOT NULL CHECK (kind IN ('webhook', 'email', 'command')),
+  target TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_project_notifiers_project ON project_notifiers(project_id);
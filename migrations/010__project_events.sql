@@ -0,0 +1,14 @@
+-- what each probe in a project is measuring; stalls are looked for on meat, lid openings on the pit
+ALTER TABLE project_probes ADD COLUMN role TEXT NOT NULL DEFAULT 'meat' CHECK (role IN ('meat', 'pit'));
+
+-- things the harvester noticed during a cook. ended_at is NULL while the event is still going on
+CREATE TABLE project_events (
+  id INTEGER PRIMARY KEY NOT NULL,
+  project_id INTEGER NOT NULL REFERENCES projects(id),
+  probe_id INTEGER NOT NULL REFERENCES probes(id),
+  kind TEXT NOT NULL CHECK (kind IN ('stall', 'lid_open')),
+  started_at INTEGER NOT NULL,
+  ended_at INTEGER
+);
+
+CREATE INDEX idx_project_events_project ON project_events(project_id, started_at);
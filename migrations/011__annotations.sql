@@ -0,0 +1,10 @@
+-- notes on a project's timeline, like "wrapped" or "added charcoal"
+CREATE TABLE annotations (
+  id INTEGER PRIMARY KEY NOT NULL,
+  project_id INTEGER NOT NULL REFERENCES projects(id),
+  timestamp INTEGER NOT NULL,
+  text TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_annotations_project ON annotations(project_id, timestamp);
@@ -0,0 +1,2 @@
+-- the unit a project shows temperatures in, "C" or "F"; NULL uses the configured units
+ALTER TABLE projects ADD COLUMN units TEXT CHECK (units IN ('C', 'F'));
@@ -0,0 +1,101 @@
+use chrono::datetime::DateTime;
+use chrono::offset::utc::UTC;
+use std::collections::{HashMap, VecDeque};
+
+use super::models::{AlarmEvent, AlarmKind, AlarmRule, AlarmState, Reading};
+
+// Degrees C a probe must come back past a threshold before an alarm clears, so a probe
+// sitting on the line doesn't flap
+pub const HYSTERESIS: f64 = 0.5;
+
+// Rate rules compare against the oldest reading at least this old, in seconds...
+const RATE_MIN_WINDOW: i64 = 60;
+// ...and forget readings older than this, in seconds
+const RATE_MAX_WINDOW: i64 = 300;
+
+// Checks readings against alarm rules. Keeps a few minutes of each probe's history for rate rules.
+pub struct Evaluator {
+    history: HashMap<i64, VecDeque<(DateTime<UTC>, f64)>>
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator {
+            history: HashMap::new()
+        }
+    }
+
+    // The state changes the reading causes among the given rules; rules for other probes are ignored.
+    // The events aren't saved, and the rules' states aren't updated.
+    pub fn evaluate(&mut self, rules: &[AlarmRule], reading: &Reading) -> Vec<AlarmEvent> {
+        let mut events = vec![];
+
+        for v in reading.values.iter() {
+            let value = match v.value {
+                Some(value) => value,
+                // an unplugged probe leaves its alarms as they are
+                None => continue
+            };
+
+            let rate = self.record(v.probe_id, reading.timestamp, value);
+
+            for rule in rules.iter().filter(|r| r.probe_id == v.probe_id) {
+                let (measured, state) = match rule.kind {
+                    AlarmKind::Rate => match rate {
+                        Some(r) => (r, next_state(rule, r.abs())),
+                        None => continue
+                    },
+                    _ => (value, next_state(rule, value))
+                };
+
+                if let Some(s) = state {
+                    let mut event = AlarmEvent::new(rule.id, s, Some(measured));
+                    event.created_at = reading.timestamp;
+                    events.push(event);
+                }
+            }
+        }
+
+        events
+    }
+
+    // Adds the value to the probe's history, returning its rate of change in degrees per minute
+    // once there's enough history
+    fn record(&mut self, probe_id: i64, timestamp: DateTime<UTC>, value: f64) -> Option<f64> {
+        let history = self.history.entry(probe_id).or_insert_with(VecDeque::new);
+
+        while history.front().map(|&(t, _)| (timestamp - t).num_seconds() > RATE_MAX_WINDOW).unwrap_or(false) {
+            history.pop_front();
+        }
+
+        let rate = history.front().and_then(|&(t, v)| {
+            let secs = (timestamp - t).num_seconds();
+            if secs >= RATE_MIN_WINDOW {
+                Some((value - v) * 60.0 / secs as f64)
+            } else {
+                None
+            }
+        });
+
+        history.push_back((timestamp, value));
+        rate
+    }
+}
+
+// The state a rule moves to for the measured value, if it changes
+fn next_state(rule: &AlarmRule, measured: f64) -> Option<AlarmState> {
+    let t = rule.threshold;
+
+    let (tripped, recovered) = match rule.kind {
+        AlarmKind::Above | AlarmKind::Rate => (measured > t, measured <= t - HYSTERESIS),
+        AlarmKind::Below => (measured < t, measured >= t + HYSTERESIS),
+        // once the target's reached it stays that way
+        AlarmKind::Target => (measured >= t, false)
+    };
+
+    match rule.state {
+        AlarmState::Cleared if tripped => Some(AlarmState::Triggered),
+        AlarmState::Triggered | AlarmState::Acknowledged if recovered => Some(AlarmState::Cleared),
+        _ => None
+    }
+}
@@ -0,0 +1,348 @@
+use chrono::datetime::DateTime;
+use chrono::duration::Duration;
+use chrono::offset::utc::UTC;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use super::models::{ProbeRole, ProbeSeries, Project, ProjectEvent, ProjectEventKind, Reading};
+
+// The band where big cuts plateau while moisture evaporates off the surface, degrees C (150-170F)
+pub const STALL_LOW: f64 = 65.5;
+pub const STALL_HIGH: f64 = 76.7;
+// Slower than this in the band is a stall, degrees C per minute
+pub const STALL_RATE: f64 = 0.05;
+
+// The trend is fit to this many minutes of the most recent readings...
+const FIT_WINDOW: f64 = 30.0;
+// ...which must span at least this many minutes...
+const MIN_SPAN: f64 = 5.0;
+// ...with at least this many readings
+const MIN_POINTS: usize = 5;
+
+// How long a stall usually lasts, in minutes. Once in one, at least MIN_STALL_LEFT more is assumed.
+const TYPICAL_STALL: f64 = 150.0;
+const MIN_STALL_LEFT: f64 = 30.0;
+
+// Meat climbs slower than this, in degrees C per minute; anything faster (a pit coming up to
+// temperature, a pot of water) isn't expected to stall
+const MEAT_MAX_RATE: f64 = 0.5;
+
+// A stall is the meat staying in the band, moving less than STALL_RATE, for this many minutes
+const STALL_SUSTAIN: i64 = 20;
+
+// A lid opening is the pit dropping this many degrees C below its high of the last few minutes...
+const LID_DROP: f64 = 8.0;
+const LID_DROP_WINDOW: i64 = 3;
+// ...and it's over once the pit is back within this of that high...
+const LID_RECOVERED: f64 = 3.0;
+// ...or after this many minutes regardless
+const LID_MAX: i64 = 30;
+
+// Minutes of readings the detector keeps for each probe
+const DETECT_HISTORY: i64 = 30;
+
+// When a probe is expected to reach a target temperature
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Eta {
+    pub probe_id: i64,
+    // degrees C
+    pub target: f64,
+    // None when the probe isn't heading for the target
+    pub at: Option<DateTime<UTC>>,
+    // the recent trend, degrees C per minute
+    pub rate: f64,
+    // 0 to 1
+    pub confidence: f64,
+    pub stalled: bool
+}
+
+impl Eta {
+    pub fn confidence_label(&self) -> &'static str {
+        if self.confidence >= 0.7 {
+            "high"
+        } else if self.confidence >= 0.4 {
+            "medium"
+        } else {
+            "low"
+        }
+    }
+}
+
+impl ToJson for Eta {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("probe_id".to_string(), self.probe_id.to_json());
+        m.insert("target".to_string(), self.target.to_json());
+        m.insert("at".to_string(), match self.at {
+            Some(ref dt) => dt.to_rfc3339().to_json(),
+            None => Json::Null
+        });
+        m.insert("rate".to_string(), self.rate.to_json());
+        m.insert("confidence".to_string(), self.confidence.to_json());
+        m.insert("confidence_label".to_string(), self.confidence_label().to_json());
+        m.insert("stalled".to_string(), self.stalled.to_json());
+        m.to_json()
+    }
+}
+
+// Least squares fit of (minutes, degrees) points, returning the slope and r squared
+fn fit(points: &[(f64, f64)]) -> (f64, f64) {
+    let n = points.len() as f64;
+    let mean_x = points.iter().fold(0.0, |sum, &(x, _)| sum + x) / n;
+    let mean_y = points.iter().fold(0.0, |sum, &(_, y)| sum + y) / n;
+
+    let sxx = points.iter().fold(0.0, |sum, &(x, _)| sum + (x - mean_x) * (x - mean_x));
+    let sxy = points.iter().fold(0.0, |sum, &(x, y)| sum + (x - mean_x) * (y - mean_y));
+    let syy = points.iter().fold(0.0, |sum, &(_, y)| sum + (y - mean_y) * (y - mean_y));
+
+    if sxx == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let slope = sxy / sxx;
+    let r2 = if syy == 0.0 { 0.0 } else { (sxy * sxy) / (sxx * syy) };
+
+    (slope, r2)
+}
+
+// The points within FIT_WINDOW minutes before end, if there are enough to fit
+fn window(points: &[(f64, f64)], end: f64) -> Option<&[(f64, f64)]> {
+    let last = match points.iter().rposition(|&(x, _)| x <= end) {
+        Some(i) => i,
+        None => return None
+    };
+    let first = points.iter().position(|&(x, _)| x >= end - FIT_WINDOW).unwrap_or(last);
+
+    if first > last || last - first + 1 < MIN_POINTS || points[last].0 - points[first].0 < MIN_SPAN {
+        return None;
+    }
+
+    Some(&points[first..last + 1])
+}
+
+fn minutes(m: f64) -> Duration {
+    Duration::seconds((m * 60.0) as i64)
+}
+
+// Estimates when the probe will reach target (degrees C) from its recent readings, allowing for
+// the stall. None if there aren't enough recent readings to say.
+pub fn estimate(series: &ProbeSeries, target: f64, now: DateTime<UTC>) -> Option<Eta> {
+    // minutes relative to now, so the numbers stay small
+    let points: Vec<(f64, f64)> = series.readings.iter()
+        .filter(|p| p.timestamp <= now)
+        .filter_map(|p| p.value.map(|v| ((p.timestamp - now).num_milliseconds() as f64 / 60000.0, v)))
+        .collect();
+
+    let (last_x, current) = match points.last() {
+        Some(&p) => p,
+        None => return None
+    };
+
+    // readings have stopped; there's nothing to go on
+    if -last_x > FIT_WINDOW {
+        return None;
+    }
+
+    let recent = match window(&points, last_x) {
+        Some(w) => w,
+        None => return None
+    };
+    let (rate, r2) = fit(recent);
+    let span = (recent[recent.len() - 1].0 - recent[0].0) / FIT_WINDOW;
+
+    let mut eta = Eta {
+        probe_id: series.probe_id,
+        target: target,
+        at: None,
+        rate: rate,
+        confidence: 0.0,
+        stalled: false
+    };
+
+    if current >= target {
+        eta.at = Some(now + minutes(last_x));
+        eta.confidence = 1.0;
+        return Some(eta);
+    }
+
+    let in_band = current >= STALL_LOW && current <= STALL_HIGH;
+
+    if in_band && rate < STALL_RATE && target > current {
+        eta.stalled = true;
+
+        // the stall began when the probe last came into the band
+        let entered = points.iter().rposition(|&(_, v)| v < STALL_LOW).map(|i| points[i].0);
+        let stalled_for = match entered {
+            Some(x) => last_x - x,
+            None => last_x - points[0].0
+        };
+        let stall_left = (TYPICAL_STALL - stalled_for).max(MIN_STALL_LEFT);
+
+        // once through, it should climb about as fast as it did before
+        let climb = entered.and_then(|x| window(&points, x)).map(|w| fit(w));
+
+        if let Some((climb_rate, climb_r2)) = climb {
+            if climb_rate > 0.0 {
+                eta.at = Some(now + minutes(last_x + stall_left + (target - current) / climb_rate));
+                // nobody knows how long a stall lasts
+                eta.confidence = climb_r2 * 0.3;
+            }
+        }
+
+        return Some(eta);
+    }
+
+    if rate <= 0.0 {
+        return Some(eta);
+    }
+
+    let mut left = (target - current) / rate;
+    eta.confidence = r2 * span.min(1.0);
+
+    // meat below the band with a target above it has a stall ahead
+    if current < STALL_LOW && target > STALL_LOW && rate < MEAT_MAX_RATE {
+        left += TYPICAL_STALL;
+        eta.confidence *= 0.5;
+    }
+
+    eta.at = Some(now + minutes(last_x + left));
+    Some(eta)
+}
+
+// A change the Detector noticed, to be saved
+#[derive(Debug)]
+pub enum Detection {
+    Started(ProjectEvent),
+    // an event only known to be one once it was over, saved whole
+    Occurred(ProjectEvent),
+    // project id, probe id, what ended, and when
+    Ended(i64, i64, ProjectEventKind, DateTime<UTC>)
+}
+
+// Watches readings for stalls on meat probes and lid openings on pit probes. Until it has seen
+// enough it doesn't know either way; once it does, it reports the state it finds, so an event
+// left going on by a restart is ended rather than forgotten.
+pub struct Detector {
+    history: HashMap<i64, VecDeque<(DateTime<UTC>, f64)>>,
+    // by (project id, probe id): whether the meat is stalled
+    stalled: HashMap<(i64, i64), bool>,
+    // by (project id, probe id): the pit's temperature before it dropped, while waiting to see if it recovers
+    lid_open: HashMap<(i64, i64), (DateTime<UTC>, f64)>
+}
+
+impl Detector {
+    pub fn new() -> Detector {
+        Detector {
+            history: HashMap::new(),
+            stalled: HashMap::new(),
+            lid_open: HashMap::new()
+        }
+    }
+
+    // What the reading starts or ends among the given projects' probes
+    pub fn observe(&mut self, projects: &[Project], reading: &Reading) -> Vec<Detection> {
+        let mut detections = vec![];
+        let now = reading.timestamp;
+
+        for v in reading.values.iter() {
+            let value = match v.value {
+                Some(value) => value,
+                None => continue
+            };
+
+            let history = self.history.entry(v.probe_id).or_insert_with(VecDeque::new);
+            history.push_back((now, value));
+            while history.front().map(|&(t, _)| (now - t).num_minutes() > DETECT_HISTORY).unwrap_or(false) {
+                history.pop_front();
+            }
+
+            for project in projects.iter() {
+                let role = match project.probes.iter().find(|p| p.probe_id == v.probe_id) {
+                    Some(p) => p.role,
+                    None => continue
+                };
+
+                let key = (project.id, v.probe_id);
+
+                let detection = match role {
+                    ProbeRole::Meat => check_stall(&mut self.stalled, key, history, now, value),
+                    ProbeRole::Pit => check_lid(&mut self.lid_open, key, history, now, value)
+                };
+
+                if let Some(d) = detection {
+                    detections.push(d);
+                }
+            }
+        }
+
+        detections
+    }
+}
+
+fn check_stall(stalled: &mut HashMap<(i64, i64), bool>, key: (i64, i64), history: &VecDeque<(DateTime<UTC>, f64)>,
+               now: DateTime<UTC>, value: f64) -> Option<Detection> {
+    // not enough history to say either way
+    if !history.front().map(|&(t, _)| (now - t).num_minutes() >= STALL_SUSTAIN).unwrap_or(false) {
+        return None;
+    }
+
+    let window: Vec<(DateTime<UTC>, f64)> = history.iter()
+        .filter(|&&(t, _)| (now - t).num_seconds() <= STALL_SUSTAIN * 60)
+        .cloned()
+        .collect();
+
+    let (start, first) = window[0];
+    let minutes = (now - start).num_seconds() as f64 / 60.0;
+    let rate = if minutes > 0.0 { (value - first) / minutes } else { 0.0 };
+
+    let is_stalled = rate.abs() < STALL_RATE && window.iter().all(|&(_, v)| v >= STALL_LOW && v <= STALL_HIGH);
+    // it has to move a good bit faster than a stall before one is over, so it doesn't flap
+    let is_moving = value < STALL_LOW || value > STALL_HIGH || rate.abs() >= STALL_RATE * 2.0;
+
+    let was = stalled.get(&key).cloned();
+
+    if is_stalled && was != Some(true) {
+        stalled.insert(key, true);
+        Some(Detection::Started(ProjectEvent::new(key.0, key.1, ProjectEventKind::Stall, start)))
+    } else if is_moving && was != Some(false) {
+        stalled.insert(key, false);
+        Some(Detection::Ended(key.0, key.1, ProjectEventKind::Stall, now))
+    } else {
+        None
+    }
+}
+
+fn check_lid(lid_open: &mut HashMap<(i64, i64), (DateTime<UTC>, f64)>, key: (i64, i64), history: &VecDeque<(DateTime<UTC>, f64)>,
+             now: DateTime<UTC>, value: f64) -> Option<Detection> {
+    // a drop is only a lid opening if the pit comes back; one that doesn't is the fire going out, or the probe moving
+    if let Some((opened, before)) = lid_open.get(&key).cloned() {
+        if value >= before - LID_RECOVERED {
+            lid_open.remove(&key);
+            let mut event = ProjectEvent::new(key.0, key.1, ProjectEventKind::LidOpen, opened);
+            event.ended_at = Some(now);
+            return Some(Detection::Occurred(event));
+        }
+
+        if (now - opened).num_minutes() >= LID_MAX {
+            lid_open.remove(&key);
+        }
+
+        return None;
+    }
+
+    // the latest high of the last few minutes; the lid opened just after it
+    let high = history.iter()
+        .filter(|&&(t, _)| (now - t).num_seconds() <= LID_DROP_WINDOW * 60)
+        .fold(None, |high: Option<(DateTime<UTC>, f64)>, &(t, v)| match high {
+            Some((_, h)) if h > v => high,
+            _ => Some((t, v))
+        });
+
+    if let Some((t, h)) = high {
+        if h - value >= LID_DROP {
+            lid_open.insert(key, (t, h));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,42 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ConnectionError {
+    // the transport failed in a way the worker thread can't recover from
+    Transport(io::Error),
+    // the worker thread panicked; carries the panic message
+    ThreadPanicked(String),
+    // the worker thread has exited
+    Stopped
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ConnectionError::Transport(ref err) => write!(f, "transport failed: {}", err),
+            &ConnectionError::ThreadPanicked(ref msg) => write!(f, "worker thread panicked: {}", msg),
+            &ConnectionError::Stopped => write!(f, "worker thread has stopped")
+        }
+    }
+}
+
+impl Error for ConnectionError {
+    fn description(&self) -> &str {
+        match self {
+            &ConnectionError::Transport(_) => "transport failed",
+            &ConnectionError::ThreadPanicked(_) => "worker thread panicked",
+            &ConnectionError::Stopped => "worker thread has stopped"
+        }
+    }
+}
+
+impl From<ConnectionError> for io::Error {
+    fn from(err: ConnectionError) -> io::Error {
+        match err {
+            ConnectionError::Transport(e) => e,
+            e => io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string())
+        }
+    }
+}
@@ -1,122 +1,180 @@
+mod error;
 mod thread_guard;
 
+use std::collections::VecDeque;
 use std::fmt;
 use std::io;
-use std::io::prelude::*;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
 use std::thread;
 use std::time::{Duration, Instant};
-use std::ops::Deref;
 
-use serial::prelude::*;
-use serial;
 use self::thread_guard::*;
-use super::Packet;
+pub use self::error::ConnectionError;
+use super::{message_type, DeviceSettings, Frame, Framer, Packet};
+use super::transport::{self, Transport};
 
-// Serial port read/write timeout in ms
-const SERIAL_TIMEOUT: u64 = 1000;
+// Transport read/write timeout in ms
+const TRANSPORT_TIMEOUT: u64 = 1000;
 
 // Serial port read loop sleep delay (prevent busy spin on IO thread) in ms
 const READER_LOOP_SLEEP: u64 = 250;
 
+// Poll interval while waiting for a SET_INFO acknowledgement, in ms
+const ACK_POLL_INTERVAL: u64 = 50;
+
 pub enum ConnectionEvent {
     Packet(Packet),
-    InvalidPacket(Packet),
+    Discarded(Vec<u8>),
     ReadError(io::Error),
     WriteError(io::Error),
-    Heartbeat
+    Heartbeat,
+    // the worker thread has given up; no further events will follow
+    Fatal(ConnectionError)
 }
 
 impl fmt::Display for ConnectionEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &ConnectionEvent::Packet(ref p) => write!(f, "{}", p),
-            &ConnectionEvent::InvalidPacket(ref p) => write!(f, "Invalid! {}", p),
+            &ConnectionEvent::Discarded(ref bytes) => write!(f, "Discarded {} bytes: {:?}", bytes.len(), bytes),
             &ConnectionEvent::ReadError(ref err) => write!(f, "READ ERROR! [{:?}]", err),
             &ConnectionEvent::WriteError(ref err) => write!(f, "WRITE ERROR! [{:?}]", err),
-            &ConnectionEvent::Heartbeat => write!(f, "Tick Tock")
+            &ConnectionEvent::Heartbeat => write!(f, "Tick Tock"),
+            &ConnectionEvent::Fatal(ref err) => write!(f, "FATAL! [{}]", err)
         }
     }
 }
 
 pub struct Connection {
-    pub tty_path: String,
     event_receiver: Receiver<ConnectionEvent>,
+    pending_events: VecDeque<ConnectionEvent>,
     packet_sender: Sender<Packet>,
     kill_thread_signal: Arc<Mutex<bool>>,
     reader_thread_handle: Option<ThreadHandle<()>>,
 }
 
 impl Connection {
-    pub fn new(tty_path: &str, heartbeat_milliseconds: Option<u64>) -> io::Result<Connection> {
+    // Opens a connection from a device spec; see transport::open
+    pub fn open(spec: &str, heartbeat_milliseconds: Option<u64>) -> io::Result<Connection> {
+        let t = try!(transport::open(spec));
+        Connection::new(t, heartbeat_milliseconds)
+    }
+
+    pub fn new<T>(mut transport: T, heartbeat_milliseconds: Option<u64>) -> io::Result<Connection> where T: Transport + 'static {
+        try!(transport.set_timeout(Duration::from_millis(TRANSPORT_TIMEOUT)));
+
         let (event_sender, event_receiver) = channel::<ConnectionEvent>();
         let (packet_sender, packet_receiver) = channel::<Packet>();
 
         let kill_signal = Arc::new(Mutex::new(false));
 
-        let reader_thread = build_connection_read_thread(tty_path.to_string(), event_sender, packet_receiver, heartbeat_milliseconds, kill_signal.clone());
+        let reader_thread = build_connection_read_thread(transport, event_sender, packet_receiver, heartbeat_milliseconds, kill_signal.clone());
 
         Ok(Connection {
-            tty_path: tty_path.to_string(),
             event_receiver: event_receiver,
+            pending_events: VecDeque::new(),
             packet_sender: packet_sender,
             kill_thread_signal: kill_signal,
             reader_thread_handle: Some(reader_thread)
         })
     }
 
-    pub fn send(&mut self, p: Packet) -> io::Result<()> {
+    pub fn send(&mut self, p: Packet) -> Result<(), ConnectionError> {
         match self.is_ok() {
             true => {
-                self.packet_sender.send(p).unwrap();
-                Ok(())
+                match self.packet_sender.send(p) {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(self.failure())
+                }
             },
-            false => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "worker threads have stopped"))
+            false => Err(self.failure())
         }
     }
 
-    pub fn wait(&self) -> io::Result<ConnectionEvent> {
-        match self.is_ok() {
-            false => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "worker threads have stopped")),
-            true => {
-                Ok(self.event_receiver.recv().unwrap())
+    // Sends the settings to the device and waits for it to acknowledge them.
+    // Any other events received in the meantime are kept for wait/get_events.
+    pub fn configure(&mut self, settings: &DeviceSettings, timeout: Duration) -> io::Result<Packet> {
+        let p = try!(settings.to_packet().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+
+        let request = Packet::from_bytes(&p.data);
+        try!(self.send(p));
+
+        let started = Instant::now();
+
+        loop {
+            match self.event_receiver.try_recv() {
+                Ok(ConnectionEvent::Packet(ack)) => {
+                    if ack.get_command_id() != message_type::SET_INFO {
+                        self.pending_events.push_back(ConnectionEvent::Packet(ack));
+                    } else if ack.is_set_info_ack_for(&request) {
+                        return Ok(ack);
+                    } else {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("SET_INFO acknowledged with flags {} but {} were sent", ack.get_data_flags(), request.get_data_flags())));
+                    }
+                },
+                Ok(ConnectionEvent::Fatal(err)) => { return Err(io::Error::from(err)); },
+                Ok(evt) => { self.pending_events.push_back(evt); },
+                Err(TryRecvError::Empty) => {
+                    if started.elapsed() >= timeout {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "no SET_INFO acknowledgement received"));
+                    }
+                    thread::sleep(Duration::from_millis(ACK_POLL_INTERVAL));
+                },
+                Err(TryRecvError::Disconnected) => { return Err(io::Error::from(self.failure())); }
             }
         }
     }
 
-    pub fn get_events(&self) -> io::Result<Vec<ConnectionEvent>> {
-        match self.is_ok() {
-            false => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "worker threads have stopped")),
-            true => {
-                let mut data: Vec<ConnectionEvent> = vec!();
+    // Blocks for the next event. Events queued before the worker thread stopped are still
+    // delivered; after that the reason it stopped is returned as an error.
+    pub fn wait(&mut self) -> Result<ConnectionEvent, ConnectionError> {
+        if let Some(evt) = self.pending_events.pop_front() {
+            return Ok(evt);
+        }
+
+        match self.event_receiver.recv() {
+            Ok(evt) => Ok(evt),
+            Err(_) => Err(self.failure())
+        }
+    }
+
+    pub fn get_events(&mut self) -> Result<Vec<ConnectionEvent>, ConnectionError> {
+        let mut data: Vec<ConnectionEvent> = self.pending_events.drain(..).collect();
 
-                loop {
-                    match self.event_receiver.try_recv() {
-                        Ok(evt) => { data.push(evt) },
-                        Err(TryRecvError::Empty) => { break; },
-                        Err(TryRecvError::Disconnected) => { panic!("channels should not disconnect") }
+        loop {
+            match self.event_receiver.try_recv() {
+                Ok(evt) => { data.push(evt) },
+                Err(TryRecvError::Empty) => { break; },
+                Err(TryRecvError::Disconnected) => {
+                    if data.is_empty() {
+                        return Err(self.failure());
                     }
+                    break;
                 }
-
-                Ok(data)
             }
         }
-    }
 
-    pub fn is_ok(&self) -> bool {
-        let mut ret = true;
+        Ok(data)
+    }
 
+    // Why the worker thread is no longer running.
+    // Only called once the worker has exited, so its guard is about to record the outcome.
+    fn failure(&self) -> ConnectionError {
         match &self.reader_thread_handle {
-            &Some(ref h) => {
-                ret = ret && match h.status.lock().unwrap().deref() {
-                    &ThreadStatus::Ok => true,
-                    _ => false
-                }
+            &Some(ref h) => match h.wait_finished() {
+                ThreadStatus::Err(msg) => ConnectionError::ThreadPanicked(msg),
+                _ => ConnectionError::Stopped
             },
-            _ => {}
+            &None => ConnectionError::Stopped
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        match &self.reader_thread_handle {
+            &Some(ref h) => h.is_running(),
+            &None => true
         }
-        ret
     }
 }
 
@@ -137,78 +195,86 @@ impl Drop for Connection {
 }
 
 
-fn build_connection_read_thread(tty_path: String, event_sender: Sender<ConnectionEvent>, packet_receiver: Receiver<Packet>, heartbeat: Option<u64>, kill_signal: Arc<Mutex<bool>>) -> ThreadHandle<()> {
-    guard_thread("reader_thread", move || {
-        let mut serial = match serial::open(&tty_path) {
-            Err(e) => {panic!(format!("Unable to create serial port: {}", e)) },
-            Ok(s) => s
-        };
+// Transport errors after which the worker thread gives up
+fn is_fatal(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::NotConnected |
+        io::ErrorKind::UnexpectedEof => true,
+        _ => false
+    }
+}
 
-        match serial.set_timeout(Duration::from_millis(SERIAL_TIMEOUT)) {
-            Err(e) => { panic!(format!("Unable to set serial timeout: {}", e)) },
-            Ok(_) => {}
-        };
+// Sends an event to the Connection; ends the worker thread once the Connection has been dropped
+macro_rules! emit {
+    ($sender:expr, $evt:expr) => {
+        if $sender.send($evt).is_err() {
+            return;
+        }
+    }
+}
 
-        let mut packet_buffer: Vec<u8> = Vec::new();
+fn build_connection_read_thread<T>(mut transport: T, event_sender: Sender<ConnectionEvent>, packet_receiver: Receiver<Packet>, heartbeat: Option<u64>, kill_signal: Arc<Mutex<bool>>) -> ThreadHandle<()>
+    where T: Transport + 'static {
+    guard_thread("reader_thread", move || {
+        let mut framer = Framer::new();
         let mut read_buffer: Vec<u8> = vec![0u8; 128];
         let mut last_read = Instant::now();
         let mut last_heartbeat = Instant::now();
 
         while !*kill_signal.lock().unwrap() {
 
-            if packet_buffer.len() > 0 && last_read.elapsed().as_secs() > 4 {
-                let evt = ConnectionEvent::ReadError(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid packet data: {:?}", packet_buffer.clone())));
-                event_sender.send(evt).unwrap();
-                packet_buffer.clear();
+            if !framer.is_empty() && last_read.elapsed().as_secs() > 4 {
+                emit!(event_sender, ConnectionEvent::Discarded(framer.flush()));
             };
 
             match packet_receiver.try_recv() {
                 Ok(p) => {
-                    match serial.write_all(&p.data) {
+                    match transport.write_all(&p.data) {
                         Ok(_) => {},
                         Err(e) => {
-                            let evt = ConnectionEvent::WriteError(e);
-                            event_sender.send(evt).unwrap();
+                            if is_fatal(&e) {
+                                emit!(event_sender, ConnectionEvent::Fatal(ConnectionError::Transport(e)));
+                                return;
+                            }
+                            emit!(event_sender, ConnectionEvent::WriteError(e));
                         }
                     }
                 },
                 Err(TryRecvError::Empty) => {  },
-                Err(TryRecvError::Disconnected) => { panic!("channels should not disconnect") }
+                Err(TryRecvError::Disconnected) => { return; }
             }
 
-            match serial.read(&mut read_buffer) {
+            match transport.read(&mut read_buffer) {
                 Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {},
                 Err(e) => {
-                    let evt = ConnectionEvent::ReadError(e);
-                    event_sender.send(evt).unwrap();
+                    if is_fatal(&e) {
+                        emit!(event_sender, ConnectionEvent::Fatal(ConnectionError::Transport(e)));
+                        return;
+                    }
+                    emit!(event_sender, ConnectionEvent::ReadError(e));
                 },
                 Ok(bytes) if bytes > 0 => {
                     last_read = Instant::now();
-                    for x in 0 .. bytes {
-                        packet_buffer.push(read_buffer[x]);
-                    }
+                    framer.push(&read_buffer[0 .. bytes]);
                 },
                 Ok(_) => { } // do nothing for 0 bytes read
             };
 
-            while packet_buffer.len() >= 128 {
-                let data: Vec<u8> = packet_buffer.drain(0..128).collect();
-                let p = Packet::from_bytes(&data);
-
-                if p.is_checksum_valid() {
-                    let evt = ConnectionEvent::Packet(p);
-                    event_sender.send(evt).unwrap();
-                } else {
-                    let evt = ConnectionEvent::InvalidPacket(p);
-                    event_sender.send(evt).unwrap();
-                }
+            while let Some(frame) = framer.next_frame() {
+                let evt = match frame {
+                    Frame::Packet(p) => ConnectionEvent::Packet(p),
+                    Frame::Discarded(bytes) => ConnectionEvent::Discarded(bytes)
+                };
+                emit!(event_sender, evt);
             }
 
             match heartbeat {
                 Some(ms) => {
                     if last_heartbeat.elapsed() >= Duration::from_millis(ms) {
-                        let evt = ConnectionEvent::Heartbeat;
-                        event_sender.send(evt).unwrap();
+                        emit!(event_sender, ConnectionEvent::Heartbeat);
                         last_heartbeat = Instant::now();
                     }
                 },
@@ -217,6 +283,5 @@ fn build_connection_read_thread(tty_path: String, event_sender: Sender<Connectio
 
             thread::sleep(Duration::from_millis(READER_LOOP_SLEEP));
         }
-        ()
     })
 }
@@ -1,15 +1,39 @@
-use std::sync::{Arc, Mutex};
+use std::any::Any;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
+#[derive(Clone)]
 pub enum ThreadStatus {
     Ok,
     Done,
-    Err
+    Err(String)
 }
 
 pub struct ThreadHandle<T> {
-    pub status: Arc<Mutex<ThreadStatus>>,
-    pub handle: thread::JoinHandle<T>
+    // signalled once the guard records how the thread ended
+    pub status: Arc<(Mutex<ThreadStatus>, Condvar)>,
+    pub handle: thread::JoinHandle<Option<T>>
+}
+
+impl<T> ThreadHandle<T> {
+    pub fn is_running(&self) -> bool {
+        match *self.status.0.lock().unwrap() {
+            ThreadStatus::Ok => true,
+            _ => false
+        }
+    }
+
+    // Blocks until the guard has recorded how the thread ended
+    pub fn wait_finished(&self) -> ThreadStatus {
+        let &(ref lock, ref changed) = &*self.status;
+        let mut status = lock.lock().unwrap();
+
+        while let ThreadStatus::Ok = *status {
+            status = changed.wait(status).unwrap();
+        }
+
+        status.clone()
+    }
 }
 
 
@@ -19,23 +43,38 @@ pub fn guard_thread<F, T>(name: &str, f: F) -> ThreadHandle<T>
     let name = name.to_string();
     let guard_name = name.clone() + "_guard";
 
-    let status = Arc::new(Mutex::new(ThreadStatus::Ok));
+    let status = Arc::new((Mutex::new(ThreadStatus::Ok), Condvar::new()));
     let tstatus = status.clone();
 
     let inner_handle = thread::Builder::new().name(name).spawn(f).unwrap();
     let handle = thread::Builder::new().name(guard_name).spawn(move || {
         let ret = inner_handle.join();
-        match ret {
+        let &(ref lock, ref changed) = &*tstatus;
+
+        let result = match ret {
             Ok(x) => {
-                *tstatus.lock().unwrap() = ThreadStatus::Done;
-                x
+                *lock.lock().unwrap() = ThreadStatus::Done;
+                Some(x)
             },
             Err(e) => {
-                *tstatus.lock().unwrap() = ThreadStatus::Err;
-                panic!(e);
+                *lock.lock().unwrap() = ThreadStatus::Err(panic_message(&e));
+                None
             }
-        }
+        };
+
+        changed.notify_all();
+        result
     }).unwrap();
 
     ThreadHandle{ handle: handle, status: status }
 }
+
+fn panic_message(e: &Box<Any + Send>) -> String {
+    match e.downcast_ref::<String>() {
+        Some(s) => s.clone(),
+        None => match e.downcast_ref::<&'static str>() {
+            Some(s) => s.to_string(),
+            None => "unknown panic".to_string()
+        }
+    }
+}
@@ -1,10 +1,14 @@
 
 mod packet;
 mod connection;
+pub mod transport;
 
 pub use self::packet::data_flags;
 pub use self::packet::message_type;
 pub use self::packet::Packet;
+pub use self::packet::{DeviceInfo, DeviceSettings, SensorInfo, SensorSettings, SetInfoBuilder, TYPES_FAHRENHEIT};
+pub use self::packet::{Frame, Framer, PACKET_SIZE};
 
 pub use self::connection::Connection;
 pub use self::connection::ConnectionEvent;
+pub use self::connection::ConnectionError;
@@ -21,6 +21,21 @@ pub fn word_in(value: u16, buffer: &mut [u8]) {
     buffer[1] = ((value >> 8) & 0xFF) as u8;
 }
 
+pub fn dword_out(data: &[u8]) -> u32 {
+    let mut value: u32 = data[0] as u32;
+    value += (data[1] as u32) << 8;
+    value += (data[2] as u32) << 16;
+    value += (data[3] as u32) << 24;
+    value
+}
+
+pub fn dword_in(value: u32, buffer: &mut [u8]) {
+    buffer[0] = (value & 0xFF) as u8;
+    buffer[1] = ((value >> 8) & 0xFF) as u8;
+    buffer[2] = ((value >> 16) & 0xFF) as u8;
+    buffer[3] = ((value >> 24) & 0xFF) as u8;
+}
+
 pub fn string_out(data: &[u8]) -> String {
     let mut value = String::new();
     for x in 0 .. data.len() {
@@ -76,6 +91,17 @@ pub fn temperature_in(value: Option<f64>, buffer: &mut [u8]) {
     buffer[3] = ((int_value >> 24) & 0xFF) as u8;
 }
 
+// trim offsets are a signed word in hundredths of a degree
+pub fn trim_out(data: &[u8]) -> f64 {
+    let word = word_out(data) as i16;
+    (word as f64) / 100.0f64
+}
+
+pub fn trim_in(value: f64, buffer: &mut [u8]) {
+    let word = (value * 100.0f64).round() as i16;
+    word_in(word as u16, buffer);
+}
+
 pub fn data_flags_out(data: &[u8]) -> data_flags::DataFlags {
     let word = word_out(data);
     match data_flags::DataFlags::from_bits(word) {
@@ -0,0 +1,71 @@
+use super::data_flags::{self, DataFlags};
+use super::Packet;
+
+// Set in the types field when the unit reads in Fahrenheit rather than Celsius
+pub const TYPES_FAHRENHEIT: u16 = 0x0001;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorInfo {
+    pub name: Option<String>,
+    pub temperature: Option<f64>,
+    pub high_limit: Option<f64>,
+    pub low_limit: Option<f64>,
+    pub trim: Option<f64>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub flags: DataFlags,
+    pub serial_number: Option<String>,
+    pub sensor1: SensorInfo,
+    pub sensor2: SensorInfo,
+    pub battery_volts: Option<f32>,
+    pub cal_values: [Option<u32>; 3],
+    pub firmware_version: Option<u16>,
+    pub types: Option<u16>
+}
+
+impl DeviceInfo {
+    pub fn from_packet(p: &Packet) -> DeviceInfo {
+        let flags = p.get_data_flags();
+
+        DeviceInfo {
+            flags: flags,
+            serial_number: when(flags, data_flags::SERIAL_NUMBER, || p.get_serial_number()),
+            sensor1: SensorInfo {
+                name: when(flags, data_flags::PROBE_NAMES, || p.get_sensor1_name()),
+                temperature: when(flags, data_flags::SENSOR_1_TEMPERATURE, || p.get_sensor1_reading()).and_then(|t| t),
+                high_limit: when(flags, data_flags::SENSOR_1_HIGH_LIMIT, || p.get_sensor1_high_limit()).and_then(|t| t),
+                low_limit: when(flags, data_flags::SENSOR_1_LOW_LIMIT, || p.get_sensor1_low_limit()).and_then(|t| t),
+                trim: when(flags, data_flags::SENSOR_1_TRIM, || p.get_sensor1_trim())
+            },
+            sensor2: SensorInfo {
+                name: when(flags, data_flags::PROBE_NAMES, || p.get_sensor2_name()),
+                temperature: when(flags, data_flags::SENSOR_2_TEMPERATURE, || p.get_sensor2_reading()).and_then(|t| t),
+                high_limit: when(flags, data_flags::SENSOR_2_HIGH_LIMIT, || p.get_sensor2_high_limit()).and_then(|t| t),
+                low_limit: when(flags, data_flags::SENSOR_2_LOW_LIMIT, || p.get_sensor2_low_limit()).and_then(|t| t),
+                trim: when(flags, data_flags::SENSOR_2_TRIM, || p.get_sensor2_trim())
+            },
+            battery_volts: when(flags, data_flags::BATTERY_CONDITION, || p.get_battery_volts()),
+            cal_values: [
+                when(flags, data_flags::CAL_VALUE_1, || p.get_cal_value1()),
+                when(flags, data_flags::CAL_VALUE_2, || p.get_cal_value2()),
+                when(flags, data_flags::CAL_VALUE_3, || p.get_cal_value3())
+            ],
+            firmware_version: when(flags, data_flags::FIRMWARE_VERSION, || p.get_firmware_version()),
+            types: when(flags, data_flags::TYPES, || p.get_types())
+        }
+    }
+
+    // Whether the temperatures are in Fahrenheit, if the packet included the types field
+    pub fn is_fahrenheit(&self) -> Option<bool> {
+        self.types.map(|t| t & TYPES_FAHRENHEIT != 0)
+    }
+}
+
+fn when<T, F>(flags: DataFlags, flag: DataFlags, f: F) -> Option<T> where F: FnOnce() -> T {
+    match flags.contains(flag) {
+        true => Some(f()),
+        false => None
+    }
+}
@@ -0,0 +1,66 @@
+use super::message_type;
+use super::{Packet, PACKET_SIZE};
+
+pub enum Frame {
+    Packet(Packet),
+    Discarded(Vec<u8>)
+}
+
+// Splits a byte stream into packets.
+// Instead of blindly cutting every PACKET_SIZE bytes, it slides through the buffer looking for the
+// next offset holding a valid packet, so a dropped or extra byte only costs the bytes around it.
+pub struct Framer {
+    buffer: Vec<u8>
+}
+
+impl Framer {
+    pub fn new() -> Framer {
+        Framer { buffer: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    // Returns the next packet, or the bytes skipped to reach it.
+    // None means more data is needed.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        let len = self.buffer.len();
+        if len < PACKET_SIZE {
+            return None;
+        }
+
+        for offset in 0 .. (len - PACKET_SIZE + 1) {
+            if is_frame(&self.buffer[offset .. (offset + PACKET_SIZE)]) {
+                if offset > 0 {
+                    return Some(Frame::Discarded(self.buffer.drain(0 .. offset).collect()));
+                }
+
+                let data: Vec<u8> = self.buffer.drain(0 .. PACKET_SIZE).collect();
+                return Some(Frame::Packet(Packet::from_bytes(&data)));
+            }
+        }
+
+        // no complete packet in the buffer; only the tail could still be the start of one
+        let garbage = len - (PACKET_SIZE - 1);
+        Some(Frame::Discarded(self.buffer.drain(0 .. garbage).collect()))
+    }
+
+    // Drops any buffered partial data
+    pub fn flush(&mut self) -> Vec<u8> {
+        self.buffer.drain(..).collect()
+    }
+}
+
+fn is_frame(data: &[u8]) -> bool {
+    let p = Packet::from_bytes(data);
+    p.is_checksum_valid() && p.get_command_id() != message_type::NOTHING
+}
@@ -1,12 +1,48 @@
 
 mod crc;
 mod converters;
+mod device_info;
+mod framer;
+mod set_info;
 pub mod data_flags;
 pub mod message_type;
 
 use std::cmp;
 use std::fmt;
 
+pub use self::device_info::{DeviceInfo, SensorInfo, TYPES_FAHRENHEIT};
+pub use self::set_info::{DeviceSettings, SensorSettings, SetInfoBuilder};
+pub use self::framer::{Frame, Framer};
+
+pub const PACKET_SIZE: usize = 128;
+
+// Packet layout (all multi-byte values are little endian):
+//
+//   0x00  1  command id
+//   0x01  1  version
+//   0x02  2  data flags
+//   0x04 10  serial number
+//   0x0E 20  sensor 1 probe name
+//   0x22 20  sensor 2 probe name
+//   0x36  4  sensor 1 temperature
+//   0x3A  4  sensor 1 high limit
+//   0x3E  4  sensor 1 low limit
+//   0x42  2  sensor 1 trim
+//   0x44  6  reserved
+//   0x4A  4  sensor 2 temperature
+//   0x4E  4  sensor 2 high limit
+//   0x52  4  sensor 2 low limit
+//   0x56  2  sensor 2 trim
+//   0x58  6  reserved
+//   0x5E  2  battery condition
+//   0x60  4  calibration value 1
+//   0x64  4  calibration value 2
+//   0x68  4  calibration value 3
+//   0x6C  2  firmware version
+//   0x6E  2  types (bit 0 set when temperatures are in Fahrenheit)
+//   0x70 14  reserved
+//   0x7E  2  checksum
+
 pub struct Packet {
   pub data: [u8; 128]
 }
@@ -43,6 +79,26 @@ impl Packet {
       p
   }
 
+  // Temperatures along with the serial number, so readings can be attributed to a device
+  pub fn readings_packet() -> Packet {
+      let mut p = Packet::new();
+      p.set_command_id(message_type::RETRIEVE_INFO);
+      p.set_version(1);
+      p.set_data_flags(data_flags::READINGS);
+      p.apply_checksum();
+      p
+  }
+
+  pub fn set_info() -> SetInfoBuilder {
+      SetInfoBuilder::new()
+  }
+
+  // A SET_INFO acknowledgement echoes the command and the flags that were written
+  pub fn is_set_info_ack_for(&self, request: &Packet) -> bool {
+      self.get_command_id() == message_type::SET_INFO &&
+          self.get_data_flags().contains(request.get_data_flags())
+  }
+
   pub fn calculate_checksum(&self) -> u16 {
       crc::compute_checksum(&self.data[0 .. 126])
   }
@@ -88,6 +144,22 @@ impl Packet {
     self.set_field(4, 10, value, converters::string_in);
   }
 
+  pub fn get_sensor1_name(&self) -> String {
+      self.get_field(0x0E, 20, converters::string_out)
+  }
+
+  pub fn set_sensor1_name(&mut self, value: &str) {
+      self.set_field(0x0E, 20, value, converters::string_in);
+  }
+
+  pub fn get_sensor2_name(&self) -> String {
+      self.get_field(0x22, 20, converters::string_out)
+  }
+
+  pub fn set_sensor2_name(&mut self, value: &str) {
+      self.set_field(0x22, 20, value, converters::string_in);
+  }
+
   pub fn get_sensor1_reading(&self) -> Option<f64> {
       self.get_field(54, 4, converters::temperature_out)
   }
@@ -96,6 +168,30 @@ impl Packet {
       self.set_field(54, 4, value, converters::temperature_in);
   }
 
+  pub fn get_sensor1_high_limit(&self) -> Option<f64> {
+      self.get_field(0x3A, 4, converters::temperature_out)
+  }
+
+  pub fn set_sensor1_high_limit(&mut self, value: Option<f64>) {
+      self.set_field(0x3A, 4, value, converters::temperature_in);
+  }
+
+  pub fn get_sensor1_low_limit(&self) -> Option<f64> {
+      self.get_field(0x3E, 4, converters::temperature_out)
+  }
+
+  pub fn set_sensor1_low_limit(&mut self, value: Option<f64>) {
+      self.set_field(0x3E, 4, value, converters::temperature_in);
+  }
+
+  pub fn get_sensor1_trim(&self) -> f64 {
+      self.get_field(0x42, 2, converters::trim_out)
+  }
+
+  pub fn set_sensor1_trim(&mut self, value: f64) {
+      self.set_field(0x42, 2, value, converters::trim_in);
+  }
+
   pub fn get_sensor2_reading(&self) -> Option<f64> {
       self.get_field(74, 4, converters::temperature_out)
   }
@@ -104,6 +200,30 @@ impl Packet {
       self.set_field(74, 4, value, converters::temperature_in);
   }
 
+  pub fn get_sensor2_high_limit(&self) -> Option<f64> {
+      self.get_field(0x4E, 4, converters::temperature_out)
+  }
+
+  pub fn set_sensor2_high_limit(&mut self, value: Option<f64>) {
+      self.set_field(0x4E, 4, value, converters::temperature_in);
+  }
+
+  pub fn get_sensor2_low_limit(&self) -> Option<f64> {
+      self.get_field(0x52, 4, converters::temperature_out)
+  }
+
+  pub fn set_sensor2_low_limit(&mut self, value: Option<f64>) {
+      self.set_field(0x52, 4, value, converters::temperature_in);
+  }
+
+  pub fn get_sensor2_trim(&self) -> f64 {
+      self.get_field(0x56, 2, converters::trim_out)
+  }
+
+  pub fn set_sensor2_trim(&mut self, value: f64) {
+      self.set_field(0x56, 2, value, converters::trim_in);
+  }
+
   pub fn get_battery_volts(&self) -> f32 {
       self.get_field(0x5E, 2, converters::battery_out)
   }
@@ -112,6 +232,46 @@ impl Packet {
       self.set_field(0x5E, 2, value, converters::battery_in);
   }
 
+  pub fn get_cal_value1(&self) -> u32 {
+      self.get_field(0x60, 4, converters::dword_out)
+  }
+
+  pub fn set_cal_value1(&mut self, value: u32) {
+      self.set_field(0x60, 4, value, converters::dword_in);
+  }
+
+  pub fn get_cal_value2(&self) -> u32 {
+      self.get_field(0x64, 4, converters::dword_out)
+  }
+
+  pub fn set_cal_value2(&mut self, value: u32) {
+      self.set_field(0x64, 4, value, converters::dword_in);
+  }
+
+  pub fn get_cal_value3(&self) -> u32 {
+      self.get_field(0x68, 4, converters::dword_out)
+  }
+
+  pub fn set_cal_value3(&mut self, value: u32) {
+      self.set_field(0x68, 4, value, converters::dword_in);
+  }
+
+  pub fn get_firmware_version(&self) -> u16 {
+      self.get_field(0x6C, 2, converters::word_out)
+  }
+
+  pub fn set_firmware_version(&mut self, value: u16) {
+      self.set_field(0x6C, 2, value, converters::word_in);
+  }
+
+  pub fn get_types(&self) -> u16 {
+      self.get_field(0x6E, 2, converters::word_out)
+  }
+
+  pub fn set_types(&mut self, value: u16) {
+      self.set_field(0x6E, 2, value, converters::word_in);
+  }
+
   pub fn get_checksum(&self) -> u16 {
       self.get_field(0x7E, 2, converters::word_out)
   }
@@ -120,6 +280,11 @@ impl Packet {
       self.set_field(0x7E, 2, value, converters::word_in);
   }
 
+  // Decodes every field the device reported, according to the packet's data flags
+  pub fn decode(&self) -> DeviceInfo {
+      DeviceInfo::from_packet(self)
+  }
+
   fn get_field<T>(&self, start: usize, length: usize, converter: fn(&[u8]) -> T) -> T {
     let chunk = &self.data[start .. (start + length)];
     converter(chunk)
@@ -0,0 +1,151 @@
+use super::data_flags::{self, DataFlags};
+use super::message_type;
+use super::Packet;
+
+// Longest probe name the packet holds, in bytes
+pub const NAME_LENGTH: usize = 20;
+// Range of a limit the packet can hold, in degrees
+const LIMIT_MIN: f64 = -300.0;
+const LIMIT_MAX: f64 = 42000.0;
+// Largest trim the packet can hold either way, in degrees
+const TRIM_MAX: f64 = 327.67;
+
+// A probe's settings for DeviceSettings; anything left None is left as it is on the device
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SensorSettings {
+    // Some(None) clears the limit
+    pub high_limit: Option<Option<f64>>,
+    pub low_limit: Option<Option<f64>>,
+    pub trim: Option<f64>
+}
+
+// Settings to change on a device with Connection::configure
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceSettings {
+    // the device only takes both names at once
+    pub names: Option<(String, String)>,
+    pub sensor1: SensorSettings,
+    pub sensor2: SensorSettings
+}
+
+impl DeviceSettings {
+    // The SET_INFO packet for the settings, or why they can't be sent
+    pub fn to_packet(&self) -> Result<Packet, String> {
+        let mut builder = SetInfoBuilder::new();
+
+        if let Some((ref name1, ref name2)) = self.names {
+            for name in [name1, name2].iter() {
+                if name.len() > NAME_LENGTH || !name.chars().all(|c| (c as u32) < 128 && c != '\0') {
+                    return Err(format!("probe names must be at most {} ASCII characters: {}", NAME_LENGTH, name));
+                }
+            }
+            builder = builder.probe_names(name1, name2);
+        }
+
+        try!(check_sensor(1, &self.sensor1));
+        try!(check_sensor(2, &self.sensor2));
+
+        if let Some(v) = self.sensor1.high_limit { builder = builder.sensor1_high_limit(v); }
+        if let Some(v) = self.sensor1.low_limit { builder = builder.sensor1_low_limit(v); }
+        if let Some(v) = self.sensor1.trim { builder = builder.sensor1_trim(v); }
+        if let Some(v) = self.sensor2.high_limit { builder = builder.sensor2_high_limit(v); }
+        if let Some(v) = self.sensor2.low_limit { builder = builder.sensor2_low_limit(v); }
+        if let Some(v) = self.sensor2.trim { builder = builder.sensor2_trim(v); }
+
+        if builder.flags == data_flags::NONE {
+            return Err("no settings to change".to_string());
+        }
+
+        Ok(builder.build())
+    }
+}
+
+fn check_sensor(sensor: u8, settings: &SensorSettings) -> Result<(), String> {
+    for limit in [settings.high_limit, settings.low_limit].iter() {
+        if let &Some(Some(v)) = limit {
+            if !(v >= LIMIT_MIN && v <= LIMIT_MAX) {
+                return Err(format!("sensor {} limit {} is out of range", sensor, v));
+            }
+        }
+    }
+
+    if let (Some(Some(high)), Some(Some(low))) = (settings.high_limit, settings.low_limit) {
+        if low > high {
+            return Err(format!("sensor {} low limit {} is above its high limit {}", sensor, low, high));
+        }
+    }
+
+    if let Some(trim) = settings.trim {
+        if !(trim.abs() <= TRIM_MAX) {
+            return Err(format!("sensor {} trim {} is out of range", sensor, trim));
+        }
+    }
+
+    Ok(())
+}
+
+// Builds a SET_INFO packet; only the fields that are given are flagged for the device to update
+pub struct SetInfoBuilder {
+    packet: Packet,
+    flags: DataFlags
+}
+
+impl SetInfoBuilder {
+    pub fn new() -> SetInfoBuilder {
+        SetInfoBuilder {
+            packet: Packet::new(),
+            flags: data_flags::NONE
+        }
+    }
+
+    pub fn probe_names(mut self, sensor1: &str, sensor2: &str) -> SetInfoBuilder {
+        self.packet.set_sensor1_name(sensor1);
+        self.packet.set_sensor2_name(sensor2);
+        self.flags = self.flags | data_flags::PROBE_NAMES;
+        self
+    }
+
+    pub fn sensor1_high_limit(mut self, value: Option<f64>) -> SetInfoBuilder {
+        self.packet.set_sensor1_high_limit(value);
+        self.flags = self.flags | data_flags::SENSOR_1_HIGH_LIMIT;
+        self
+    }
+
+    pub fn sensor1_low_limit(mut self, value: Option<f64>) -> SetInfoBuilder {
+        self.packet.set_sensor1_low_limit(value);
+        self.flags = self.flags | data_flags::SENSOR_1_LOW_LIMIT;
+        self
+    }
+
+    pub fn sensor1_trim(mut self, value: f64) -> SetInfoBuilder {
+        self.packet.set_sensor1_trim(value);
+        self.flags = self.flags | data_flags::SENSOR_1_TRIM;
+        self
+    }
+
+    pub fn sensor2_high_limit(mut self, value: Option<f64>) -> SetInfoBuilder {
+        self.packet.set_sensor2_high_limit(value);
+        self.flags = self.flags | data_flags::SENSOR_2_HIGH_LIMIT;
+        self
+    }
+
+    pub fn sensor2_low_limit(mut self, value: Option<f64>) -> SetInfoBuilder {
+        self.packet.set_sensor2_low_limit(value);
+        self.flags = self.flags | data_flags::SENSOR_2_LOW_LIMIT;
+        self
+    }
+
+    pub fn sensor2_trim(mut self, value: f64) -> SetInfoBuilder {
+        self.packet.set_sensor2_trim(value);
+        self.flags = self.flags | data_flags::SENSOR_2_TRIM;
+        self
+    }
+
+    pub fn build(mut self) -> Packet {
+        self.packet.set_command_id(message_type::SET_INFO);
+        self.packet.set_version(1);
+        self.packet.set_data_flags(self.flags);
+        self.packet.apply_checksum();
+        self.packet
+    }
+}
@@ -0,0 +1,46 @@
+mod pipe;
+mod serial_port;
+mod tcp;
+
+use std::io;
+use std::time::Duration;
+
+pub use self::pipe::{pipe, PipeTransport};
+pub use self::serial_port::SerialTransport;
+pub use self::tcp::TcpTransport;
+
+// Prefix used to select a TCP transport (e.g. ser2net on another host) instead of a local tty
+const TCP_PREFIX: &'static str = "tcp://";
+
+// A byte stream to a BlueTherm device.
+// Reads must give up with an ErrorKind::TimedOut error once the configured timeout elapses.
+pub trait Transport: Send {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        (**self).set_timeout(timeout)
+    }
+}
+
+// Opens a transport from a device spec: either "tcp://host:port" or a tty path
+pub fn open(spec: &str) -> io::Result<Box<Transport>> {
+    if spec.starts_with(TCP_PREFIX) {
+        let t = try!(TcpTransport::connect(&spec[TCP_PREFIX.len() ..]));
+        Ok(Box::new(t))
+    } else {
+        let t = try!(SerialTransport::open(spec));
+        Ok(Box::new(t))
+    }
+}
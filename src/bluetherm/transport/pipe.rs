@@ -0,0 +1,87 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Transport;
+
+// Default read timeout of a pipe end, in ms
+const DEFAULT_TIMEOUT: u64 = 1000;
+
+struct Channel {
+    data: Mutex<VecDeque<u8>>,
+    available: Condvar
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            data: Mutex::new(VecDeque::new()),
+            available: Condvar::new()
+        }
+    }
+}
+
+// One end of an in-memory, bidirectional byte pipe
+pub struct PipeTransport {
+    incoming: Arc<Channel>,
+    outgoing: Arc<Channel>,
+    timeout: Duration
+}
+
+// Creates a connected pair of pipe ends; bytes written to one can be read from the other
+pub fn pipe() -> (PipeTransport, PipeTransport) {
+    let a = Arc::new(Channel::new());
+    let b = Arc::new(Channel::new());
+
+    let first = PipeTransport {
+        incoming: a.clone(),
+        outgoing: b.clone(),
+        timeout: Duration::from_millis(DEFAULT_TIMEOUT)
+    };
+
+    let second = PipeTransport {
+        incoming: b,
+        outgoing: a,
+        timeout: Duration::from_millis(DEFAULT_TIMEOUT)
+    };
+
+    (first, second)
+}
+
+impl Transport for PipeTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let mut data = self.incoming.data.lock().unwrap();
+
+        while data.is_empty() {
+            let elapsed = started.elapsed();
+            if elapsed >= self.timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+            }
+
+            let (guard, _) = self.incoming.available.wait_timeout(data, self.timeout - elapsed).unwrap();
+            data = guard;
+        }
+
+        let count = cmp::min(buf.len(), data.len());
+        for x in 0 .. count {
+            buf[x] = data.pop_front().unwrap();
+        }
+
+        Ok(count)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut data = self.outgoing.data.lock().unwrap();
+        data.extend(buf.iter().cloned());
+        self.outgoing.available.notify_all();
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
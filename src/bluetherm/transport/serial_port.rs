@@ -0,0 +1,33 @@
+use std::io;
+use std::io::prelude::*;
+use std::time::Duration;
+
+use serial::prelude::*;
+use serial;
+use super::Transport;
+
+pub struct SerialTransport {
+    port: serial::SystemPort
+}
+
+impl SerialTransport {
+    pub fn open(tty_path: &str) -> io::Result<SerialTransport> {
+        let port = try!(serial::open(tty_path));
+        Ok(SerialTransport { port: port })
+    }
+}
+
+impl Transport for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Write::write_all(&mut self.port, buf)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        try!(self.port.set_timeout(timeout));
+        Ok(())
+    }
+}
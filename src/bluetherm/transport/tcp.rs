@@ -0,0 +1,45 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::Transport;
+
+pub struct TcpTransport {
+    stream: TcpStream
+}
+
+impl TcpTransport {
+    pub fn connect(address: &str) -> io::Result<TcpTransport> {
+        let stream = try!(TcpStream::connect(address));
+        Ok(TcpTransport::from_stream(stream))
+    }
+
+    pub fn from_stream(stream: TcpStream) -> TcpTransport {
+        TcpTransport { stream: stream }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            // socket timeouts surface as WouldBlock on unix; normalize to match serial ports
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"))
+            },
+            Ok(0) if buf.len() > 0 => {
+                Err(io::Error::new(io::ErrorKind::ConnectionAborted, "connection closed by remote host"))
+            },
+            r => r
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Write::write_all(&mut self.stream, buf)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        try!(self.stream.set_read_timeout(Some(timeout)));
+        self.stream.set_write_timeout(Some(timeout))
+    }
+}
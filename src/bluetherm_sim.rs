@@ -0,0 +1,87 @@
+extern crate getopts;
+extern crate pibq;
+
+use getopts::Options;
+use std::env;
+use std::net::TcpListener;
+
+use pibq::bluetherm::transport::TcpTransport;
+use pibq::sim::curve::Curve;
+use pibq::sim::device::{self, SimDevice};
+use pibq::sim::faults::FaultPlan;
+use pibq::sim::pty::PtyTransport;
+
+// Default probe curves: a pit that comes up to temp with the occasional lid opening,
+// and a meat probe that ramps, stalls, then finishes
+const DEFAULT_PROBE1: &'static str = "20,ramp:120:1200,hold:3600,dip:40:180,hold:7200,dip:40:180,hold:7200";
+const DEFAULT_PROBE2: &'static str = "5,ramp:68:10800,hold:7200,ramp:95:7200";
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("t", "tcp", "listen for TCP connections on this address", "ADDR");
+    opts.optflag("p", "pty", "create a pseudo-terminal and print its path");
+    opts.optopt("", "serial", "serial number to report", "SERIAL");
+    opts.optopt("1", "probe1", "curve script for probe 1: start,ramp:TEMP:SECS,hold:SECS,dip:DEPTH:SECS,unplug:SECS", "SCRIPT");
+    opts.optopt("2", "probe2", "curve script for probe 2", "SCRIPT");
+    opts.optopt("x", "speed", "simulated seconds per real second", "FACTOR");
+    opts.optopt("f", "faults", "faults to inject, e.g. crc:10,truncate:25,silence:40", "SPEC");
+    opts.optflag("", "fahrenheit", "report temperatures in Fahrenheit");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            println!("{}", f.to_string());
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    if matches.opt_present("h") || (matches.opt_present("t") == matches.opt_present("p")) {
+        print_usage(&program, opts);
+        return;
+    }
+
+    let curve1 = Curve::parse(&matches.opt_str("1").unwrap_or(DEFAULT_PROBE1.to_string())).unwrap();
+    let curve2 = Curve::parse(&matches.opt_str("2").unwrap_or(DEFAULT_PROBE2.to_string())).unwrap();
+    let faults = FaultPlan::parse(&matches.opt_str("f").unwrap_or("".to_string())).unwrap();
+    let speed = matches.opt_str("x").map(|s| s.parse::<f64>().unwrap()).unwrap_or(1f64);
+    let serial = matches.opt_str("serial").unwrap_or("SIM0000001".to_string());
+
+    let mut device = SimDevice::new(&serial, [curve1, curve2], faults, speed);
+    device.fahrenheit = matches.opt_present("fahrenheit");
+
+    match matches.opt_str("t") {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr.as_str()).unwrap();
+            println!("listening on {}", addr);
+
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => { println!("accept failed: {}", e); continue; }
+                };
+
+                println!("client connected");
+                let mut transport = TcpTransport::from_stream(stream);
+                if let Err(e) = device::serve(&mut transport, &mut device) {
+                    println!("client disconnected: {}", e);
+                }
+            }
+        },
+        None => {
+            let mut transport = PtyTransport::open().unwrap();
+            println!("pty ready at {}", transport.slave_path);
+
+            if let Err(e) = device::serve(&mut transport, &mut device) {
+                println!("pty failed: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use rustc_serialize::Decodable;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use toml;
+
+use super::models::TemperatureUnit;
+use super::reconnect::ReconnectPolicy;
+
+// Location of the shared config file when none is given on the command line
+pub const DEFAULT_PATH: &'static str = "/etc/pibq/pibq.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ConfigError::Io(ref err) => write!(f, "unable to read config: {}", err),
+            &ConfigError::Parse(ref msg) => write!(f, "invalid config: {}", msg)
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        match self {
+            &ConfigError::Io(_) => "unable to read config",
+            &ConfigError::Parse(_) => "invalid config"
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DatabaseConfig {
+    pub path: String,
+    // days to keep raw readings outside any project once they're rolled up; None keeps them forever
+    pub retention_days: Option<u32>
+}
+
+#[derive(Clone, Debug)]
+pub struct HarvesterConfig {
+    // tty paths or tcp://host:port specs
+    pub devices: Vec<String>,
+    // interval between sending query packets, in ms
+    pub query_interval: u64,
+    // if no packets are returned, amount of time to wait before creating a timeout error, in ms
+    pub timeout_interval: u64,
+    // heartbeat interval, in ms
+    pub heartbeat_interval: u64,
+    // interval between rollup / retention passes, in seconds
+    pub maintenance_interval: u64,
+    // what the thermometers report in when they don't say; readings are stored in C either way
+    pub device_units: TemperatureUnit,
+    pub reconnect: ReconnectPolicy
+}
+
+#[derive(Clone, Debug)]
+pub struct WebConfig {
+    pub bind: String,
+    pub port: u16,
+    pub webroot: String,
+    pub pool_size: u32
+}
+
+#[derive(Clone, Debug)]
+pub struct AlertConfig {
+    // host:port of the SMTP relay used for email alerts
+    pub smtp_server: Option<String>,
+    pub smtp_from: String,
+    // minimum time between repeated notifications for the same alarm, in seconds
+    pub min_interval: u64,
+    // programs projects may use as command notifiers
+    pub commands: Vec<String>
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    // display unit for projects that don't choose one
+    pub units: TemperatureUnit,
+    pub database: DatabaseConfig,
+    pub harvester: HarvesterConfig,
+    pub web: WebConfig,
+    pub alerts: AlertConfig
+}
+
+// The file format: every key is optional and falls back to the defaults
+#[derive(RustcDecodable, Debug)]
+struct ConfigFile {
+    units: Option<String>,
+    database: Option<DatabaseFile>,
+    harvester: Option<HarvesterFile>,
+    web: Option<WebFile>,
+    alerts: Option<AlertFile>
+}
+
+#[derive(RustcDecodable, Debug)]
+struct DatabaseFile {
+    path: Option<String>,
+    retention_days: Option<u32>
+}
+
+#[derive(RustcDecodable, Debug)]
+struct HarvesterFile {
+    devices: Option<Vec<String>>,
+    query_interval: Option<u64>,
+    timeout_interval: Option<u64>,
+    heartbeat_interval: Option<u64>,
+    maintenance_interval: Option<u64>,
+    device_units: Option<String>,
+    reconnect: Option<ReconnectFile>
+}
+
+#[derive(RustcDecodable, Debug)]
+struct ReconnectFile {
+    max_errors: Option<u32>,
+    initial_backoff: Option<u64>,
+    max_backoff: Option<u64>,
+    jitter: Option<f64>,
+    give_up_after: Option<u32>
+}
+
+#[derive(RustcDecodable, Debug)]
+struct WebFile {
+    bind: Option<String>,
+    port: Option<u16>,
+    webroot: Option<String>,
+    pool_size: Option<u32>
+}
+
+#[derive(RustcDecodable, Debug)]
+struct AlertFile {
+    smtp_server: Option<String>,
+    smtp_from: Option<String>,
+    min_interval: Option<u64>,
+    commands: Option<Vec<String>>
+}
+
+impl Config {
+    pub fn default() -> Config {
+        Config {
+            units: TemperatureUnit::Fahrenheit,
+            database: DatabaseConfig {
+                path: "pibq.sqlite".to_string(),
+                retention_days: None
+            },
+            harvester: HarvesterConfig {
+                devices: vec![],
+                query_interval: 5000,
+                timeout_interval: 7500,
+                heartbeat_interval: 1000,
+                maintenance_interval: 60,
+                device_units: TemperatureUnit::Celsius,
+                reconnect: ReconnectPolicy::default()
+            },
+            web: WebConfig {
+                bind: "0.0.0.0".to_string(),
+                port: 3000,
+                webroot: "web".to_string(),
+                pool_size: 5
+            },
+            alerts: AlertConfig {
+                smtp_server: None,
+                smtp_from: "pibq@localhost".to_string(),
+                min_interval: 300,
+                commands: vec![]
+            }
+        }
+    }
+
+    // Loads the given file, or the default path if it exists, or falls back to the defaults
+    pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+        match path {
+            Some(p) => Config::from_file(p),
+            None if Path::new(DEFAULT_PATH).exists() => Config::from_file(DEFAULT_PATH),
+            None => Ok(Config::default())
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let mut f = try!(File::open(path));
+        let mut contents = String::new();
+        try!(f.read_to_string(&mut contents));
+        Config::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+        let mut parser = toml::Parser::new(contents);
+        let table = match parser.parse() {
+            Some(t) => t,
+            None => {
+                let msgs: Vec<String> = parser.errors.iter().map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    format!("line {}, column {}: {}", line + 1, col + 1, e.desc)
+                }).collect();
+                return Err(ConfigError::Parse(msgs.join("; ")));
+            }
+        };
+
+        let mut decoder = toml::Decoder::new(toml::Value::Table(table));
+        let file = match ConfigFile::decode(&mut decoder) {
+            Ok(f) => f,
+            Err(e) => return Err(ConfigError::Parse(e.to_string()))
+        };
+
+        let mut config = Config::default();
+        try!(config.merge(file));
+        Ok(config)
+    }
+
+    fn merge(&mut self, file: ConfigFile) -> Result<(), ConfigError> {
+        if let Some(units) = file.units {
+            self.units = try!(parse_units("units", &units));
+        }
+
+        if let Some(db) = file.database {
+            if let Some(v) = db.path { self.database.path = v; }
+            if let Some(v) = db.retention_days { self.database.retention_days = if v > 0 { Some(v) } else { None }; }
+        }
+
+        if let Some(h) = file.harvester {
+            if let Some(v) = h.devices { self.harvester.devices = v; }
+            if let Some(v) = h.query_interval { self.harvester.query_interval = v; }
+            if let Some(v) = h.timeout_interval { self.harvester.timeout_interval = v; }
+            if let Some(v) = h.heartbeat_interval { self.harvester.heartbeat_interval = v; }
+            if let Some(v) = h.maintenance_interval { self.harvester.maintenance_interval = v; }
+            if let Some(v) = h.device_units { self.harvester.device_units = try!(parse_units("harvester.device_units", &v)); }
+
+            if let Some(r) = h.reconnect {
+                let policy = &mut self.harvester.reconnect;
+                if let Some(v) = r.max_errors { policy.max_errors = v; }
+                if let Some(v) = r.initial_backoff { policy.initial_backoff = v; }
+                if let Some(v) = r.max_backoff { policy.max_backoff = v; }
+                if let Some(v) = r.jitter {
+                    if v < 0.0 || v > 1.0 {
+                        return Err(ConfigError::Parse(format!("harvester.reconnect.jitter must be between 0 and 1, not {}", v)));
+                    }
+                    policy.jitter = v;
+                }
+                if let Some(v) = r.give_up_after { policy.give_up_after = if v > 0 { Some(v) } else { None }; }
+            }
+        }
+
+        if let Some(w) = file.web {
+            if let Some(v) = w.bind { self.web.bind = v; }
+            if let Some(v) = w.port { self.web.port = v; }
+            if let Some(v) = w.webroot { self.web.webroot = v; }
+            if let Some(v) = w.pool_size {
+                if v == 0 {
+                    return Err(ConfigError::Parse("web.pool_size must be at least 1".to_string()));
+                }
+                self.web.pool_size = v;
+            }
+        }
+
+        if let Some(a) = file.alerts {
+            if a.smtp_server.is_some() { self.alerts.smtp_server = a.smtp_server; }
+            if let Some(v) = a.smtp_from { self.alerts.smtp_from = v; }
+            if let Some(v) = a.min_interval { self.alerts.min_interval = v; }
+            if let Some(v) = a.commands { self.alerts.commands = v; }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_units(key: &str, value: &str) -> Result<TemperatureUnit, ConfigError> {
+    match TemperatureUnit::from_str(value) {
+        Some(u) => Ok(u),
+        None => Err(ConfigError::Parse(format!("{} must be \"F\" or \"C\", not \"{}\"", key, value)))
+    }
+}
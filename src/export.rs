@@ -0,0 +1,219 @@
+use chrono::datetime::DateTime;
+use chrono::offset::utc::UTC;
+use rusqlite::{self, Connection};
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use super::models::{Annotation, ConnectionStatus, ProbeSeries, Project, ProjectEvent, TemperatureUnit};
+use super::sql;
+
+// Statuses are read in pages of this many
+const STATUS_PAGE: u32 = 1000;
+
+// A stretch of a project when a thermometer wasn't connected; ended_at is None if it never came back
+#[derive(Clone, Debug)]
+pub struct Gap {
+    pub device_id: Option<i64>,
+    pub started_at: DateTime<UTC>,
+    pub ended_at: Option<DateTime<UTC>>
+}
+
+impl ToJson for Gap {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("device_id".to_string(), self.device_id.to_json());
+        m.insert("started_at".to_string(), self.started_at.to_rfc3339().to_json());
+        m.insert("ended_at".to_string(), self.ended_at.map(|dt| dt.to_rfc3339()).to_json());
+        m.to_json()
+    }
+}
+
+// Everything recorded for a project, for copying out of the database
+pub struct ProjectExport {
+    pub project: Project,
+    // degrees C, in the project's probe order
+    pub series: Vec<ProbeSeries>,
+    pub annotations: Vec<Annotation>,
+    pub events: Vec<ProjectEvent>,
+    pub gaps: Vec<Gap>
+}
+
+impl ProjectExport {
+    pub fn load(conn: &Connection, project: Project) -> rusqlite::Result<ProjectExport> {
+        let series = try!(sql::get_project_readings(conn, &project, None));
+        let annotations = try!(sql::get_project_annotations(conn, project.id));
+        let events = try!(sql::get_project_events(conn, project.id));
+
+        let mut statuses = vec![];
+        let mut after_id = 0;
+
+        loop {
+            let page = try!(sql::get_connection_statuses(conn, Some(project.start), Some(project.end), after_id, STATUS_PAGE));
+            let full = page.len() as u32 == STATUS_PAGE;
+            after_id = page.last().map(|s| s.id).unwrap_or(after_id);
+            statuses.extend(page);
+
+            if !full {
+                break;
+            }
+        }
+
+        let devices: Vec<Option<i64>> = try!(sql::get_probes(conn)).into_iter()
+            .filter(|probe| project.probes.iter().any(|p| p.probe_id == probe.id))
+            .map(|probe| probe.device_id)
+            .collect();
+        let gaps = find_gaps(&project, &devices, &statuses);
+
+        Ok(ProjectExport {
+            project: project,
+            series: series,
+            annotations: annotations,
+            events: events,
+            gaps: gaps
+        })
+    }
+
+    // One row per reading time, with a column per probe; None where the probe had no reading
+    pub fn rows(&self, units: TemperatureUnit) -> Vec<(DateTime<UTC>, Vec<Option<f64>>)> {
+        let mut rows: BTreeMap<DateTime<UTC>, Vec<Option<f64>>> = BTreeMap::new();
+
+        for (col, series) in self.series.iter().enumerate() {
+            for point in series.readings.iter() {
+                let row = rows.entry(point.timestamp).or_insert_with(|| vec![None; self.series.len()]);
+                row[col] = point.value.map(|c| units.from_celsius(c));
+            }
+        }
+
+        rows.into_iter().collect()
+    }
+
+    // timestamp, a column per probe, and a note column for annotations and connection gaps.
+    // Times are RFC 3339 in UTC; temperatures are in the given units, named in each probe's header.
+    pub fn write_csv<W: Write>(&self, w: &mut W, units: TemperatureUnit) -> io::Result<()> {
+        let mut header = vec!["timestamp".to_string()];
+        for series in self.series.iter() {
+            header.push(format!("{} ({})", series.name, units.as_str()));
+        }
+        header.push("note".to_string());
+        try!(write_csv_row(w, &header));
+
+        // notes go in among the readings at their own times
+        let mut notes: Vec<(DateTime<UTC>, String)> = vec![];
+        for a in self.annotations.iter() {
+            notes.push((a.timestamp, a.text.clone()));
+        }
+        for gap in self.gaps.iter() {
+            notes.push((gap.started_at, "disconnected".to_string()));
+            if let Some(ended) = gap.ended_at {
+                notes.push((ended, "reconnected".to_string()));
+            }
+        }
+        notes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let blanks = vec!["".to_string(); self.series.len()];
+        let mut notes = notes.into_iter().peekable();
+
+        for (timestamp, values) in self.rows(units) {
+            while notes.peek().map(|n| n.0 <= timestamp).unwrap_or(false) {
+                let (at, text) = notes.next().unwrap();
+                try!(write_note(w, at, &blanks, &text));
+            }
+
+            let mut row = vec![timestamp.to_rfc3339()];
+            for v in values.iter() {
+                row.push(v.map(|v| format!("{:.2}", v)).unwrap_or("".to_string()));
+            }
+            row.push("".to_string());
+            try!(write_csv_row(w, &row));
+        }
+
+        for (at, text) in notes {
+            try!(write_note(w, at, &blanks, &text));
+        }
+
+        w.flush()
+    }
+
+    pub fn as_json(&self, units: TemperatureUnit) -> Json {
+        let probes: Vec<Json> = self.series.iter().map(|series| {
+            let role = self.project.probes.iter().find(|p| p.probe_id == series.probe_id).map(|p| p.role.as_str());
+            let readings: Vec<Json> = series.readings.iter().map(|p| {
+                vec![p.timestamp.to_rfc3339().to_json(), p.value.map(|c| units.from_celsius(c)).to_json()].to_json()
+            }).collect();
+
+            let mut m: BTreeMap<String, Json> = BTreeMap::new();
+            m.insert("probe_id".to_string(), series.probe_id.to_json());
+            m.insert("name".to_string(), series.name.to_json());
+            m.insert("role".to_string(), role.to_json());
+            m.insert("readings".to_string(), readings.to_json());
+            m.to_json()
+        }).collect();
+
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("project".to_string(), self.project.to_json());
+        m.insert("units".to_string(), units.as_str().to_json());
+        m.insert("probes".to_string(), probes.to_json());
+        m.insert("annotations".to_string(), self.annotations.to_json());
+        m.insert("events".to_string(), self.events.to_json());
+        m.insert("gaps".to_string(), self.gaps.to_json());
+        m.to_json()
+    }
+}
+
+// Works out when each of the given devices (those of the project's probes) was disconnected during the project.
+// A device whose first status in the project is a connect was down when the project started.
+pub fn find_gaps(project: &Project, devices: &[Option<i64>], statuses: &[ConnectionStatus]) -> Vec<Gap> {
+    let mut open: HashMap<Option<i64>, DateTime<UTC>> = HashMap::new();
+    let mut seen: Vec<Option<i64>> = vec![];
+    let mut gaps = vec![];
+
+    for status in statuses.iter().filter(|s| devices.contains(&s.device_id)) {
+        let first = !seen.contains(&status.device_id);
+        if first {
+            seen.push(status.device_id);
+        }
+
+        if status.is_disconnect && !open.contains_key(&status.device_id) {
+            open.insert(status.device_id, status.created_at);
+        } else if status.is_connect {
+            let started = match open.remove(&status.device_id) {
+                Some(started) => Some(started),
+                None if first => Some(project.start),
+                None => None
+            };
+
+            if let Some(started) = started {
+                gaps.push(Gap { device_id: status.device_id, started_at: started, ended_at: Some(status.created_at) });
+            }
+        }
+    }
+
+    for (device_id, started) in open {
+        gaps.push(Gap { device_id: device_id, started_at: started, ended_at: None });
+    }
+
+    gaps.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+    gaps
+}
+
+fn write_note<W: Write>(w: &mut W, at: DateTime<UTC>, blanks: &[String], text: &str) -> io::Result<()> {
+    let mut row = vec![at.to_rfc3339()];
+    row.extend(blanks.iter().cloned());
+    row.push(text.to_string());
+    write_csv_row(w, &row)
+}
+
+fn write_csv_row<W: Write>(w: &mut W, fields: &[String]) -> io::Result<()> {
+    let escaped: Vec<String> = fields.iter().map(|f| csv_escape(f)).collect();
+    write!(w, "{}\r\n", escaped.join(","))
+}
+
+// Quotes a field if it holds a comma, quote or line break
+pub fn csv_escape(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
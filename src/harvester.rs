@@ -1,59 +1,134 @@
+extern crate chrono;
 extern crate getopts;
 extern crate rusqlite;
 extern crate pibq;
 
 use std::env;
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::thread;
 use std::time::{Duration, Instant};
-use getopts::Options;
+use chrono::offset::utc::UTC;
+use getopts::{Matches, Options};
 
+use pibq::alarms;
+use pibq::analytics::{self, Detection};
 use pibq::bluetherm;
+use pibq::config::{Config, HarvesterConfig};
+use pibq::notify;
+use pibq::reconnect::{Backoff, ReconnectPolicy};
 use pibq::sql;
-use pibq::models::{ConnectionStatus, Reading};
-
-// interval between sending query packtets, in ms
-const QUERY_INTERVAL: u64 = 5000;
-
-// if no packets are returned, amount of time to wait before creating a timeout error, in ms
-const TIMEOUT_INTERVAL: u64 = 7500;
-
-// Heartbeat interval, in ms
-const HEARTBEAT_INTERVAL: u64 = 1000;
+use pibq::models::{AlarmEvent, ConnectionStatus, Device, Probe, ProbeValue, Reading, TemperatureUnit};
 
 struct Harvester {
     sql_conn: rusqlite::Connection,
     bt_conn: Option<bluetherm::Connection>,
     serial: String,
+    device: Option<Device>,
+    probes: Vec<Probe>,
+    // what the device reports in when its packets don't say, and what it last said
+    default_units: TemperatureUnit,
+    units: Option<TemperatureUnit>,
+    alarms: alarms::Evaluator,
+    detector: analytics::Detector,
+    notifier: Sender<AlarmEvent>,
     disconnected: bool,
     disconnect_reason: Option<bluetherm::ConnectionEvent>,
-    error_count: i64,
+    error_count: u32,
+    policy: ReconnectPolicy,
+    backoff: Backoff,
+    reconnecting: bool,
     send_interval: Duration,
     timeout_interval: Duration,
+    heartbeat_interval: u64,
     last_send: Option<Instant>,
     last_receive: Option<Instant>
 }
 
 impl Harvester {
-    fn new(sql_conn: rusqlite::Connection, serial: &str) -> Harvester {
+    fn new(sql_conn: rusqlite::Connection, serial: &str, config: &HarvesterConfig, notifier: Sender<AlarmEvent>) -> Harvester {
+        // until the device reports its serial number, assume it's whatever was last seen on this path
+        let device = sql::get_device_by_path(&sql_conn, serial).unwrap();
+
         Harvester {
             sql_conn: sql_conn,
-            bt_conn: Some(Harvester::connect_bluetherm(serial)),
+            bt_conn: None,
             serial: serial.to_string(),
+            device: device,
+            probes: vec![],
+            default_units: config.device_units,
+            units: None,
+            alarms: alarms::Evaluator::new(),
+            detector: analytics::Detector::new(),
+            notifier: notifier,
             disconnected: true,
             disconnect_reason: None,
             error_count: 0,
-            send_interval: Duration::from_millis(QUERY_INTERVAL),
-            timeout_interval: Duration::from_millis(TIMEOUT_INTERVAL),
+            backoff: config.reconnect.backoff(),
+            policy: config.reconnect.clone(),
+            reconnecting: false,
+            send_interval: Duration::from_millis(config.query_interval),
+            timeout_interval: Duration::from_millis(config.timeout_interval),
+            heartbeat_interval: config.heartbeat_interval,
             last_send: None,
             last_receive: None
         }
     }
 
-    fn connect_bluetherm(serial: &str) -> bluetherm::Connection {
-        bluetherm::Connection::new(serial, Some(HEARTBEAT_INTERVAL)).unwrap()
+    // Opens the connection, backing off between attempts that don't lead to a reading.
+    // Returns false once the reconnect policy gives up.
+    fn connect(&mut self) -> bool {
+        loop {
+            if self.reconnecting {
+                match self.backoff.next_delay() {
+                    None => return false,
+                    Some(delay) => {
+                        println!("[{}] reconnecting in {}ms", self.serial, delay.as_secs() * 1000 + (delay.subsec_nanos() / 1_000_000) as u64);
+                        thread::sleep(delay);
+                    }
+                }
+            }
+            self.reconnecting = true;
+
+            match bluetherm::Connection::open(&self.serial, Some(self.heartbeat_interval)) {
+                Ok(c) => {
+                    println!("[{}] connected", self.serial);
+                    self.bt_conn = Some(c);
+                    return true;
+                },
+                Err(e) => {
+                    self.bt_error(bluetherm::ConnectionEvent::Fatal(bluetherm::ConnectionError::Transport(e)));
+                }
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.error_count = 0;
+        self.last_send = None;
+        self.last_receive = None;
+
+        println!("[{}] killing old connection.", self.serial);
+        drop(self.bt_conn.take());
+        println!("[{}] old conneciton dropped", self.serial);
     }
 
+    // Runs until the reconnect policy gives up
     fn start(&mut self) {
         loop {
+            if self.bt_conn.is_none() && !self.connect() {
+                let msg = format!("Giving up after {} reconnect attempts", self.backoff.attempts());
+                println!("[{}] {}", self.serial, msg);
+
+                let mut s = ConnectionStatus::new();
+                s.device_id = self.device_id();
+                s.is_disconnect = true;
+                s.info = Some(msg);
+                self.record_status(s);
+                return;
+            }
+
             match (self.last_send, self.last_receive) {
                 (Some(sent), _) if sent.elapsed() < self.send_interval => {},
                 //(Some(sent), Some(received)) if sent > received => {},
@@ -63,7 +138,13 @@ impl Harvester {
                 }
             }
 
-            let event = self.get_bt_conn().wait().unwrap();
+            let event = match self.bt_conn.as_mut() {
+                None => continue,
+                Some(c) => match c.wait() {
+                    Ok(evt) => evt,
+                    Err(err) => bluetherm::ConnectionEvent::Fatal(err)
+                }
+            };
 
             match event {
                 bluetherm::ConnectionEvent::Packet(p) => {
@@ -72,9 +153,15 @@ impl Harvester {
                     self.last_receive = Some(Instant::now());
                     self.bt_success();
                 },
-                e @ bluetherm::ConnectionEvent::InvalidPacket(_) => { self.bt_error(e); },
+                bluetherm::ConnectionEvent::Discarded(bytes) => {
+                    println!("[{}] discarded {} bytes while resynchronizing", self.serial, bytes.len());
+                },
                 e @ bluetherm::ConnectionEvent::ReadError(_) => { self.bt_error(e); },
                 e @ bluetherm::ConnectionEvent::WriteError(_) => { self.bt_error(e); },
+                e @ bluetherm::ConnectionEvent::Fatal(_) => {
+                    self.bt_error(e);
+                    self.disconnect();
+                },
                 e @ bluetherm::ConnectionEvent::Heartbeat => {
                     match self.last_receive {
                         None => {
@@ -102,18 +189,157 @@ impl Harvester {
         self.bt_conn.as_mut().unwrap()
     }
 
+    fn device_id(&self) -> Option<i64> {
+        self.device.as_ref().map(|d| d.id)
+    }
+
     fn record_packet(&mut self, packet: bluetherm::Packet) {
+        let serial_number = packet.get_serial_number();
+
+        let known = match self.device {
+            Some(ref d) => serial_number.len() == 0 || d.serial_number == serial_number,
+            None => serial_number.len() == 0
+        };
+
+        if !known {
+            let device = match sql::find_or_create_device(&self.sql_conn, &serial_number, &self.serial) {
+                Err(e) => {
+                    println!("[{}] unable to record device {}: {}", self.serial, serial_number, e);
+                    return;
+                },
+                Ok(d) => d
+            };
+            println!("[{}] device {} (id {})", self.serial, device.serial_number, device.id);
+            self.device = Some(device);
+            self.probes.clear();
+        }
+
+        if self.probes.is_empty() && !self.load_probes() {
+            return;
+        }
+
+        let units = match packet.decode().is_fahrenheit() {
+            Some(true) => TemperatureUnit::Fahrenheit,
+            Some(false) => TemperatureUnit::Celsius,
+            None => self.default_units
+        };
+
+        if self.units != Some(units) {
+            println!("[{}] reporting in {}", self.serial, units.symbol());
+            self.units = Some(units);
+        }
+
+        // stored in C whatever the device reports in
         let mut reading = Reading::new();
-        reading.value1 = packet.get_sensor1_reading();
-        reading.value2 = packet.get_sensor2_reading();
-        sql::insert_reading(&self.sql_conn, &mut reading).unwrap();
+        reading.device_id = self.device_id();
+        reading.values.push(ProbeValue { probe_id: self.probes[0].id, value: packet.get_sensor1_reading().map(|t| units.to_celsius(t)) });
+        reading.values.push(ProbeValue { probe_id: self.probes[1].id, value: packet.get_sensor2_reading().map(|t| units.to_celsius(t)) });
+        // the database can be busy for longer than the timeout, as during a backup; the reading is lost but not the harvester
+        if let Err(e) = sql::insert_reading(&self.sql_conn, &mut reading) {
+            println!("[{}] unable to record reading: {}", self.serial, e);
+            return;
+        }
+
+        self.check_alarms(&reading);
+        self.detect_events(&reading);
+    }
+
+    fn check_alarms(&mut self, reading: &Reading) {
+        let rules = match sql::get_active_alarm_rules(&self.sql_conn, reading.timestamp) {
+            Err(e) => {
+                println!("[{}] unable to load alarm rules: {}", self.serial, e);
+                return;
+            },
+            Ok(r) => r
+        };
+
+        for mut event in self.alarms.evaluate(&rules, reading) {
+            if let Err(e) = sql::insert_alarm_event(&self.sql_conn, &mut event) {
+                println!("[{}] unable to record alarm {}: {}", self.serial, event.rule_id, e);
+            }
+            println!("[{}] alarm {} {} at {:.1}", self.serial, event.rule_id, event.state.as_str(), event.value.unwrap_or(0.0));
+
+            // the dispatcher only goes away if its thread has panicked
+            if self.notifier.send(event).is_err() {
+                println!("[{}] alarm notifications are down", self.serial);
+            }
+        }
+    }
+
+    // Records stalls and lid openings on the timelines of the projects the reading falls in
+    fn detect_events(&mut self, reading: &Reading) {
+        let projects = match sql::get_active_projects(&self.sql_conn, reading.timestamp) {
+            Err(e) => {
+                println!("[{}] unable to load projects: {}", self.serial, e);
+                return;
+            },
+            Ok(p) => p
+        };
+
+        for detection in self.detector.observe(&projects, reading) {
+            match detection {
+                Detection::Started(mut event) => {
+                    match sql::start_project_event(&self.sql_conn, &mut event) {
+                        Ok(true) => println!("[{}] project {} probe {} {} started", self.serial, event.project_id, event.probe_id, event.kind.as_str()),
+                        Ok(false) => {},
+                        Err(e) => println!("[{}] unable to record project {} event: {}", self.serial, event.project_id, e)
+                    }
+                },
+                Detection::Occurred(mut event) => {
+                    match sql::start_project_event(&self.sql_conn, &mut event) {
+                        Ok(true) => println!("[{}] project {} probe {} {} recorded", self.serial, event.project_id, event.probe_id, event.kind.as_str()),
+                        Ok(false) => {},
+                        Err(e) => println!("[{}] unable to record project {} event: {}", self.serial, event.project_id, e)
+                    }
+                },
+                Detection::Ended(project_id, probe_id, kind, at) => {
+                    match sql::end_project_event(&self.sql_conn, project_id, probe_id, kind, at) {
+                        Ok(true) => println!("[{}] project {} probe {} {} ended", self.serial, project_id, probe_id, kind.as_str()),
+                        Ok(false) => {},
+                        Err(e) => println!("[{}] unable to record project {} event: {}", self.serial, project_id, e)
+                    }
+                }
+            }
+        }
+    }
+
+    // The BlueTherm has two probes; these are named after the device until a project names them.
+    // False if they couldn't be loaded, to be tried again with the next packet.
+    fn load_probes(&mut self) -> bool {
+        let device_id = self.device_id();
+
+        for channel in 1..3 {
+            let name = match self.device {
+                Some(ref d) => format!("{} #{}", d.serial_number, channel),
+                None => format!("Probe {}", channel)
+            };
+
+            match sql::find_or_create_probe(&self.sql_conn, device_id, channel, &name) {
+                Ok(probe) => self.probes.push(probe),
+                Err(e) => {
+                    println!("[{}] unable to load probes: {}", self.serial, e);
+                    self.probes.clear();
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    // A database too busy to take the status costs the status, not the harvester
+    fn record_status(&self, mut s: ConnectionStatus) {
+        if let Err(e) = sql::insert_connection_status(&self.sql_conn, &mut s) {
+            println!("[{}] unable to record connection status: {}", self.serial, e);
+        }
     }
 
     fn send_packet(&mut self) {
-        let p = bluetherm::Packet::temp_packet();
+        let p = bluetherm::Packet::readings_packet();
         match self.get_bt_conn().send(p) {
             Err(e) => {
-                self.bt_error(bluetherm::ConnectionEvent::ReadError(e));
+                self.bt_error(bluetherm::ConnectionEvent::Fatal(e));
+                self.disconnect();
             },
             Ok(_) => {}
         }
@@ -122,12 +348,14 @@ impl Harvester {
     fn bt_success(&mut self) {
         if self.disconnected {
             let mut s = ConnectionStatus::new();
+            s.device_id = self.device_id();
             s.is_connect = true;
-            sql::insert_connection_status(&self.sql_conn, &mut s).unwrap();
+            self.record_status(s);
 
             self.disconnected = false;
             self.disconnect_reason = None;
             self.error_count = 0;
+            self.backoff.reset();
         }
     }
 
@@ -139,10 +367,10 @@ impl Harvester {
                 &None => {},
                 &Some(ref reason) => {
                     match (reason, &evt) {
-                        (&bluetherm::ConnectionEvent::InvalidPacket(_), &bluetherm::ConnectionEvent::InvalidPacket(_)) |
                         (&bluetherm::ConnectionEvent::ReadError(_), &bluetherm::ConnectionEvent::ReadError(_)) |
                         (&bluetherm::ConnectionEvent::WriteError(_), &bluetherm::ConnectionEvent::WriteError(_)) |
-                        (&bluetherm::ConnectionEvent::Heartbeat, &bluetherm::ConnectionEvent::Heartbeat) => {
+                        (&bluetherm::ConnectionEvent::Heartbeat, &bluetherm::ConnectionEvent::Heartbeat) |
+                        (&bluetherm::ConnectionEvent::Fatal(_), &bluetherm::ConnectionEvent::Fatal(_)) => {
                             report_error = false;
                         },
                         _ => {},
@@ -153,48 +381,92 @@ impl Harvester {
 
         if report_error {
             let msg = match evt {
-                bluetherm::ConnectionEvent::InvalidPacket(ref p) => { format!("Invalid Packet: [{}]", p) },
                 bluetherm::ConnectionEvent::ReadError(ref err) => { format!("Read Error: {}", err) },
                 bluetherm::ConnectionEvent::WriteError(ref err) => { format!("Write Error: {}", err) },
                 bluetherm::ConnectionEvent::Heartbeat => { "Timeout".to_string() },
+                bluetherm::ConnectionEvent::Fatal(ref err) => { format!("Connection Failed: {}", err) },
                 _ => "Unknown Error".to_string()
             };
 
             let mut s = ConnectionStatus::new();
+            s.device_id = self.device_id();
             s.is_disconnect = true;
             s.info = Some(msg);
-            sql::insert_connection_status(&self.sql_conn, &mut s).unwrap();
+            self.record_status(s);
         }
 
-        println!("error: {}", evt);
+        println!("[{}] error: {}", self.serial, evt);
 
         self.disconnected = true;
         self.disconnect_reason = Some(evt);
 
         self.error_count = self.error_count + 1;
 
-        if self.error_count > 3 {
-            self.error_count = 0;
-            println!("killing old connection.");
-
-            self.last_send = None;
-            self.last_receive = None;
+        if self.error_count > self.policy.max_errors && self.bt_conn.is_some() {
+            self.disconnect();
+        }
+    }
+}
 
-            let old = self.bt_conn.take();
-            drop(old.unwrap());
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options]", program);
+    print!("{}", opts.usage(&brief));
+}
 
-            println!("old conneciton dropped; making new");
+fn parse_opt<T>(matches: &Matches, name: &str) -> Result<Option<T>, String> where T: FromStr, T::Err: ToString {
+    match matches.opt_str(name) {
+        None => Ok(None),
+        Some(s) => s.parse::<T>().map(Some).map_err(|e| format!("invalid value for --{}: {}", name, e.to_string()))
+    }
+}
 
-            self.bt_conn = Some(Harvester::connect_bluetherm(&self.serial));
+// Keeps the rollup tables current and prunes raw readings past the retention period
+fn maintain(sql_conn: rusqlite::Connection, interval: u64, retention_days: Option<u32>) {
+    loop {
+        match sql::update_rollups(&sql_conn) {
+            Err(e) => println!("unable to update rollups: {}", e),
+            Ok(_) => {}
+        }
 
-            println!("new connection made");
+        if let Some(days) = retention_days {
+            let cutoff = UTC::now() - chrono::duration::Duration::days(days as i64);
+            match sql::prune_readings(&sql_conn, cutoff) {
+                Err(e) => println!("unable to prune readings: {}", e),
+                Ok(0) => {},
+                Ok(n) => println!("pruned {} readings older than {} days", n, days)
+            }
         }
+
+        thread::sleep(Duration::from_secs(interval));
     }
 }
 
-fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
-    print!("{}", opts.usage(&brief));
+// Loads the config file, then applies any command line overrides
+fn load_config(matches: &Matches) -> Result<Config, String> {
+    let mut config = match Config::load(matches.opt_str("c").as_ref().map(|s| s.as_str())) {
+        Ok(c) => c,
+        Err(e) => return Err(e.to_string())
+    };
+
+    let devices = matches.opt_strs("s");
+    if devices.len() > 0 { config.harvester.devices = devices; }
+    if let Some(v) = matches.opt_str("d") { config.database.path = v; }
+
+    {
+        let h = &mut config.harvester;
+        if let Some(v) = try!(parse_opt(matches, "query-interval")) { h.query_interval = v; }
+        if let Some(v) = try!(parse_opt(matches, "timeout-interval")) { h.timeout_interval = v; }
+        if let Some(v) = try!(parse_opt(matches, "heartbeat-interval")) { h.heartbeat_interval = v; }
+
+        let policy: &mut ReconnectPolicy = &mut h.reconnect;
+        if let Some(v) = try!(parse_opt(matches, "max-errors")) { policy.max_errors = v; }
+        if let Some(v) = try!(parse_opt(matches, "initial-backoff")) { policy.initial_backoff = v; }
+        if let Some(v) = try!(parse_opt(matches, "max-backoff")) { policy.max_backoff = v; }
+        if let Some(v) = try!(parse_opt(matches, "jitter")) { policy.jitter = v; }
+        if let Some(v) = try!(parse_opt::<u32>(matches, "give-up-after")) { policy.give_up_after = if v > 0 { Some(v) } else { None }; }
+    }
+
+    Ok(config)
 }
 
 fn main() {
@@ -202,9 +474,17 @@ fn main() {
     let program = args[0].clone();
 
     let mut opts = Options::new();
-    opts.reqopt("s", "serial", "tty serial device", "DEV");
+    opts.optopt("c", "config", &format!("config file (default {})", pibq::config::DEFAULT_PATH), "FILE");
+    opts.optmulti("s", "serial", "tty serial device, or tcp://host:port; repeat to poll several devices", "DEV");
     opts.optopt("d", "dbfile", "sqlite DB file", "FILE");
-    opts.optopt("m", "migrations", "migration folder", "DIR");
+    opts.optopt("", "query-interval", "interval between query packets, in ms", "MS");
+    opts.optopt("", "timeout-interval", "time without a reply before the connection is considered lost, in ms", "MS");
+    opts.optopt("", "heartbeat-interval", "connection heartbeat interval, in ms", "MS");
+    opts.optopt("", "max-errors", "consecutive errors before the connection is rebuilt", "N");
+    opts.optopt("", "initial-backoff", "delay before the first reconnect attempt, in ms", "MS");
+    opts.optopt("", "max-backoff", "maximum delay between reconnect attempts, in ms", "MS");
+    opts.optopt("", "jitter", "random fraction (0 - 1) applied to reconnect delays", "FRACTION");
+    opts.optopt("", "give-up-after", "failed reconnect attempts before exiting (0 retries forever)", "N");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
@@ -218,12 +498,56 @@ fn main() {
         print_usage(&program, opts);
         return;
     }
-    let serial = matches.opt_str("s").unwrap();
-    let dbfile = matches.opt_str("d").unwrap_or("pibq.sqlite".to_string());
-    let migrations = matches.opt_str("m").unwrap_or("migrations".to_string());
 
-    let db = sql::get_connection(&dbfile, Some(migrations)).unwrap();
+    let config = match load_config(&matches) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(&program, opts);
+            return;
+        }
+    };
+
+    if config.harvester.devices.is_empty() {
+        println!("no device given; use -s or set harvester.devices in the config file");
+        print_usage(&program, opts);
+        return;
+    }
+
+    // migrate once up front; each device thread then opens its own connection
+    if let Err(e) = sql::get_connection(&config.database.path, true) {
+        println!("unable to migrate {}: {}", config.database.path, e);
+        return;
+    }
+
+    {
+        let db = sql::get_connection(&config.database.path, false).unwrap();
+        let interval = config.harvester.maintenance_interval;
+        let retention_days = config.database.retention_days;
+        thread::Builder::new().name("maintenance".to_string()).spawn(move || {
+            maintain(db, interval, retention_days);
+        }).unwrap();
+    }
+
+    let notifier = notify::start_dispatcher(&config.database.path, config.alerts.clone(), config.units);
+
+    let handles: Vec<thread::JoinHandle<()>> = config.harvester.devices.iter().map(|serial| {
+        let serial = serial.clone();
+        let dbfile = config.database.path.clone();
+        let harvester_config = config.harvester.clone();
+        let notifier = notifier.clone();
+
+        thread::Builder::new().name(format!("harvester {}", serial)).spawn(move || {
+            let db = sql::get_connection(&dbfile, false).unwrap();
+            let mut h = Harvester::new(db, &serial, &harvester_config, notifier);
+            h.start();
+        }).unwrap()
+    }).collect();
+
+    // harvesters only return once their reconnect policy gives up
+    for handle in handles {
+        let _ = handle.join();
+    }
 
-    let mut h = Harvester::new(db, &serial);
-    h.start();
+    process::exit(1);
 }
@@ -0,0 +1,320 @@
+use chrono::datetime::DateTime;
+use chrono::duration::Duration;
+use chrono::offset::local::Local;
+use chrono::offset::utc::UTC;
+use chrono::offset::TimeZone;
+use rusqlite::{self, Connection};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::mem;
+
+use super::models::{Annotation, ProbeValue, Project, ProjectProbe, Reading, TemperatureUnit};
+use super::sql;
+
+// Older loggers wrote local times without an offset
+const LOCAL_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    // the file isn't a CSV we can make sense of
+    Csv(String),
+    Db(rusqlite::Error)
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ImportError::Io(ref err) => write!(f, "unable to read: {}", err),
+            &ImportError::Csv(ref msg) => write!(f, "invalid CSV: {}", msg),
+            &ImportError::Db(ref err) => write!(f, "database error: {}", err)
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn description(&self) -> &str {
+        match self {
+            &ImportError::Io(ref err) => err.description(),
+            &ImportError::Csv(_) => "invalid CSV",
+            &ImportError::Db(ref err) => err.description()
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> ImportError {
+        ImportError::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(err: rusqlite::Error) -> ImportError {
+        ImportError::Db(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    // name of the project created for the readings
+    pub name: String,
+    // serial number of the device the readings are recorded against; created if it doesn't exist
+    pub device_serial: String,
+    // units of probe columns whose header doesn't say, like "Pit (F)" does
+    pub units: TemperatureUnit
+}
+
+impl ImportOptions {
+    pub fn new(name: &str) -> ImportOptions {
+        ImportOptions {
+            name: name.to_string(),
+            device_serial: "imported".to_string(),
+            units: TemperatureUnit::Celsius
+        }
+    }
+}
+
+// What an import did. Line numbers are 1-based lines of the file, counting the header.
+#[derive(Debug)]
+pub struct ImportReport {
+    // None if no readings were imported to make a project of
+    pub project: Option<Project>,
+    pub imported: usize,
+    // rows the device already had a reading for at that time, which are left as they were
+    pub conflicts: Vec<(usize, DateTime<UTC>)>,
+    // rows that couldn't be read, and why
+    pub errors: Vec<(usize, String)>,
+    pub annotations: usize
+}
+
+struct Column {
+    index: usize,
+    name: String,
+    units: TemperatureUnit
+}
+
+// Reads a CSV laid out like the export: a timestamp column, a column per probe and optionally a note
+// column, into a new project. The readings go in under one device, so rows it already has readings for
+// are caught by idx_readings_time and reported rather than failing the whole import. The readings, project
+// and notes go in together or not at all.
+pub fn import_csv<R: Read>(conn: &Connection, input: &mut R, options: &ImportOptions) -> Result<ImportReport, ImportError> {
+    let mut contents = String::new();
+    try!(input.read_to_string(&mut contents));
+
+    let records = try!(parse_csv(&contents));
+    let mut records = records.into_iter();
+
+    let header = match records.next() {
+        Some((_, fields)) => fields,
+        None => return Err(ImportError::Csv("the file is empty".to_string()))
+    };
+
+    let mut columns = vec![];
+    let mut note_index = None;
+
+    for (index, field) in header.iter().enumerate().skip(1) {
+        let field = field.trim();
+        if field.to_lowercase() == "note" {
+            note_index = Some(index);
+        } else {
+            let (name, units) = split_units(field, options.units);
+            let name = if name.is_empty() { format!("Probe {}", columns.len() + 1) } else { name };
+            columns.push(Column { index: index, name: name, units: units });
+        }
+    }
+
+    if columns.is_empty() {
+        return Err(ImportError::Csv("no probe columns after the timestamp".to_string()));
+    }
+
+    let mut report = ImportReport {
+        project: None,
+        imported: 0,
+        conflicts: vec![],
+        errors: vec![],
+        annotations: 0
+    };
+    let mut notes: Vec<(DateTime<UTC>, String)> = vec![];
+    let mut span: Option<(DateTime<UTC>, DateTime<UTC>)> = None;
+
+    // one transaction, so a failed import leaves nothing half-written
+    sql::in_transaction(conn, move |conn| -> Result<ImportReport, ImportError> {
+        // an existing device keeps its path, so importing under a live thermometer's serial doesn't upset the harvester
+        let devices = try!(sql::get_devices(conn));
+        let device = match devices.into_iter().find(|d| d.serial_number == options.device_serial) {
+            Some(d) => d,
+            None => try!(sql::find_or_create_device(conn, &options.device_serial, "import"))
+        };
+
+        let mut probes = vec![];
+        for (i, column) in columns.iter().enumerate() {
+            probes.push(try!(sql::find_or_create_probe(conn, Some(device.id), (i + 1) as i64, &column.name)));
+        }
+
+        for (line, fields) in records {
+            if fields.iter().all(|f| f.trim().is_empty()) {
+                continue;
+            }
+
+            let timestamp = match parse_timestamp(fields[0].trim()) {
+                Some(dt) => dt,
+                None => {
+                    report.errors.push((line, format!("invalid timestamp \"{}\"", fields[0].trim())));
+                    continue;
+                }
+            };
+
+            let mut values = vec![];
+            let mut invalid = None;
+
+            for (column, probe) in columns.iter().zip(probes.iter()) {
+                let field = fields.get(column.index).map(|f| f.trim()).unwrap_or("");
+                if field.is_empty() {
+                    continue;
+                }
+
+                match field.parse::<f64>() {
+                    Ok(v) => values.push(ProbeValue { probe_id: probe.id, value: Some(column.units.to_celsius(v)) }),
+                    Err(_) => {
+                        invalid = Some(format!("invalid temperature \"{}\" for {}", field, column.name));
+                        break;
+                    }
+                }
+            }
+
+            if let Some(msg) = invalid {
+                report.errors.push((line, msg));
+                continue;
+            }
+
+            if let Some(text) = note_index.and_then(|i| fields.get(i)).map(|f| f.trim()) {
+                // the export's connection gap markers aren't notes anyone wrote
+                if !text.is_empty() && text != "disconnected" && text != "reconnected" {
+                    notes.push((timestamp, text.to_string()));
+                }
+            }
+
+            if values.is_empty() {
+                continue;
+            }
+
+            span = match span {
+                None => Some((timestamp, timestamp)),
+                Some((first, last)) => Some((if timestamp < first { timestamp } else { first }, if timestamp > last { timestamp } else { last }))
+            };
+
+            let mut reading = Reading::new();
+            reading.device_id = Some(device.id);
+            reading.timestamp = timestamp;
+            reading.values = values;
+
+            if try!(sql::insert_reading_if_new(conn, &mut reading)) {
+                report.imported += 1;
+            } else {
+                report.conflicts.push((line, timestamp));
+            }
+        }
+
+        // nothing new, as when a file is imported twice, doesn't need another project
+        let (first, last) = match span {
+            Some(s) if report.imported > 0 => s,
+            _ => return Ok(report)
+        };
+
+        let project_probes = columns.iter().zip(probes.iter()).enumerate().map(|(i, (column, probe))| {
+            ProjectProbe::new(probe.id, &column.name, i as i64 + 1)
+        }).collect();
+
+        // project readings are strictly between start and end, so leave a second either side
+        let mut project = Project::new(options.name.clone(), first - Duration::seconds(1), last + Duration::seconds(1), project_probes);
+        if columns.iter().all(|c| c.units == columns[0].units) {
+            project.units = Some(columns[0].units);
+        }
+        try!(sql::insert_project_in_transaction(conn, &mut project));
+
+        for (at, text) in notes {
+            let mut annotation = Annotation::new(project.id, at, &text);
+            try!(sql::insert_annotation(conn, &mut annotation));
+            report.annotations += 1;
+        }
+
+        report.project = Some(project);
+        Ok(report)
+    })
+}
+
+// RFC 3339, or the server's local time as "2016-06-18 14:30:00"
+pub fn parse_timestamp(s: &str) -> Option<DateTime<UTC>> {
+    match DateTime::parse_from_rfc3339(s) {
+        Ok(dt) => Some(dt.with_timezone(&UTC)),
+        Err(_) => Local.datetime_from_str(s, LOCAL_FORMAT).ok().map(|dt| dt.with_timezone(&UTC))
+    }
+}
+
+// "Pit (F)" is named Pit and in Fahrenheit; a header without units gets the default
+fn split_units(header: &str, default: TemperatureUnit) -> (String, TemperatureUnit) {
+    if header.ends_with(")") {
+        if let Some(open) = header.rfind(" (") {
+            if let Some(units) = TemperatureUnit::from_str(&header[open + 2..header.len() - 1]) {
+                return (header[..open].trim().to_string(), units);
+            }
+        }
+    }
+
+    (header.to_string(), default)
+}
+
+// Splits CSV text into records, each with the line it starts on. Quoted fields may hold commas,
+// doubled quotes and line breaks; lines may end in CRLF or LF.
+pub fn parse_csv(contents: &str) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut records = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                },
+                _ => field.push(c)
+            }
+            continue;
+        }
+
+        match c {
+            '"' => quoted = true,
+            ',' => fields.push(mem::replace(&mut field, String::new())),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                fields.push(mem::replace(&mut field, String::new()));
+                records.push((start, mem::replace(&mut fields, vec![])));
+                line += 1;
+                start = line;
+            },
+            _ => field.push(c)
+        }
+    }
+
+    if quoted {
+        return Err(ImportError::Csv(format!("unterminated quote in the record starting on line {}", start)));
+    }
+
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((start, fields));
+    }
+
+    Ok(records)
+}
@@ -7,7 +7,17 @@ extern crate rusqlite;
 extern crate chrono;
 extern crate rustc_serialize;
 extern crate r2d2;
+extern crate toml;
+extern crate url;
 
+pub mod alarms;
+pub mod analytics;
 pub mod bluetherm;
+pub mod config;
+pub mod export;
+pub mod import;
 pub mod sql;
 pub mod models;
+pub mod notify;
+pub mod reconnect;
+pub mod sim;
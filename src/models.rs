@@ -1,13 +1,14 @@
 use chrono::datetime::DateTime;
 use chrono::duration::Duration;
-use chrono::offset::local::Local;
+use chrono::offset::utc::UTC;
 use chrono::offset::TimeZone;
 use rustc_serialize::Encodable;
 use rustc_serialize::json::{self, Json, ToJson};
 use std::collections::BTreeMap;
 
-fn date_to_json(dt: &DateTime<Local>) -> Json {
-    dt.format("%Y-%m-%d %H:%M:%S").to_string().to_json()
+// RFC 3339 in UTC; clients convert to their own timezone for display
+fn date_to_json(dt: &DateTime<UTC>) -> Json {
+    dt.to_rfc3339().to_json()
 }
 
 pub trait DbObject {
@@ -33,23 +34,98 @@ impl<Tz> CustomToJson for DateTime<Tz>
     }
 }
 
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct Device {
+    pub id: i64,
+    pub serial_number: String,
+    pub path: Option<String>,
+    pub created_at: DateTime<UTC>
+}
+
+impl Device {
+    pub fn new(serial_number: &str, path: Option<String>) -> Device {
+        Device {
+            id: 0,
+            serial_number: serial_number.to_string(),
+            path: path,
+            created_at: UTC::now()
+        }
+    }
+}
+
+impl DbObject for Device {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for Device {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("serial_number".to_string(), self.serial_number.to_json());
+        m.insert("path".to_string(), self.path.to_json());
+        m.insert("created_at".to_string(), date_to_json(&self.created_at));
+        m.to_json()
+    }
+}
+
+// A single temperature channel; device_id is None for readings recorded before devices were tracked
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Probe {
+    pub id: i64,
+    pub device_id: Option<i64>,
+    pub channel: i64,
+    pub name: String
+}
+
+impl Probe {
+    pub fn new(device_id: Option<i64>, channel: i64, name: &str) -> Probe {
+        Probe {
+            id: 0,
+            device_id: device_id,
+            channel: channel,
+            name: name.to_string()
+        }
+    }
+}
+
+impl DbObject for Probe {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for Probe {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("device_id".to_string(), self.device_id.to_json());
+        m.insert("channel".to_string(), self.channel.to_json());
+        m.insert("name".to_string(), self.name.to_json());
+        m.to_json()
+    }
+}
+
 #[derive(RustcEncodable, RustcDecodable, Debug)]
 pub struct ConnectionStatus {
     pub id: i64,
+    pub device_id: Option<i64>,
     pub is_connect: bool,
     pub is_disconnect: bool,
     pub info: Option<String>,
-    pub created_at: DateTime<Local>
+    pub created_at: DateTime<UTC>
 }
 
 impl ConnectionStatus {
     pub fn new() -> ConnectionStatus {
         ConnectionStatus {
             id: 0,
+            device_id: None,
             is_connect: false,
             is_disconnect: false,
             info: None,
-            created_at: Local::now()
+            created_at: UTC::now()
         }
     }
 }
@@ -60,34 +136,48 @@ impl DbObject for ConnectionStatus {
     }
 }
 
+impl ToJson for ConnectionStatus {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("device_id".to_string(), self.device_id.to_json());
+        m.insert("is_connect".to_string(), self.is_connect.to_json());
+        m.insert("is_disconnect".to_string(), self.is_disconnect.to_json());
+        m.insert("info".to_string(), self.info.to_json());
+        m.insert("created_at".to_string(), date_to_json(&self.created_at));
+        m.to_json()
+    }
+}
+
 #[derive(RustcEncodable, RustcDecodable, Debug)]
 pub struct Project {
     pub id: i64,
     pub name: String,
-    pub start: DateTime<Local>,
-    pub end: DateTime<Local>,
-    pub sensor1_name: String,
-    pub sensor2_name: String,
-    pub created_at: DateTime<Local>,
-    pub updated_at: DateTime<Local>
+    pub start: DateTime<UTC>,
+    pub end: DateTime<UTC>,
+    pub probes: Vec<ProjectProbe>,
+    // display unit for the project; None uses the configured one
+    pub units: Option<TemperatureUnit>,
+    pub created_at: DateTime<UTC>,
+    pub updated_at: DateTime<UTC>
 }
 
 impl Project {
-    pub fn new(name: String, start: DateTime<Local>, end: DateTime<Local>, sensor1_name: String, sensor2_name: String) -> Project {
+    pub fn new(name: String, start: DateTime<UTC>, end: DateTime<UTC>, probes: Vec<ProjectProbe>) -> Project {
         Project {
             id: 0,
             name: name,
             start: start,
             end: end,
-            sensor1_name: sensor1_name,
-            sensor2_name: sensor2_name,
-            created_at: Local::now(),
-            updated_at: Local::now()
+            probes: probes,
+            units: None,
+            created_at: UTC::now(),
+            updated_at: UTC::now()
         }
     }
 
     pub fn default() -> Project {
-        Self::new("".to_string(), Local::now(), Local::now() + Duration::hours(12), "".to_string(), "".to_string())
+        Self::new("".to_string(), UTC::now(), UTC::now() + Duration::hours(12), vec![])
     }
 }
 
@@ -104,8 +194,8 @@ impl ToJson for Project {
         m.insert("name".to_string(), self.name.to_json());
         m.insert("start".to_string(), date_to_json(&self.start));
         m.insert("end".to_string(), date_to_json(&self.end));
-        m.insert("sensor1_name".to_string(), self.sensor1_name.to_json());
-        m.insert("sensor2_name".to_string(), self.sensor2_name.to_json());
+        m.insert("probes".to_string(), self.probes.to_json());
+        m.insert("units".to_string(), self.units.map(|u| u.as_str()).to_json());
         m.insert("created_at".to_string(), date_to_json(&self.created_at));
         m.insert("updated_at".to_string(), date_to_json(&self.updated_at));
 
@@ -113,21 +203,141 @@ impl ToJson for Project {
     }
 }
 
+// Temperatures are stored in C; this is what they're shown and entered in
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit
+}
+
+impl TemperatureUnit {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TemperatureUnit::Celsius => "C",
+            TemperatureUnit::Fahrenheit => "F"
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<TemperatureUnit> {
+        match s {
+            "C" | "c" => Some(TemperatureUnit::Celsius),
+            "F" | "f" => Some(TemperatureUnit::Fahrenheit),
+            _ => None
+        }
+    }
+
+    // e.g. "°F"
+    pub fn symbol(&self) -> String {
+        format!("\u{b0}{}", self.as_str())
+    }
+
+    pub fn from_celsius(&self, c: f64) -> f64 {
+        match *self {
+            TemperatureUnit::Celsius => c,
+            TemperatureUnit::Fahrenheit => c * 1.8 + 32.0
+        }
+    }
+
+    pub fn to_celsius(&self, v: f64) -> f64 {
+        match *self {
+            TemperatureUnit::Celsius => v,
+            TemperatureUnit::Fahrenheit => (v - 32.0) / 1.8
+        }
+    }
+
+    // Rates and differences have no offset to apply
+    pub fn delta_from_celsius(&self, c: f64) -> f64 {
+        match *self {
+            TemperatureUnit::Celsius => c,
+            TemperatureUnit::Fahrenheit => c * 1.8
+        }
+    }
+
+    pub fn delta_to_celsius(&self, v: f64) -> f64 {
+        match *self {
+            TemperatureUnit::Celsius => v,
+            TemperatureUnit::Fahrenheit => v / 1.8
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum ProbeRole {
+    // in the food
+    Meat,
+    // measuring the cooker
+    Pit
+}
+
+impl ProbeRole {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ProbeRole::Meat => "meat",
+            ProbeRole::Pit => "pit"
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<ProbeRole> {
+        match s {
+            "meat" => Some(ProbeRole::Meat),
+            "pit" => Some(ProbeRole::Pit),
+            _ => None
+        }
+    }
+}
+
+// A probe included in a project, under the name the project gives it
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct ProjectProbe {
+    pub probe_id: i64,
+    pub name: String,
+    pub position: i64,
+    pub role: ProbeRole
+}
+
+impl ProjectProbe {
+    pub fn new(probe_id: i64, name: &str, position: i64) -> ProjectProbe {
+        ProjectProbe {
+            probe_id: probe_id,
+            name: name.to_string(),
+            position: position,
+            role: ProbeRole::Meat
+        }
+    }
+}
+
+impl ToJson for ProjectProbe {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("probe_id".to_string(), self.probe_id.to_json());
+        m.insert("name".to_string(), self.name.to_json());
+        m.insert("position".to_string(), self.position.to_json());
+        m.insert("role".to_string(), self.role.as_str().to_json());
+        m.to_json()
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct ProbeValue {
+    pub probe_id: i64,
+    pub value: Option<f64>
+}
+
 #[derive(RustcEncodable, RustcDecodable, Debug)]
 pub struct Reading {
     pub id: i64,
-    pub value1: Option<f64>,
-    pub value2: Option<f64>,
-    pub timestamp: DateTime<Local>
+    pub device_id: Option<i64>,
+    pub values: Vec<ProbeValue>,
+    pub timestamp: DateTime<UTC>
 }
 
 impl Reading {
     pub fn new() -> Reading {
         Reading {
             id: 0,
-            value1: None,
-            value2: None,
-            timestamp: Local::now()
+            device_id: None,
+            values: vec![],
+            timestamp: UTC::now()
         }
     }
 }
@@ -136,8 +346,8 @@ impl ToJson for Reading {
     fn to_json(&self) -> Json {
         let mut m: BTreeMap<String, Json> = BTreeMap::new();
 
-        m.insert("value1".to_string(), self.value1.to_json());
-        m.insert("value2".to_string(), self.value2.to_json());
+        m.insert("device_id".to_string(), self.device_id.to_json());
+        m.insert("values".to_string(), self.values.iter().map(|v| (v.probe_id, v.value)).collect::<Vec<_>>().to_json());
         m.insert("timestamp".to_string(), date_to_json(&self.timestamp));
 
         m.to_json()
@@ -149,3 +359,397 @@ impl DbObject for Reading {
         self.id
     }
 }
+
+// One point of a probe series; a downsampled point covers a bucket of readings, a raw one has min == max == value
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct SeriesPoint {
+    pub timestamp: DateTime<UTC>,
+    pub value: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>
+}
+
+impl SeriesPoint {
+    pub fn raw(timestamp: DateTime<UTC>, value: Option<f64>) -> SeriesPoint {
+        SeriesPoint {
+            timestamp: timestamp,
+            value: value,
+            min: value,
+            max: value
+        }
+    }
+}
+
+impl ToJson for SeriesPoint {
+    fn to_json(&self) -> Json {
+        vec![date_to_json(&self.timestamp), self.value.to_json(), self.min.to_json(), self.max.to_json()].to_json()
+    }
+}
+
+// The readings of one probe over a project, in timestamp order.
+// newest is the time of the last reading included, which for a downsampled series is later than its last point.
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct ProbeSeries {
+    pub probe_id: i64,
+    pub name: String,
+    pub readings: Vec<SeriesPoint>,
+    pub newest: Option<DateTime<UTC>>
+}
+
+impl ProbeSeries {
+    pub fn new(probe_id: i64, name: &str) -> ProbeSeries {
+        ProbeSeries {
+            probe_id: probe_id,
+            name: name.to_string(),
+            readings: vec![],
+            newest: None
+        }
+    }
+}
+
+impl ToJson for ProbeSeries {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("probe_id".to_string(), self.probe_id.to_json());
+        m.insert("name".to_string(), self.name.to_json());
+        m.insert("readings".to_string(), self.readings.to_json());
+        m.insert("newest".to_string(), match self.newest {
+            Some(ref dt) => date_to_json(dt),
+            None => Json::Null
+        });
+        m.to_json()
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum AlarmKind {
+    // the probe goes over the threshold
+    Above,
+    // the probe goes under the threshold
+    Below,
+    // the probe reaches the threshold; stays triggered until acknowledged
+    Target,
+    // the probe changes faster than threshold degrees per minute, either way
+    Rate
+}
+
+impl AlarmKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AlarmKind::Above => "above",
+            AlarmKind::Below => "below",
+            AlarmKind::Target => "target",
+            AlarmKind::Rate => "rate"
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<AlarmKind> {
+        match s {
+            "above" => Some(AlarmKind::Above),
+            "below" => Some(AlarmKind::Below),
+            "target" => Some(AlarmKind::Target),
+            "rate" => Some(AlarmKind::Rate),
+            _ => None
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlarmState {
+    Triggered,
+    Acknowledged,
+    Cleared
+}
+
+impl AlarmState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AlarmState::Triggered => "triggered",
+            AlarmState::Acknowledged => "acknowledged",
+            AlarmState::Cleared => "cleared"
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<AlarmState> {
+        match s {
+            "triggered" => Some(AlarmState::Triggered),
+            "acknowledged" => Some(AlarmState::Acknowledged),
+            "cleared" => Some(AlarmState::Cleared),
+            _ => None
+        }
+    }
+
+    // triggered or acknowledged
+    pub fn is_active(&self) -> bool {
+        *self != AlarmState::Cleared
+    }
+}
+
+// A threshold on one of a project's probes. threshold is in degrees C (per minute for Rate);
+// state is the rule's newest event, Cleared if it has none
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct AlarmRule {
+    pub id: i64,
+    pub project_id: i64,
+    pub probe_id: i64,
+    pub kind: AlarmKind,
+    pub threshold: f64,
+    pub state: AlarmState,
+    pub created_at: DateTime<UTC>
+}
+
+impl AlarmRule {
+    pub fn new(project_id: i64, probe_id: i64, kind: AlarmKind, threshold: f64) -> AlarmRule {
+        AlarmRule {
+            id: 0,
+            project_id: project_id,
+            probe_id: probe_id,
+            kind: kind,
+            threshold: threshold,
+            state: AlarmState::Cleared,
+            created_at: UTC::now()
+        }
+    }
+}
+
+impl DbObject for AlarmRule {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for AlarmRule {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("project_id".to_string(), self.project_id.to_json());
+        m.insert("probe_id".to_string(), self.probe_id.to_json());
+        m.insert("kind".to_string(), self.kind.as_str().to_json());
+        m.insert("threshold".to_string(), self.threshold.to_json());
+        m.insert("state".to_string(), self.state.as_str().to_json());
+        m.insert("created_at".to_string(), date_to_json(&self.created_at));
+        m.to_json()
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct AlarmEvent {
+    pub id: i64,
+    pub rule_id: i64,
+    pub state: AlarmState,
+    // the reading that caused the transition, in degrees C (per minute for Rate); None when acknowledged
+    pub value: Option<f64>,
+    pub created_at: DateTime<UTC>
+}
+
+impl AlarmEvent {
+    pub fn new(rule_id: i64, state: AlarmState, value: Option<f64>) -> AlarmEvent {
+        AlarmEvent {
+            id: 0,
+            rule_id: rule_id,
+            state: state,
+            value: value,
+            created_at: UTC::now()
+        }
+    }
+}
+
+impl DbObject for AlarmEvent {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for AlarmEvent {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("rule_id".to_string(), self.rule_id.to_json());
+        m.insert("state".to_string(), self.state.as_str().to_json());
+        m.insert("value".to_string(), self.value.to_json());
+        m.insert("created_at".to_string(), date_to_json(&self.created_at));
+        m.to_json()
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum NotifierKind {
+    // JSON POST to a URL
+    Webhook,
+    // mail through the configured SMTP server
+    Email,
+    // run a local program with the alert in its environment
+    Command
+}
+
+impl NotifierKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            NotifierKind::Webhook => "webhook",
+            NotifierKind::Email => "email",
+            NotifierKind::Command => "command"
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<NotifierKind> {
+        match s {
+            "webhook" => Some(NotifierKind::Webhook),
+            "email" => Some(NotifierKind::Email),
+            "command" => Some(NotifierKind::Command),
+            _ => None
+        }
+    }
+}
+
+// Somewhere a project's alarms are sent
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct ProjectNotifier {
+    pub id: i64,
+    pub project_id: i64,
+    pub kind: NotifierKind,
+    pub target: String,
+    pub created_at: DateTime<UTC>
+}
+
+impl ProjectNotifier {
+    pub fn new(project_id: i64, kind: NotifierKind, target: &str) -> ProjectNotifier {
+        ProjectNotifier {
+            id: 0,
+            project_id: project_id,
+            kind: kind,
+            target: target.to_string(),
+            created_at: UTC::now()
+        }
+    }
+}
+
+impl DbObject for ProjectNotifier {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for ProjectNotifier {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("project_id".to_string(), self.project_id.to_json());
+        m.insert("kind".to_string(), self.kind.as_str().to_json());
+        m.insert("target".to_string(), self.target.to_json());
+        m.insert("created_at".to_string(), date_to_json(&self.created_at));
+        m.to_json()
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum ProjectEventKind {
+    // a meat probe sitting still partway up
+    Stall,
+    // the pit probe dropping sharply and coming back
+    LidOpen
+}
+
+impl ProjectEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ProjectEventKind::Stall => "stall",
+            ProjectEventKind::LidOpen => "lid_open"
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<ProjectEventKind> {
+        match s {
+            "stall" => Some(ProjectEventKind::Stall),
+            "lid_open" => Some(ProjectEventKind::LidOpen),
+            _ => None
+        }
+    }
+}
+
+// Something noticed in a project's readings, over a span of time
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct ProjectEvent {
+    pub id: i64,
+    pub project_id: i64,
+    pub probe_id: i64,
+    pub kind: ProjectEventKind,
+    pub started_at: DateTime<UTC>,
+    // None while it's still going on
+    pub ended_at: Option<DateTime<UTC>>
+}
+
+impl ProjectEvent {
+    pub fn new(project_id: i64, probe_id: i64, kind: ProjectEventKind, started_at: DateTime<UTC>) -> ProjectEvent {
+        ProjectEvent {
+            id: 0,
+            project_id: project_id,
+            probe_id: probe_id,
+            kind: kind,
+            started_at: started_at,
+            ended_at: None
+        }
+    }
+}
+
+impl DbObject for ProjectEvent {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for ProjectEvent {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("project_id".to_string(), self.project_id.to_json());
+        m.insert("probe_id".to_string(), self.probe_id.to_json());
+        m.insert("kind".to_string(), self.kind.as_str().to_json());
+        m.insert("started_at".to_string(), date_to_json(&self.started_at));
+        m.insert("ended_at".to_string(), match self.ended_at {
+            Some(ref dt) => date_to_json(dt),
+            None => Json::Null
+        });
+        m.to_json()
+    }
+}
+
+// A note on a project's timeline
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone)]
+pub struct Annotation {
+    pub id: i64,
+    pub project_id: i64,
+    // when the note is about, which may be before it was written
+    pub timestamp: DateTime<UTC>,
+    pub text: String,
+    pub created_at: DateTime<UTC>
+}
+
+impl Annotation {
+    pub fn new(project_id: i64, timestamp: DateTime<UTC>, text: &str) -> Annotation {
+        Annotation {
+            id: 0,
+            project_id: project_id,
+            timestamp: timestamp,
+            text: text.to_string(),
+            created_at: UTC::now()
+        }
+    }
+}
+
+impl DbObject for Annotation {
+    fn get_id(&self) -> i64 {
+        self.id
+    }
+}
+
+impl ToJson for Annotation {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("project_id".to_string(), self.project_id.to_json());
+        m.insert("timestamp".to_string(), date_to_json(&self.timestamp));
+        m.insert("text".to_string(), self.text.to_json());
+        m.insert("created_at".to_string(), date_to_json(&self.created_at));
+        m.to_json()
+    }
+}
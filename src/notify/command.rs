@@ -0,0 +1,46 @@
+use std::process::Command;
+
+use super::{Alert, Notifier, NotifyError};
+
+// Runs a local program with the alert's details in PIBQ_* environment variables.
+// The program gets no arguments; it's expected to exit 0.
+pub struct CommandNotifier {
+    program: String
+}
+
+impl CommandNotifier {
+    pub fn new(program: &str) -> CommandNotifier {
+        CommandNotifier {
+            program: program.to_string()
+        }
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let value = match alert.value {
+            Some(v) => v.to_string(),
+            None => "".to_string()
+        };
+
+        let status = try!(Command::new(&self.program)
+            .env("PIBQ_PROJECT_ID", alert.project_id.to_string())
+            .env("PIBQ_PROJECT", &alert.project_name)
+            .env("PIBQ_PROBE", &alert.probe_name)
+            .env("PIBQ_RULE_ID", alert.rule_id.to_string())
+            .env("PIBQ_KIND", alert.kind.as_str())
+            .env("PIBQ_STATE", alert.state.as_str())
+            .env("PIBQ_THRESHOLD", alert.threshold.to_string())
+            .env("PIBQ_VALUE", value)
+            .env("PIBQ_AT", alert.at.to_rfc3339())
+            .env("PIBQ_SUBJECT", alert.subject())
+            .env("PIBQ_MESSAGE", alert.message())
+            .status());
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(NotifyError::Rejected(format!("{} exited with {}", self.program, status)))
+        }
+    }
+}
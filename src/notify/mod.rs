@@ -0,0 +1,287 @@
+mod command;
+mod smtp;
+mod webhook;
+
+pub use self::command::CommandNotifier;
+pub use self::smtp::SmtpNotifier;
+pub use self::webhook::WebhookNotifier;
+
+use chrono::datetime::DateTime;
+use chrono::offset::local::Local;
+use chrono::offset::utc::UTC;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::config::AlertConfig;
+use super::models::{AlarmEvent, AlarmKind, AlarmRule, AlarmState, NotifierKind, Project, ProjectNotifier, TemperatureUnit};
+use super::sql;
+
+// How long a notifier waits on a remote server before giving up
+pub const TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Io(io::Error),
+    // the server or command answered, but not with success
+    Rejected(String),
+    // the notifier can't be used as configured
+    Config(String)
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &NotifyError::Io(ref err) => write!(f, "{}", err),
+            &NotifyError::Rejected(ref msg) => write!(f, "rejected: {}", msg),
+            &NotifyError::Config(ref msg) => write!(f, "misconfigured: {}", msg)
+        }
+    }
+}
+
+impl Error for NotifyError {
+    fn description(&self) -> &str {
+        match self {
+            &NotifyError::Io(ref err) => err.description(),
+            &NotifyError::Rejected(_) => "notification rejected",
+            &NotifyError::Config(_) => "notifier misconfigured"
+        }
+    }
+}
+
+impl From<io::Error> for NotifyError {
+    fn from(err: io::Error) -> NotifyError {
+        NotifyError::Io(err)
+    }
+}
+
+// An alarm state change, with what a person needs to make sense of it
+#[derive(Clone, Debug)]
+pub struct Alert {
+    pub project_id: i64,
+    pub project_name: String,
+    pub probe_name: String,
+    pub rule_id: i64,
+    pub kind: AlarmKind,
+    pub state: AlarmState,
+    // degrees C, per minute for Rate rules
+    pub threshold: f64,
+    pub value: Option<f64>,
+    // for the human readable text
+    pub units: TemperatureUnit,
+    pub at: DateTime<UTC>
+}
+
+impl Alert {
+    // units are the project's, or the given default if it hasn't chosen
+    pub fn new(project: &Project, rule: &AlarmRule, event: &AlarmEvent, units: TemperatureUnit) -> Alert {
+        let probe_name = match project.probes.iter().find(|p| p.probe_id == rule.probe_id) {
+            Some(p) => p.name.clone(),
+            None => format!("probe {}", rule.probe_id)
+        };
+
+        Alert {
+            project_id: project.id,
+            project_name: project.name.clone(),
+            probe_name: probe_name,
+            rule_id: rule.id,
+            kind: rule.kind,
+            state: event.state,
+            threshold: rule.threshold,
+            value: event.value,
+            units: project.units.unwrap_or(units),
+            at: event.created_at
+        }
+    }
+
+    fn temperature(&self, c: f64) -> String {
+        match self.kind {
+            AlarmKind::Rate => format!("{:.1}{}/min", self.units.delta_from_celsius(c), self.units.symbol()),
+            _ => format!("{:.1}{}", self.units.from_celsius(c), self.units.symbol())
+        }
+    }
+
+    // e.g. "goes above 203.0°F"
+    pub fn condition(&self) -> String {
+        let t = self.temperature(self.threshold);
+
+        match self.kind {
+            AlarmKind::Above => format!("goes above {}", t),
+            AlarmKind::Below => format!("goes below {}", t),
+            AlarmKind::Target => format!("reaches {}", t),
+            AlarmKind::Rate => format!("changes faster than {}", t)
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        format!("[pibq] {}: {} {} ({})", self.project_name, self.probe_name, self.condition(), self.state.as_str())
+    }
+
+    pub fn message(&self) -> String {
+        let value = match self.value {
+            Some(v) => self.temperature(v),
+            None => "no reading".to_string()
+        };
+
+        format!("{} on {} was {} at {}, with a reading of {}.\nThe alarm is set for when it {}.",
+                self.probe_name, self.project_name, self.state.as_str(),
+                self.at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), value, self.condition())
+    }
+}
+
+impl ToJson for Alert {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("project_id".to_string(), self.project_id.to_json());
+        m.insert("project".to_string(), self.project_name.to_json());
+        m.insert("probe".to_string(), self.probe_name.to_json());
+        m.insert("rule_id".to_string(), self.rule_id.to_json());
+        m.insert("kind".to_string(), self.kind.as_str().to_json());
+        m.insert("state".to_string(), self.state.as_str().to_json());
+        m.insert("threshold".to_string(), self.threshold.to_json());
+        m.insert("value".to_string(), self.value.to_json());
+        m.insert("units".to_string(), self.units.as_str().to_json());
+        m.insert("at".to_string(), self.at.to_rfc3339().to_json());
+        m.insert("message".to_string(), self.message().to_json());
+        m.to_json()
+    }
+}
+
+// Opens a connection with TIMEOUT_SECS on connecting as well as reading and writing, so one
+// unreachable host can't hold up the alerts queued behind it for the OS's minutes-long connect timeout
+fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "address didn't resolve");
+
+    for a in try!(addr.to_socket_addrs()) {
+        match TcpStream::connect_timeout(&a, timeout) {
+            Ok(stream) => {
+                try!(stream.set_read_timeout(Some(timeout)));
+                try!(stream.set_write_timeout(Some(timeout)));
+                return Ok(stream);
+            },
+            Err(e) => last_err = e
+        }
+    }
+
+    Err(last_err)
+}
+
+// Somewhere to send alerts
+pub trait Notifier: Send {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError>;
+}
+
+// The notifier for one of a project's notifier settings
+pub fn from_setting(setting: &ProjectNotifier, config: &AlertConfig) -> Result<Box<Notifier>, NotifyError> {
+    match setting.kind {
+        NotifierKind::Webhook => {
+            let n = try!(WebhookNotifier::new(&setting.target));
+            Ok(Box::new(n))
+        },
+        NotifierKind::Email => {
+            match config.smtp_server {
+                Some(ref server) => {
+                    try!(smtp::check_address(&setting.target));
+                    Ok(Box::new(SmtpNotifier::new(server, &config.smtp_from, &setting.target)))
+                },
+                None => Err(NotifyError::Config("no alerts.smtp_server configured".to_string()))
+            }
+        },
+        NotifierKind::Command => {
+            // the web UI only offers allowed commands, but the database isn't to be trusted with what gets run
+            if config.commands.iter().any(|c| *c == setting.target) {
+                Ok(Box::new(CommandNotifier::new(&setting.target)))
+            } else {
+                Err(NotifyError::Config(format!("{} isn't in alerts.commands", setting.target)))
+            }
+        }
+    }
+}
+
+// Drops an alert if the same rule went to the same state within the interval
+pub struct RateLimiter {
+    min_interval: Duration,
+    last_sent: HashMap<(i64, AlarmState), Instant>
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration) -> RateLimiter {
+        RateLimiter {
+            min_interval: min_interval,
+            last_sent: HashMap::new()
+        }
+    }
+
+    pub fn allow(&mut self, rule_id: i64, state: AlarmState, now: Instant) -> bool {
+        let key = (rule_id, state);
+
+        let allowed = match self.last_sent.get(&key) {
+            Some(last) => now.duration_since(*last) >= self.min_interval,
+            None => true
+        };
+
+        if allowed {
+            self.last_sent.insert(key, now);
+        }
+
+        allowed
+    }
+}
+
+// Starts a thread that sends alarm events to their projects' notifiers, returning where to send
+// the events. Notifying happens off the caller's thread, so a slow mail server can't hold up readings.
+pub fn start_dispatcher(db_path: &str, config: AlertConfig, units: TemperatureUnit) -> Sender<AlarmEvent> {
+    let (tx, rx) = mpsc::channel::<AlarmEvent>();
+    let db_path = db_path.to_string();
+
+    thread::Builder::new().name("notifier".to_string()).spawn(move || {
+        let conn = sql::get_connection(&db_path, false).unwrap();
+        let mut limiter = RateLimiter::new(Duration::from_secs(config.min_interval));
+
+        for event in rx.iter() {
+            // only the harvester's own transitions are sent; people acknowledging alarms already know
+            if event.state == AlarmState::Acknowledged {
+                continue;
+            }
+
+            if !limiter.allow(event.rule_id, event.state, Instant::now()) {
+                continue;
+            }
+
+            let (rule, project) = match sql::get_alarm_rule(&conn, event.rule_id) {
+                Ok(Some(rule)) => match sql::get_project(&conn, rule.project_id) {
+                    Ok(Some(project)) => (rule, project),
+                    Ok(None) => continue,
+                    Err(e) => { println!("notifier: {}", e); continue; }
+                },
+                Ok(None) => continue,
+                Err(e) => { println!("notifier: {}", e); continue; }
+            };
+
+            let settings = match sql::get_project_notifiers(&conn, project.id) {
+                Ok(s) => s,
+                Err(e) => { println!("notifier: {}", e); continue; }
+            };
+
+            let alert = Alert::new(&project, &rule, &event, units);
+
+            for setting in settings.iter() {
+                let result = from_setting(setting, &config).and_then(|n| n.notify(&alert));
+
+                match result {
+                    Ok(_) => println!("notifier: sent {} {} to {}", setting.kind.as_str(), alert.state.as_str(), setting.target),
+                    Err(e) => println!("notifier: {} {} failed: {}", setting.kind.as_str(), setting.target, e)
+                }
+            }
+        }
+    }).unwrap();
+
+    tx
+}
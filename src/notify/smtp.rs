@@ -0,0 +1,99 @@
+use chrono::offset::utc::UTC;
+use std::io::{BufRead, BufReader, Write};
+
+use super::{connect, Alert, Notifier, NotifyError};
+
+// Mails the alert through an SMTP relay that accepts mail without authentication,
+// such as a local MTA or the home router
+pub struct SmtpNotifier {
+    // host:port
+    server: String,
+    from: String,
+    to: String
+}
+
+impl SmtpNotifier {
+    pub fn new(server: &str, from: &str, to: &str) -> SmtpNotifier {
+        SmtpNotifier {
+            server: server.to_string(),
+            from: from.to_string(),
+            to: to.to_string()
+        }
+    }
+}
+
+// The address goes into RCPT TO and the To header as is, so anything that could end the line or the
+// angle brackets is refused
+pub fn check_address(address: &str) -> Result<(), NotifyError> {
+    if !address.contains('@') || address.contains(|c| c == '\r' || c == '\n' || c == '<' || c == '>') {
+        return Err(NotifyError::Config(format!("invalid email address {:?}", address)));
+    }
+
+    Ok(())
+}
+
+// Header values can't hold line breaks, which would start new headers
+fn header_value(value: &str) -> String {
+    value.replace(|c| c == '\r' || c == '\n', " ")
+}
+
+// Reads a reply, following multi-line "250-" continuations, and checks its code
+fn expect<R: BufRead>(reader: &mut R, code: &str) -> Result<(), NotifyError> {
+    loop {
+        let mut line = String::new();
+        if try!(reader.read_line(&mut line)) == 0 {
+            return Err(NotifyError::Rejected(format!("connection closed waiting for {}", code)));
+        }
+
+        if !line.starts_with(code) {
+            return Err(NotifyError::Rejected(line.trim().to_string()));
+        }
+
+        // "250 " ends the reply, "250-" continues it
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+// Lines starting with a dot are escaped by doubling it, so they can't end the DATA section
+fn dot_stuff(text: &str) -> String {
+    text.lines()
+        .map(|l| if l.starts_with('.') { format!(".{}", l) } else { l.to_string() })
+        .collect::<Vec<String>>()
+        .join("\r\n")
+}
+
+impl Notifier for SmtpNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        try!(check_address(&self.to));
+        let stream = try!(connect(self.server.as_str()));
+
+        let mut writer = try!(stream.try_clone());
+        let mut reader = BufReader::new(stream);
+
+        try!(expect(&mut reader, "220"));
+
+        try!(write!(writer, "HELO pibq\r\n"));
+        try!(expect(&mut reader, "250"));
+
+        try!(write!(writer, "MAIL FROM:<{}>\r\n", self.from));
+        try!(expect(&mut reader, "250"));
+
+        try!(write!(writer, "RCPT TO:<{}>\r\n", self.to));
+        try!(expect(&mut reader, "25"));
+
+        try!(write!(writer, "DATA\r\n"));
+        try!(expect(&mut reader, "354"));
+
+        try!(write!(writer, "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n.\r\n",
+                    self.from, self.to, header_value(&alert.subject()), UTC::now().to_rfc2822(), dot_stuff(&alert.message())));
+        try!(expect(&mut reader, "250"));
+
+        try!(write!(writer, "QUIT\r\n"));
+        // the message is accepted; a relay that hangs up without answering QUIT doesn't matter
+        let _ = expect(&mut reader, "221");
+
+        Ok(())
+    }
+}
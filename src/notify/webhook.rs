@@ -0,0 +1,65 @@
+use rustc_serialize::json::ToJson;
+use std::io::{BufRead, BufReader, Write};
+use url::Url;
+
+use super::{connect, Alert, Notifier, NotifyError};
+
+// POSTs the alert as JSON. Plain http only; there's no TLS support in this build.
+pub struct WebhookNotifier {
+    host: String,
+    port: u16,
+    // path and query
+    path: String
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Result<WebhookNotifier, NotifyError> {
+        let url = match Url::parse(url) {
+            Ok(u) => u,
+            Err(e) => return Err(NotifyError::Config(format!("invalid url {}: {}", url, e)))
+        };
+
+        if url.scheme() != "http" {
+            return Err(NotifyError::Config(format!("only http webhooks are supported, not {}", url.scheme())));
+        }
+
+        let host = match url.host_str() {
+            Some(h) => h.to_string(),
+            None => return Err(NotifyError::Config(format!("no host in {}", url)))
+        };
+
+        let path = match url.query() {
+            Some(q) => format!("{}?{}", url.path(), q),
+            None => url.path().to_string()
+        };
+
+        Ok(WebhookNotifier {
+            host: host,
+            port: url.port_or_known_default().unwrap_or(80),
+            path: path
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let body = alert.to_json().to_string();
+
+        let mut stream = try!(connect((self.host.as_str(), self.port)));
+
+        try!(write!(stream, "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    self.path, self.host, self.port, body.len(), body));
+        try!(stream.flush());
+
+        // only the status line matters
+        let mut status_line = String::new();
+        try!(BufReader::new(stream).read_line(&mut status_line));
+
+        let code = status_line.split_whitespace().nth(1).and_then(|c| c.parse::<u16>().ok());
+
+        match code {
+            Some(c) if c >= 200 && c < 300 => Ok(()),
+            _ => Err(NotifyError::Rejected(status_line.trim().to_string()))
+        }
+    }
+}
@@ -0,0 +1,368 @@
+extern crate chrono;
+extern crate getopts;
+extern crate rusqlite;
+
+extern crate pibq;
+
+use chrono::datetime::DateTime;
+use chrono::duration::Duration;
+use chrono::offset::local::Local;
+use chrono::offset::utc::UTC;
+use getopts::{Matches, Options};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use pibq::config::Config;
+use pibq::export::ProjectExport;
+use pibq::import;
+use pibq::models::{ProbeRole, Project, ProjectProbe, TemperatureUnit};
+use pibq::sql;
+use pibq::sql::migrations;
+
+const COMMANDS: &'static str = "
+Commands:
+    projects                list projects
+    probes                  list probes, for --probe
+    create-project          create a project from --name, --start, --end, --probe and --units
+    edit-project ID         change the given fields of a project; --probe replaces its probes
+    delete-project ID       delete a project and its alarms, notes and notifiers
+    dump-project ID         print everything recorded for a project as JSON
+    status                  show each device's latest connection status and reading
+    migrations              list migrations and whether they've been applied or changed since
+    migrate                 apply pending migrations, creating the database if needed
+    prune [DAYS]            delete readings older than DAYS (default database.retention_days) outside any project
+    vacuum                  reclaim space left by deleted rows
+    backup FILE             copy the database to FILE while holding off writers
+";
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options] COMMAND [ARGS]", program);
+    print!("{}{}", opts.usage(&brief), COMMANDS);
+}
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", msg);
+    process::exit(1);
+}
+
+fn open(config: &Config) -> rusqlite::Connection {
+    match sql::get_connection(&config.database.path, false) {
+        Ok(c) => c,
+        Err(e) => fail(&format!("unable to open {}: {}", config.database.path, e))
+    }
+}
+
+fn local_time(dt: &DateTime<UTC>) -> String {
+    dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn arg<'a>(args: &'a [String], name: &str) -> &'a str {
+    match args.first() {
+        Some(a) => a,
+        None => fail(&format!("missing {}", name))
+    }
+}
+
+fn parse_id(s: &str) -> i64 {
+    match s.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => fail(&format!("invalid id {}", s))
+    }
+}
+
+// "now", RFC 3339, or local time as "2016-06-18 14:30:00"
+fn parse_time(s: &str) -> DateTime<UTC> {
+    if s == "now" {
+        return UTC::now();
+    }
+
+    match import::parse_timestamp(s) {
+        Some(dt) => dt,
+        None => fail(&format!("invalid time {}", s))
+    }
+}
+
+fn parse_units(s: &str) -> Option<TemperatureUnit> {
+    if s == "default" {
+        return None;
+    }
+
+    match TemperatureUnit::from_str(s) {
+        Some(u) => Some(u),
+        None => fail("units must be C, F or default")
+    }
+}
+
+fn get_project(conn: &rusqlite::Connection, id: i64) -> Project {
+    match sql::get_project(conn, id) {
+        Ok(Some(p)) => p,
+        Ok(None) => fail(&format!("no project {}", id)),
+        Err(e) => fail(&e.to_string())
+    }
+}
+
+// Probes come as ID or ID:NAME, the name defaulting to the probe's own; --pit-probe ones measure the pit
+fn parse_probes(conn: &rusqlite::Connection, matches: &Matches) -> Vec<ProjectProbe> {
+    let probes = match sql::get_probes(conn) {
+        Ok(p) => p,
+        Err(e) => fail(&e.to_string())
+    };
+
+    let mut result = vec![];
+    let specs = matches.opt_strs("probe").into_iter().map(|s| (s, ProbeRole::Meat))
+        .chain(matches.opt_strs("pit-probe").into_iter().map(|s| (s, ProbeRole::Pit)));
+
+    for (spec, role) in specs {
+        let mut parts = spec.splitn(2, ':');
+        let id = parse_id(parts.next().unwrap_or(""));
+        let probe = match probes.iter().find(|p| p.id == id) {
+            Some(p) => p,
+            None => fail(&format!("no probe {}", id))
+        };
+        let name = parts.next().map(|n| n.trim()).unwrap_or("");
+
+        let mut pp = ProjectProbe::new(id, if name.is_empty() { probe.name.as_str() } else { name }, result.len() as i64 + 1);
+        pp.role = role;
+        result.push(pp);
+    }
+
+    result
+}
+
+// Applies the project options that were given
+fn assign_project(conn: &rusqlite::Connection, project: &mut Project, matches: &Matches) {
+    if let Some(v) = matches.opt_str("name") { project.name = v; }
+    if let Some(v) = matches.opt_str("start") { project.start = parse_time(&v); }
+    if let Some(v) = matches.opt_str("end") { project.end = parse_time(&v); }
+    if let Some(v) = matches.opt_str("units") { project.units = parse_units(&v); }
+
+    if matches.opt_present("probe") || matches.opt_present("pit-probe") {
+        project.probes = parse_probes(conn, matches);
+    }
+
+    if project.name.trim().is_empty() {
+        fail("the project needs a --name");
+    }
+    if project.probes.is_empty() {
+        fail("the project needs at least one --probe");
+    }
+    if project.end <= project.start {
+        fail("the project must end after it starts");
+    }
+}
+
+fn list_projects(conn: &rusqlite::Connection) {
+    let projects = match sql::get_projects(conn) {
+        Ok(p) => p,
+        Err(e) => fail(&e.to_string())
+    };
+
+    for p in projects.iter() {
+        let probes: Vec<String> = p.probes.iter().map(|pp| format!("{} ({})", pp.name, pp.probe_id)).collect();
+        println!("{:>5}  {:<30}  {} - {}  {}", p.id, p.name, local_time(&p.start), local_time(&p.end), probes.join(", "));
+    }
+}
+
+fn list_probes(conn: &rusqlite::Connection) {
+    let probes = match sql::get_probes(conn) {
+        Ok(p) => p,
+        Err(e) => fail(&e.to_string())
+    };
+    let devices = match sql::get_devices(conn) {
+        Ok(d) => d,
+        Err(e) => fail(&e.to_string())
+    };
+
+    for p in probes.iter() {
+        let serial = p.device_id.and_then(|id| devices.iter().find(|d| d.id == id)).map(|d| d.serial_number.as_str()).unwrap_or("-");
+        println!("{:>5}  {:<20}  {:<16}  channel {}", p.id, p.name, serial, p.channel);
+    }
+}
+
+fn show_status(conn: &rusqlite::Connection) {
+    let devices = match sql::get_devices(conn) {
+        Ok(d) => d,
+        Err(e) => fail(&e.to_string())
+    };
+    let statuses = match sql::get_latest_connection_statuses(conn) {
+        Ok(s) => s,
+        Err(e) => fail(&e.to_string())
+    };
+
+    for status in statuses.iter() {
+        let device = status.device_id.and_then(|id| devices.iter().find(|d| d.id == id));
+        let state = if status.is_connect { "connected" } else { "disconnected" };
+
+        println!("{} ({}): {} at {}{}",
+                 device.map(|d| d.serial_number.as_str()).unwrap_or("unknown device"),
+                 device.and_then(|d| d.path.as_ref()).map(|p| p.as_str()).unwrap_or("-"),
+                 state, local_time(&status.created_at),
+                 status.info.as_ref().map(|i| format!(": {}", i)).unwrap_or("".to_string()));
+
+        if let Some(d) = device {
+            match sql::get_latest_reading_time(conn, d.id) {
+                Ok(Some(at)) => println!("    last reading at {}", local_time(&at)),
+                Ok(None) => println!("    no readings"),
+                Err(e) => fail(&e.to_string())
+            }
+        }
+    }
+
+    if statuses.is_empty() {
+        println!("No connection statuses recorded");
+    }
+}
+
+fn list_migrations(conn: &rusqlite::Connection) {
+    let states = match migrations::get_migration_states(conn) {
+        Ok(s) => s,
+        Err(e) => fail(&e.to_string())
+    };
+
+    for s in states.iter() {
+        let state = match (s.applied, s.modified) {
+            (false, _) => "pending",
+            (true, false) => "applied",
+            (true, true) => "modified"
+        };
+        println!("{:>5}  {:<8}  {}", s.version, state, s.name.unwrap_or("(not in this build)"));
+    }
+}
+
+// Holds a write lock so no writer is partway through changing the file while it's copied
+fn backup(conn: &rusqlite::Connection, from: &str, to: &str) -> Result<u64, String> {
+    try!(conn.execute_batch("BEGIN IMMEDIATE").map_err(|e| e.to_string()));
+    let copied = fs::copy(from, to).map_err(|e| e.to_string());
+    try!(conn.execute_batch("ROLLBACK").map_err(|e| e.to_string()));
+    copied
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("c", "config", &format!("config file (default {})", pibq::config::DEFAULT_PATH), "FILE");
+    opts.optopt("d", "dbfile", "sqlite DB file", "FILE");
+    opts.optopt("", "name", "project name", "NAME");
+    opts.optopt("", "start", "project start: now, RFC 3339 or local \"YYYY-MM-DD HH:MM:SS\"", "TIME");
+    opts.optopt("", "end", "project end (default 12 hours after the start)", "TIME");
+    opts.optmulti("", "probe", "probe in the meat, by id, optionally with the project's name for it", "ID[:NAME]");
+    opts.optmulti("", "pit-probe", "probe measuring the pit", "ID[:NAME]");
+    opts.optopt("u", "units", "C, F or default", "UNITS");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            println!("{}", f.to_string());
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_usage(&program, opts);
+        return;
+    }
+
+    let mut config = match Config::load(matches.opt_str("c").as_ref().map(|s| s.as_str())) {
+        Ok(c) => c,
+        Err(e) => fail(&e.to_string())
+    };
+    if let Some(v) = matches.opt_str("d") { config.database.path = v; }
+
+    let command = matches.free[0].as_str();
+    let rest = &matches.free[1..];
+
+    match command {
+        "projects" => list_projects(&open(&config)),
+        "probes" => list_probes(&open(&config)),
+        "create-project" => {
+            let conn = open(&config);
+            let start = matches.opt_str("start").map(|s| parse_time(&s)).unwrap_or(UTC::now());
+            let mut project = Project::new("".to_string(), start, start + Duration::hours(12), vec![]);
+            assign_project(&conn, &mut project, &matches);
+
+            match sql::insert_project(&conn, &mut project) {
+                Ok(_) => println!("Created project {}", project.id),
+                Err(e) => fail(&e.to_string())
+            }
+        },
+        "edit-project" => {
+            let conn = open(&config);
+            let mut project = get_project(&conn, parse_id(arg(rest, "project id")));
+            assign_project(&conn, &mut project, &matches);
+            project.updated_at = UTC::now();
+
+            match sql::update_project(&conn, &mut project) {
+                Ok(_) => println!("Updated project {}", project.id),
+                Err(e) => fail(&e.to_string())
+            }
+        },
+        "delete-project" => {
+            let conn = open(&config);
+            let project = get_project(&conn, parse_id(arg(rest, "project id")));
+
+            match sql::delete_project(&conn, project.id) {
+                Ok(_) => println!("Deleted project {} \"{}\"", project.id, project.name),
+                Err(e) => fail(&e.to_string())
+            }
+        },
+        "dump-project" => {
+            let conn = open(&config);
+            let project = get_project(&conn, parse_id(arg(rest, "project id")));
+            let units = matches.opt_str("units").and_then(|u| parse_units(&u)).or(project.units).unwrap_or(config.units);
+
+            match ProjectExport::load(&conn, project) {
+                Ok(export) => println!("{}", export.as_json(units).pretty()),
+                Err(e) => fail(&e.to_string())
+            }
+        },
+        "status" => show_status(&open(&config)),
+        "migrations" => list_migrations(&open(&config)),
+        "migrate" => {
+            let conn = match sql::get_connection(&config.database.path, true) {
+                Ok(c) => c,
+                Err(e) => fail(&format!("unable to migrate {}: {}", config.database.path, e))
+            };
+            list_migrations(&conn);
+        },
+        "prune" => {
+            let days = match rest.first() {
+                Some(d) => match d.parse::<u32>() {
+                    Ok(d) => d,
+                    Err(_) => fail(&format!("invalid number of days {}", d))
+                },
+                None => match config.database.retention_days {
+                    Some(d) => d,
+                    None => fail("give DAYS, or set database.retention_days")
+                }
+            };
+
+            let conn = open(&config);
+            let result = sql::update_rollups(&conn).and_then(|_| sql::prune_readings(&conn, UTC::now() - Duration::days(days as i64)));
+            match result {
+                Ok(n) => println!("Pruned {} readings older than {} days", n, days),
+                Err(e) => fail(&e.to_string())
+            }
+        },
+        "vacuum" => {
+            match sql::vacuum(&open(&config)) {
+                Ok(_) => println!("Vacuumed {}", config.database.path),
+                Err(e) => fail(&e.to_string())
+            }
+        },
+        "backup" => {
+            let to = arg(rest, "backup file");
+            match backup(&open(&config), &config.database.path, to) {
+                Ok(bytes) => println!("Copied {} bytes to {}", bytes, to),
+                Err(e) => fail(&format!("unable to back up to {}: {}", to, e))
+            }
+        },
+        _ => {
+            print_usage(&program, opts);
+            process::exit(1);
+        }
+    }
+}
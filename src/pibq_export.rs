@@ -0,0 +1,107 @@
+extern crate getopts;
+
+extern crate pibq;
+
+use getopts::Options;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+
+use pibq::config::Config;
+use pibq::export::ProjectExport;
+use pibq::models::TemperatureUnit;
+use pibq::sql;
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options] PROJECT_ID", program);
+    print!("{}", opts.usage(&brief));
+}
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", msg);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("c", "config", &format!("config file (default {})", pibq::config::DEFAULT_PATH), "FILE");
+    opts.optopt("d", "dbfile", "sqlite DB file", "FILE");
+    opts.optopt("f", "format", "csv (the default) or json", "FORMAT");
+    opts.optopt("u", "units", "C or F (default the project's units)", "UNITS");
+    opts.optopt("o", "output", "file to write (default stdout)", "FILE");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            println!("{}", f.to_string());
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        print_usage(&program, opts);
+        return;
+    }
+
+    let mut config = match Config::load(matches.opt_str("c").as_ref().map(|s| s.as_str())) {
+        Ok(c) => c,
+        Err(e) => fail(&e.to_string())
+    };
+    if let Some(v) = matches.opt_str("d") { config.database.path = v; }
+
+    let project_id = match matches.free[0].parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => fail(&format!("invalid project id {}", matches.free[0]))
+    };
+
+    let format = matches.opt_str("f").unwrap_or("csv".to_string());
+    if format != "csv" && format != "json" {
+        fail("format must be csv or json");
+    }
+
+    let conn = match sql::get_connection(&config.database.path, false) {
+        Ok(c) => c,
+        Err(e) => fail(&format!("unable to open {}: {}", config.database.path, e))
+    };
+
+    let project = match sql::get_project(&conn, project_id) {
+        Ok(Some(p)) => p,
+        Ok(None) => fail(&format!("no project {}", project_id)),
+        Err(e) => fail(&e.to_string())
+    };
+
+    let units = match matches.opt_str("u") {
+        None => project.units.unwrap_or(config.units),
+        Some(u) => match TemperatureUnit::from_str(&u) {
+            Some(u) => u,
+            None => fail("units must be C or F")
+        }
+    };
+
+    let export = match ProjectExport::load(&conn, project) {
+        Ok(e) => e,
+        Err(e) => fail(&e.to_string())
+    };
+
+    let mut out: Box<Write> = match matches.opt_str("o") {
+        None => Box::new(io::stdout()),
+        Some(path) => match File::create(&path) {
+            Ok(f) => Box::new(f),
+            Err(e) => fail(&format!("unable to write {}: {}", path, e))
+        }
+    };
+
+    let result = if format == "json" {
+        writeln!(out, "{}", export.as_json(units))
+    } else {
+        export.write_csv(&mut out, units)
+    };
+
+    if let Err(e) = result {
+        fail(&e.to_string());
+    }
+}
@@ -0,0 +1,98 @@
+extern crate getopts;
+
+extern crate pibq;
+
+use getopts::Options;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use pibq::config::Config;
+use pibq::import::{self, ImportOptions};
+use pibq::models::TemperatureUnit;
+use pibq::sql;
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options] FILE", program);
+    print!("{}", opts.usage(&brief));
+}
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", msg);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("c", "config", &format!("config file (default {})", pibq::config::DEFAULT_PATH), "FILE");
+    opts.optopt("d", "dbfile", "sqlite DB file", "FILE");
+    opts.optopt("n", "name", "project name (default the file name)", "NAME");
+    opts.optopt("s", "serial", "serial number to record the readings against (default \"imported\")", "SERIAL");
+    opts.optopt("u", "units", "C or F, for probe columns that don't say (default C)", "UNITS");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            println!("{}", f.to_string());
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        print_usage(&program, opts);
+        return;
+    }
+
+    let mut config = match Config::load(matches.opt_str("c").as_ref().map(|s| s.as_str())) {
+        Ok(c) => c,
+        Err(e) => fail(&e.to_string())
+    };
+    if let Some(v) = matches.opt_str("d") { config.database.path = v; }
+
+    let path = &matches.free[0];
+    let name = matches.opt_str("n").unwrap_or_else(|| {
+        Path::new(path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or(path.clone())
+    });
+
+    let mut options = ImportOptions::new(&name);
+    if let Some(v) = matches.opt_str("s") { options.device_serial = v; }
+    if let Some(v) = matches.opt_str("u") {
+        options.units = match TemperatureUnit::from_str(&v) {
+            Some(u) => u,
+            None => fail("units must be C or F")
+        };
+    }
+
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => fail(&format!("unable to read {}: {}", path, e))
+    };
+
+    let conn = match sql::get_connection(&config.database.path, false) {
+        Ok(c) => c,
+        Err(e) => fail(&format!("unable to open {}: {}", config.database.path, e))
+    };
+
+    let report = match import::import_csv(&conn, &mut file, &options) {
+        Ok(r) => r,
+        Err(e) => fail(&e.to_string())
+    };
+
+    for &(line, ref msg) in report.errors.iter() {
+        println!("line {}: skipped, {}", line, msg);
+    }
+    for &(line, at) in report.conflicts.iter() {
+        println!("line {}: skipped, {} already has a reading at {}", line, options.device_serial, at.to_rfc3339());
+    }
+
+    match report.project {
+        Some(ref p) => println!("Imported {} readings and {} notes into project {} \"{}\"", report.imported, report.annotations, p.id, p.name),
+        None => println!("No new readings; no project created")
+    }
+    println!("{} conflicting rows, {} invalid rows", report.conflicts.len(), report.errors.len());
+}
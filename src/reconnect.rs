@@ -0,0 +1,83 @@
+use std::cmp;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How a connection is rebuilt after it fails
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    // consecutive errors tolerated before the connection is torn down and rebuilt
+    pub max_errors: u32,
+    // delay before the first reconnect attempt, in ms; doubles with each failed attempt
+    pub initial_backoff: u64,
+    // upper bound on the reconnect delay, in ms
+    pub max_backoff: u64,
+    // fraction of the delay (0 - 1) that is randomly added or removed, so several harvesters don't retry in lockstep
+    pub jitter: f64,
+    // consecutive failed reconnects after which to give up entirely; None retries forever
+    pub give_up_after: Option<u32>
+}
+
+impl ReconnectPolicy {
+    pub fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_errors: 3,
+            initial_backoff: 1000,
+            max_backoff: 5 * 60 * 1000,
+            jitter: 0.1f64,
+            give_up_after: None
+        }
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            policy: self.clone(),
+            attempts: 0
+        }
+    }
+}
+
+pub struct Backoff {
+    policy: ReconnectPolicy,
+    attempts: u32
+}
+
+impl Backoff {
+    // Delay before the next attempt, or None once the policy says to give up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        match self.policy.give_up_after {
+            Some(limit) if self.attempts >= limit => return None,
+            _ => {}
+        }
+
+        let exponent = cmp::min(self.attempts, 32);
+        let base = cmp::min(self.policy.initial_backoff.saturating_mul(1u64 << exponent), self.policy.max_backoff);
+        self.attempts = self.attempts + 1;
+
+        let jitter = (base as f64) * self.policy.jitter * (random_unit() * 2f64 - 1f64);
+        let delay = ((base as f64) + jitter).max(0f64) as u64;
+
+        Some(Duration::from_millis(delay))
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+// A number in [0, 1); only needs to spread retries out, not be unpredictable
+fn random_unit() -> f64 {
+    let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.subsec_nanos() as u64,
+        Err(_) => 0
+    };
+
+    let mut x = nanos ^ 0x9E3779B97F4A7C15u64;
+    x = x ^ (x << 13);
+    x = x ^ (x >> 7);
+    x = x ^ (x << 17);
+
+    ((x % 1_000_000) as f64) / 1_000_000f64
+}
@@ -0,0 +1,105 @@
+// Scripted temperature curves for the simulator.
+//
+// A script is a comma separated list starting with the initial temperature (C), followed by segments:
+//
+//   ramp:<target>:<secs>   move linearly to target over secs
+//   hold:<secs>            plateau (e.g. a stall) at the current temperature
+//   dip:<depth>:<secs>     drop by depth then recover, like opening the lid
+//   unplug:<secs>          probe reports no reading
+//
+// After the last segment the curve holds its final temperature.
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Ramp(f64, f64),
+    Hold(f64),
+    Dip(f64, f64),
+    Unplug(f64)
+}
+
+impl Segment {
+    fn duration(&self) -> f64 {
+        match self {
+            &Segment::Ramp(_, secs) => secs,
+            &Segment::Hold(secs) => secs,
+            &Segment::Dip(_, secs) => secs,
+            &Segment::Unplug(secs) => secs
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Curve {
+    start: f64,
+    segments: Vec<Segment>
+}
+
+impl Curve {
+    pub fn parse(script: &str) -> Result<Curve, String> {
+        let mut parts = script.split(',').map(|s| s.trim());
+
+        let start = match parts.next() {
+            Some(s) => try!(parse_number(s)),
+            None => return Err("empty curve script".to_string())
+        };
+
+        let mut segments = vec![];
+
+        for part in parts {
+            let fields: Vec<&str> = part.split(':').collect();
+            let segment = match (fields[0], fields.len()) {
+                ("ramp", 3) => Segment::Ramp(try!(parse_number(fields[1])), try!(parse_number(fields[2]))),
+                ("hold", 2) => Segment::Hold(try!(parse_number(fields[1]))),
+                ("dip", 3) => Segment::Dip(try!(parse_number(fields[1])), try!(parse_number(fields[2]))),
+                ("unplug", 2) => Segment::Unplug(try!(parse_number(fields[1]))),
+                _ => return Err(format!("invalid curve segment: {}", part))
+            };
+            segments.push(segment);
+        }
+
+        Ok(Curve { start: start, segments: segments })
+    }
+
+    // Temperature at the given number of seconds into the script
+    pub fn value_at(&self, elapsed: f64) -> Option<f64> {
+        let mut current = self.start;
+        let mut offset = elapsed;
+
+        for segment in &self.segments {
+            let duration = segment.duration();
+            let fraction = if duration > 0f64 { offset / duration } else { 1f64 };
+
+            if fraction < 1f64 {
+                return match segment {
+                    &Segment::Ramp(target, _) => Some(current + (target - current) * fraction),
+                    &Segment::Hold(_) => Some(current),
+                    &Segment::Dip(depth, _) => Some(current - depth * dip_shape(fraction)),
+                    &Segment::Unplug(_) => None
+                };
+            }
+
+            if let &Segment::Ramp(target, _) = segment {
+                current = target;
+            }
+            offset = offset - duration;
+        }
+
+        Some(current)
+    }
+}
+
+// Fast drop over the first fifth of the dip, then a slower recovery
+fn dip_shape(fraction: f64) -> f64 {
+    if fraction < 0.2f64 {
+        fraction / 0.2f64
+    } else {
+        1f64 - (fraction - 0.2f64) / 0.8f64
+    }
+}
+
+fn parse_number(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("invalid number '{}': {}", s, e))
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::Instant;
+
+use bluetherm::{data_flags, message_type, Frame, Framer, Packet, TYPES_FAHRENHEIT};
+use bluetherm::transport::Transport;
+use super::curve::Curve;
+use super::faults::{Fault, FaultPlan};
+
+// Bytes sent for a truncated frame
+const TRUNCATED_LENGTH: usize = 60;
+
+pub struct SimDevice {
+    pub serial_number: String,
+    pub names: [String; 2],
+    pub high_limits: [Option<f64>; 2],
+    pub low_limits: [Option<f64>; 2],
+    pub trims: [f64; 2],
+    // report temperatures in Fahrenheit; the curves are always in Celsius
+    pub fahrenheit: bool,
+    curves: [Curve; 2],
+    faults: FaultPlan,
+    speed: f64,
+    started: Instant
+}
+
+impl SimDevice {
+    pub fn new(serial_number: &str, curves: [Curve; 2], faults: FaultPlan, speed: f64) -> SimDevice {
+        SimDevice {
+            serial_number: serial_number.to_string(),
+            names: ["Probe 1".to_string(), "Probe 2".to_string()],
+            high_limits: [None, None],
+            low_limits: [None, None],
+            trims: [0f64, 0f64],
+            fahrenheit: false,
+            curves: curves,
+            faults: faults,
+            speed: speed,
+            started: Instant::now()
+        }
+    }
+
+    // Simulated seconds since the device was started
+    fn elapsed(&self) -> f64 {
+        let e = self.started.elapsed();
+        (e.as_secs() as f64 + e.subsec_nanos() as f64 / 1_000_000_000f64) * self.speed
+    }
+
+    fn temperature(&self, sensor: usize) -> Option<f64> {
+        let fahrenheit = self.fahrenheit;
+        self.curves[sensor].value_at(self.elapsed())
+            .map(|t| t + self.trims[sensor])
+            .map(|t| if fahrenheit { t * 1.8 + 32.0 } else { t })
+    }
+
+    // Builds the bytes to send in reply to a request; None means stay silent
+    pub fn respond(&mut self, request: &Packet) -> Option<Vec<u8>> {
+        if !request.is_checksum_valid() {
+            println!("ignoring request with bad checksum");
+            return None;
+        }
+
+        let command = request.get_command_id();
+        let response = if command == message_type::RETRIEVE_INFO {
+            self.retrieve_info(request)
+        } else if command == message_type::SET_INFO {
+            self.set_info(request)
+        } else {
+            println!("ignoring unsupported command {}", command);
+            return None;
+        };
+
+        match self.faults.next() {
+            None => Some(response.data.to_vec()),
+            Some(Fault::BadCrc) => {
+                println!("fault: bad crc");
+                let mut data = response.data.to_vec();
+                data[0x7E] = data[0x7E] ^ 0xFF;
+                Some(data)
+            },
+            Some(Fault::Truncated) => {
+                println!("fault: truncated frame");
+                Some(response.data[0 .. TRUNCATED_LENGTH].to_vec())
+            },
+            Some(Fault::Silence) => {
+                println!("fault: silence");
+                None
+            }
+        }
+    }
+
+    fn retrieve_info(&self, request: &Packet) -> Packet {
+        let mut p = Packet::new();
+        p.set_command_id(message_type::RETRIEVE_INFO);
+        p.set_version(1);
+        p.set_data_flags(request.get_data_flags());
+        p.set_serial_number(&self.serial_number);
+        p.set_sensor1_name(&self.names[0]);
+        p.set_sensor2_name(&self.names[1]);
+        p.set_sensor1_reading(self.temperature(0));
+        p.set_sensor2_reading(self.temperature(1));
+        p.set_sensor1_high_limit(self.high_limits[0]);
+        p.set_sensor1_low_limit(self.low_limits[0]);
+        p.set_sensor1_trim(self.trims[0]);
+        p.set_sensor2_high_limit(self.high_limits[1]);
+        p.set_sensor2_low_limit(self.low_limits[1]);
+        p.set_sensor2_trim(self.trims[1]);
+        p.set_battery_volts(3.0f32);
+        p.set_firmware_version(0x0100);
+        p.set_types(if self.fahrenheit { TYPES_FAHRENHEIT } else { 0 });
+        p.apply_checksum();
+        p
+    }
+
+    fn set_info(&mut self, request: &Packet) -> Packet {
+        let info = request.decode();
+
+        if let Some(name) = info.sensor1.name { self.names[0] = name; }
+        if let Some(name) = info.sensor2.name { self.names[1] = name; }
+
+        if request.get_data_flags().contains(data_flags::SENSOR_1_HIGH_LIMIT) { self.high_limits[0] = info.sensor1.high_limit; }
+        if request.get_data_flags().contains(data_flags::SENSOR_1_LOW_LIMIT) { self.low_limits[0] = info.sensor1.low_limit; }
+        if request.get_data_flags().contains(data_flags::SENSOR_2_HIGH_LIMIT) { self.high_limits[1] = info.sensor2.high_limit; }
+        if request.get_data_flags().contains(data_flags::SENSOR_2_LOW_LIMIT) { self.low_limits[1] = info.sensor2.low_limit; }
+        if let Some(trim) = info.sensor1.trim { self.trims[0] = trim; }
+        if let Some(trim) = info.sensor2.trim { self.trims[1] = trim; }
+
+        let mut p = Packet::new();
+        p.set_command_id(message_type::SET_INFO);
+        p.set_version(1);
+        p.set_data_flags(request.get_data_flags());
+        p.apply_checksum();
+        p
+    }
+}
+
+// Answers requests on the transport until it fails
+pub fn serve<T: Transport>(transport: &mut T, device: &mut SimDevice) -> ::std::io::Result<()> {
+    let mut framer = Framer::new();
+    let mut read_buffer = [0u8; 128];
+
+    loop {
+        match transport.read(&mut read_buffer) {
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::TimedOut => {},
+            Err(e) => return Err(e),
+            Ok(bytes) => { framer.push(&read_buffer[0 .. bytes]); }
+        }
+
+        while let Some(frame) = framer.next_frame() {
+            match frame {
+                Frame::Packet(request) => {
+                    match device.respond(&request) {
+                        Some(response) => { try!(transport.write_all(&response)); },
+                        None => {}
+                    }
+                },
+                Frame::Discarded(bytes) => { println!("discarded {} bytes of request data", bytes.len()); }
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// Injectable faults, each triggered on every Nth response.
+// Spec format: "crc:10,truncate:25,silence:40"
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    BadCrc,
+    Truncated,
+    Silence
+}
+
+pub struct FaultPlan {
+    rules: Vec<(Fault, u64)>,
+    count: u64
+}
+
+impl FaultPlan {
+    pub fn parse(spec: &str) -> Result<FaultPlan, String> {
+        let mut rules = vec![];
+
+        for part in spec.split(',').map(|s| s.trim()).filter(|s| s.len() > 0) {
+            let fields: Vec<&str> = part.split(':').collect();
+            if fields.len() != 2 {
+                return Err(format!("invalid fault: {}", part));
+            }
+
+            let fault = match fields[0] {
+                "crc" => Fault::BadCrc,
+                "truncate" => Fault::Truncated,
+                "silence" => Fault::Silence,
+                f => return Err(format!("unknown fault: {}", f))
+            };
+
+            let every = match fields[1].parse::<u64>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(format!("invalid fault interval: {}", fields[1]))
+            };
+
+            rules.push((fault, every));
+        }
+
+        Ok(FaultPlan { rules: rules, count: 0 })
+    }
+
+    // Fault to apply to the next response, if any
+    pub fn next(&mut self) -> Option<Fault> {
+        self.count = self.count + 1;
+
+        for &(fault, every) in &self.rules {
+            if self.count % every == 0 {
+                return Some(fault);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,4 @@
+pub mod curve;
+pub mod device;
+pub mod faults;
+pub mod pty;
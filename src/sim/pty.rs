@@ -0,0 +1,103 @@
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+use libc;
+use bluetherm::transport::Transport;
+
+// Master side of a pseudo-terminal; the slave path can be handed to the harvester as a tty
+pub struct PtyTransport {
+    master: libc::c_int,
+    slave: libc::c_int,
+    pub slave_path: String,
+    timeout: Duration
+}
+
+fn last_error<T>() -> io::Result<T> {
+    Err(io::Error::last_os_error())
+}
+
+// The error from the call that just failed, once the descriptors opened so far are closed
+unsafe fn close_on_error<T>(fds: &[libc::c_int]) -> io::Result<T> {
+    let err = io::Error::last_os_error();
+    for &fd in fds {
+        libc::close(fd);
+    }
+    Err(err)
+}
+
+impl PtyTransport {
+    pub fn open() -> io::Result<PtyTransport> {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if master < 0 { return last_error(); }
+            if libc::grantpt(master) != 0 { return close_on_error(&[master]); }
+            if libc::unlockpt(master) != 0 { return close_on_error(&[master]); }
+
+            let name = libc::ptsname(master);
+            if name.is_null() { return close_on_error(&[master]); }
+            let slave_path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            // Hold the slave open in raw mode so nothing is echoed back to us and
+            // reads on the master don't fail while no client is attached
+            let slave = libc::open(name, libc::O_RDWR | libc::O_NOCTTY);
+            if slave < 0 { return close_on_error(&[master]); }
+
+            let mut attrs: libc::termios = mem::zeroed();
+            if libc::tcgetattr(slave, &mut attrs) != 0 { return close_on_error(&[slave, master]); }
+            libc::cfmakeraw(&mut attrs);
+            if libc::tcsetattr(slave, libc::TCSANOW, &attrs) != 0 { return close_on_error(&[slave, master]); }
+
+            Ok(PtyTransport {
+                master: master,
+                slave: slave,
+                slave_path: slave_path,
+                timeout: Duration::from_millis(1000)
+            })
+        }
+    }
+}
+
+impl Transport for PtyTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout_ms = self.timeout.as_secs() * 1000 + (self.timeout.subsec_nanos() / 1_000_000) as u64;
+        let mut fds = libc::pollfd { fd: self.master, events: libc::POLLIN, revents: 0 };
+
+        unsafe {
+            match libc::poll(&mut fds, 1, timeout_ms as libc::c_int) {
+                r if r < 0 => last_error(),
+                0 => Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out")),
+                _ => {
+                    let count = libc::read(self.master, buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+                    if count < 0 { last_error() } else { Ok(count as usize) }
+                }
+            }
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut written = 0;
+        while written < buf.len() {
+            let remaining = &buf[written ..];
+            let count = unsafe { libc::write(self.master, remaining.as_ptr() as *const libc::c_void, remaining.len()) };
+            if count < 0 { return last_error(); }
+            written = written + count as usize;
+        }
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+impl Drop for PtyTransport {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.slave);
+            libc::close(self.master);
+        }
+    }
+}
@@ -1,75 +1,191 @@
-use std::cmp::Ordering;
-use std::fs::{self, File};
-use std::io::{self, Read};
-use std::path::Path;
 use rusqlite::{self, Connection};
-
-struct Version {
-    path: String,
-    version: u32
+use std::error::Error;
+use std::fmt;
+
+// A schema change, compiled into the binary from the migrations directory
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str
 }
 
-impl PartialEq for Version {
-    fn eq(&self, other: &Version) -> bool {
-        self.version == other.version
+impl Migration {
+    // Recorded when the migration is applied, so a later edit to an applied migration is noticed
+    pub fn checksum(&self) -> String {
+        checksum(self.sql)
     }
 }
 
-impl Eq for Version {}
+macro_rules! migration {
+    ($version:expr, $name:expr) => (
+        Migration { version: $version, name: $name, sql: include_str!(concat!("../../migrations/", $name)) }
+    )
+}
+
+// In version order; add new migrations to the end
+pub static MIGRATIONS: &'static [Migration] = &[
+    migration!(1, "001__readings.sql"),
+    migration!(2, "002__connection_statuses.sql"),
+    migration!(3, "003__projects.sql"),
+    migration!(4, "004__devices.sql"),
+    migration!(5, "005__probes.sql"),
+    migration!(6, "006__utc_timestamps.sql"),
+    migration!(7, "007__rollups.sql"),
+    migration!(8, "008__alarms.sql"),
+    migration!(9, "009__notifiers.sql"),
+    migration!(10, "010__project_events.sql"),
+    migration!(11, "011__annotations.sql"),
+    migration!(12, "012__project_units.sql")
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Db(rusqlite::Error),
+    // the migration's SQL failed; nothing of it was kept
+    Failed(u32, rusqlite::Error),
+    // an applied migration has been edited since
+    Modified(u32),
+    // the database has a migration this binary doesn't, so it's from a newer build
+    Unknown(u32)
+}
 
-impl PartialOrd for Version {
-    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
-        self.version.partial_cmp(&other.version)
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &MigrationError::Db(ref err) => write!(f, "database error: {}", err),
+            &MigrationError::Failed(version, ref err) => write!(f, "migration {} failed and was rolled back: {}", version, err),
+            &MigrationError::Modified(version) => write!(f, "migration {} has changed since it was applied", version),
+            &MigrationError::Unknown(version) => write!(f, "the database has migration {}, which this build doesn't know", version)
+        }
     }
+}
 
+impl Error for MigrationError {
+    fn description(&self) -> &str {
+        match self {
+            &MigrationError::Db(ref err) => err.description(),
+            &MigrationError::Failed(_, _) => "migration failed",
+            &MigrationError::Modified(_) => "migration modified",
+            &MigrationError::Unknown(_) => "unknown migration"
+        }
+    }
 }
 
-impl Ord for Version {
-    fn cmp(&self, other: &Version) -> Ordering {
-        self.version.cmp(&other.version)
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> MigrationError {
+        MigrationError::Db(err)
     }
 }
 
-pub fn perform_migration(conn: &Connection, migrate_directory: &str) -> rusqlite::Result<()> {
+// A migration and whether it has been applied; name is None for a version only the database knows
+pub struct MigrationState {
+    pub version: u32,
+    pub name: Option<&'static str>,
+    pub applied: bool,
+    // applied, but the migration has changed since
+    pub modified: bool
+}
+
+// Checks the applied migrations against the ones compiled in, then applies the rest.
+pub fn perform_migration(conn: &Connection) -> Result<(), MigrationError> {
+    apply_migrations(conn, MIGRATIONS)
+}
 
-    try!(ensure_version_table(conn));
+// Like perform_migration, for a given list in version order.
+// Each runs in its own immediate transaction with its version row, so a failure leaves the schema as it was
+// and two processes starting together don't both apply it.
+pub fn apply_migrations(conn: &Connection, migrations: &[Migration]) -> Result<(), MigrationError> {
+    try!(in_transaction(conn, |conn| ensure_version_table(conn, migrations).map_err(MigrationError::Db)));
 
-    let all_versions = match get_migration_versions(migrate_directory) {
-        Ok(v) => v,
-        Err(e) => return Err(rusqlite::Error::InvalidParameterName(e.to_string()))
-    };
+    for state in try!(migration_states(conn, migrations)) {
+        if state.name.is_none() {
+            return Err(MigrationError::Unknown(state.version));
+        }
+        if state.modified {
+            return Err(MigrationError::Modified(state.version));
+        }
+    }
+
+    for m in migrations.iter() {
+        try!(in_transaction(conn, |conn| {
+            let applied: i64 = try!(conn.query_row("SELECT COUNT(*) FROM schema_migrations WHERE version = $1",
+                                                   &[&(m.version as i64)], |row| row.get(0)));
+            if applied > 0 {
+                return Ok(());
+            }
+
+            try!(conn.execute_batch(m.sql).map_err(|e| MigrationError::Failed(m.version, e)));
+            try!(conn.execute("INSERT INTO schema_migrations (version, checksum) VALUES ($1, $2)",
+                              &[&(m.version as i64), &m.checksum()]));
+            Ok(())
+        }));
+    }
+
+    Ok(())
+}
 
-    let installed_versions = try!(get_existing_versions(conn));
+// Every compiled-in migration and any others the database has, in version order. Doesn't change the database.
+pub fn get_migration_states(conn: &Connection) -> rusqlite::Result<Vec<MigrationState>> {
+    migration_states(conn, MIGRATIONS)
+}
 
-    let mut missing_versions = vec![];
+fn migration_states(conn: &Connection, migrations: &[Migration]) -> rusqlite::Result<Vec<MigrationState>> {
+    let installed = try!(get_existing_versions(conn));
+    let mut states = vec![];
+
+    for m in migrations.iter() {
+        let row = installed.iter().find(|&&(v, _)| v == m.version);
+        states.push(MigrationState {
+            version: m.version,
+            name: Some(m.name),
+            applied: row.is_some(),
+            modified: match row {
+                Some(&(_, Some(ref c))) => *c != m.checksum(),
+                _ => false
+            }
+        });
+    }
 
-    for v in all_versions {
-        match installed_versions.contains(&v) {
-            true => {},
-            false => missing_versions.push(v)
+    for &(version, _) in installed.iter() {
+        if !migrations.iter().any(|m| m.version == version) {
+            states.push(MigrationState { version: version, name: None, applied: true, modified: false });
         }
     }
 
-    for v in missing_versions {
-        let sql = match get_file_contents(&v.path) {
-            Ok(s) => s,
-            Err(e) => return Err(rusqlite::Error::InvalidParameterName(e.to_string()))
-        };
+    states.sort_by_key(|s| s.version);
+    Ok(states)
+}
 
-        try!(conn.execute_batch(&sql));
-        try!(conn.execute("INSERT INTO schema_migrations (version) VALUES ($1)", &[&(v.version as i64)]));
+fn in_transaction<F>(conn: &Connection, f: F) -> Result<(), MigrationError> where F: FnOnce(&Connection) -> Result<(), MigrationError> {
+    try!(conn.execute_batch("BEGIN IMMEDIATE"));
+
+    match f(conn) {
+        Ok(_) => {
+            try!(conn.execute_batch("COMMIT"));
+            Ok(())
+        },
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            Err(e)
+        }
     }
-
-    Ok(())
 }
 
-fn get_existing_versions(conn: &Connection) -> rusqlite::Result<Vec<Version>> {
-    let mut stmt = try!(conn.prepare("SELECT version FROM schema_migrations"));
+// Applied versions and their checksums; empty if nothing has been migrated yet
+fn get_existing_versions(conn: &Connection) -> rusqlite::Result<Vec<(u32, Option<String>)>> {
+    let columns = try!(get_version_columns(conn));
+    if columns.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let sql = match columns.iter().any(|c| c == "checksum") {
+        true => "SELECT version, checksum FROM schema_migrations ORDER BY version",
+        false => "SELECT version, NULL FROM schema_migrations ORDER BY version"
+    };
+
+    let mut stmt = try!(conn.prepare(sql));
     let version_itr = try!(stmt.query_map(&[], |row| {
-        Version {
-            path: "".to_string(),
-            version: row.get::<i32, i64>(0) as u32
-        }
+        (row.get::<i32, i64>(0) as u32, row.get::<i32, Option<String>>(1))
     }));
 
     let mut versions = vec![];
@@ -78,83 +194,67 @@ fn get_existing_versions(conn: &Connection) -> rusqlite::Result<Vec<Version>> {
         versions.push(try!(v));
     }
 
-    versions.sort();
-
     Ok(versions)
 }
 
-fn get_file_contents(path: &str) -> io::Result<String> {
-    let mut f = try!(File::open(&path));
-    let mut contents = String::new();
-    try!(f.read_to_string(&mut contents));
-    Ok(contents)
+fn get_version_columns(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = try!(conn.prepare("PRAGMA table_info(schema_migrations)"));
+    let column_itr = try!(stmt.query_map(&[], |row| row.get::<i32, String>(1)));
+
+    let mut columns = vec![];
+
+    for c in column_itr {
+        columns.push(try!(c));
+    }
+
+    Ok(columns)
 }
 
-fn get_migration_versions(migrations_dir: &str) -> io::Result<Vec<Version>> {
+const CREATE_VERSION_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER NOT NULL, checksum TEXT)";
 
-    let all_paths = try!(fs::read_dir(migrations_dir));
+// Databases migrated before checksums were recorded get the column, and their applied migrations are
+// taken to be the ones compiled in
+fn ensure_version_table(conn: &Connection, migrations: &[Migration]) -> rusqlite::Result<()> {
+    try!(conn.execute(CREATE_VERSION_TABLE, &[]));
 
+    if !try!(get_version_columns(conn)).iter().any(|c| c == "checksum") {
+        try!(conn.execute("ALTER TABLE schema_migrations ADD COLUMN checksum TEXT", &[]));
+    }
 
-    let sql_files = all_paths.filter_map(|dirent| dirent.ok())
-                             .map(|dirent| dirent.path())
-                             .filter(|path| {
-                                 match path.extension() {
-                                     None => false,
-                                     Some(s) => s == "sql",
-                                 }
-                             });
+    for m in migrations.iter() {
+        try!(conn.execute("UPDATE schema_migrations SET checksum = $1 WHERE version = $2 AND checksum IS NULL",
+                          &[&m.checksum(), &(m.version as i64)]));
+    }
 
-     let mut res = vec![];
-     for file in sql_files {
-         let version = try!(calculate_version(&file));
-         let path = match file.to_str() {
-             Some(p) => p,
-             None => return Err(io::Error::new(io::ErrorKind::Other, "Cant read file")),
-         };
-         res.push(Version {
-             path: path.to_string(),
-             version: version,
-         });
-     }
-     res.sort();
-     Ok(res)
+    Ok(())
 }
 
-fn calculate_version(path: &Path) -> io::Result<u32> {
-    let file_name = match path.file_stem() {
-        Some(s) => s,
-        None => return Err(io::Error::new(io::ErrorKind::Other, "Could not determine filename")),
-    };
+// 64-bit FNV-1a, as hex. Only needs to notice edits, not resist them.
+fn checksum(s: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
 
-    match file_name.to_str() {
-        None => Err(io::Error::new(io::ErrorKind::Other, "Could not get string path")),
-        Some(s) => {
-            let mut parts = s.split("__");
-            let version = match parts.next() {
-                Some(s) => s,
-                None => return Err(io::Error::new(io::ErrorKind::Other, "Invalid filename")),
-            };
-
-            let version = match version.parse::<u32>() {
-                Ok(v) => v,
-                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("Invalid filename: {}", e)))
-            };
-
-            Ok(version)
-        }
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
     }
+
+    format!("{:016x}", hash)
 }
 
-const CREATE_VERSION_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER NOT NULL)";
+#[test]
+fn test_migration_versions() {
+    let mut last = 0;
 
-fn ensure_version_table(conn: &Connection) -> rusqlite::Result<()> {
-    try!(conn.execute(CREATE_VERSION_TABLE, &[]));
-    Ok(())
+    for m in MIGRATIONS.iter() {
+        assert!(m.version > last);
+        assert_eq!(m.version, m.name.split("__").next().unwrap().parse::<u32>().unwrap());
+        last = m.version;
+    }
 }
 
 #[test]
-fn test_calculate_version() {
-    assert_eq!(1u32, calculate_version(&Path::new("some/thing/001__hi_there.sql")).unwrap());
-    assert_eq!(45u32, calculate_version(&Path::new("some/thing/045__hi_there.sql")).unwrap());
-    assert_eq!(101u32, calculate_version(&Path::new("some/thing/101__hi_there.sql")).unwrap());
+fn test_checksum() {
+    assert_eq!("cbf29ce484222325", checksum(""));
+    assert_eq!("af63dc4c8601ec8c", checksum("a"));
+    assert!(checksum("CREATE TABLE a (id INTEGER)") != checksum("CREATE TABLE a (id  INTEGER)"));
 }
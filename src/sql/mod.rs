@@ -1,9 +1,11 @@
-mod migrations;
+pub mod migrations;
 pub mod pool;
 
 use chrono::datetime::DateTime;
-//use chrono::offset::TimeZone;
-use chrono::offset::local::Local;
+use chrono::offset::TimeZone;
+use chrono::offset::utc::UTC;
+use chrono::Timelike;
+use std::cmp::{max, min};
 use std::path::Path;
 use std::time::Duration;
 use r2d2;
@@ -11,25 +13,119 @@ use rusqlite::{self, Connection, SQLITE_OPEN_CREATE, SQLITE_OPEN_READ_WRITE};
 
 use super::models;
 
+// How long a connection waits on a lock held by another process (harvesters and web server share the DB), in ms
+const BUSY_TIMEOUT: u32 = 5000;
+
+pub fn set_busy_timeout(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!("PRAGMA busy_timeout = {};", BUSY_TIMEOUT))
+}
+
+// Runs f in a transaction, committed if it succeeds and rolled back if it fails. rusqlite's own Transaction
+// needs a &mut Connection, which the shared and pooled connections here can't give.
+pub fn in_transaction<T, E, F>(conn: &Connection, f: F) -> Result<T, E>
+    where F: FnOnce(&Connection) -> Result<T, E>, E: From<rusqlite::Error> {
+    try!(conn.execute_batch("BEGIN"));
+
+    let result = f(conn).and_then(|v| conn.execute_batch("COMMIT").map(|_| v).map_err(E::from));
+
+    if result.is_err() {
+        let _ = conn.execute_batch("ROLLBACK");
+    }
+
+    result
+}
+
+// Times are stored as UTC milliseconds since the epoch
+fn to_epoch_ms(dt: &DateTime<UTC>) -> i64 {
+    dt.timestamp() * 1000 + (dt.nanosecond() / 1_000_000) as i64
+}
+
+// Rounds down, so times before the epoch keep a positive millisecond part
+fn from_epoch_ms(ms: i64) -> DateTime<UTC> {
+    let secs = if ms < 0 && ms % 1000 != 0 { ms / 1000 - 1 } else { ms / 1000 };
+    UTC.timestamp(secs, ((ms - secs * 1000) * 1_000_000) as u32)
+}
+
+fn device_from_row(row: &rusqlite::Row) -> models::Device {
+    models::Device {
+        id: row.get(0),
+        serial_number: row.get(1),
+        path: row.get(2),
+        created_at: from_epoch_ms(row.get(3))
+    }
+}
+
+pub fn get_devices(conn: &Connection) -> rusqlite::Result<Vec<models::Device>> {
+    let mut stmt = try!(conn.prepare("SELECT id, serial_number, path, created_at FROM devices ORDER BY id"));
+    let device_iter = try!(stmt.query_map(&[], device_from_row));
+
+    let mut result = vec![];
+
+    for device_row in device_iter {
+        result.push(try!(device_row));
+    }
+
+    Ok(result)
+}
+
+// The device most recently seen on the given tty path / tcp spec
+pub fn get_device_by_path(conn: &Connection, path: &str) -> rusqlite::Result<Option<models::Device>> {
+    let sql = "SELECT id, serial_number, path, created_at FROM devices WHERE path = $1 ORDER BY id DESC LIMIT 1";
+    let result = conn.query_row(sql, &[&path], device_from_row);
+
+    match result {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Ok(d) => Ok(Some(d)),
+        Err(e) => Err(e)
+    }
+}
+
+// Looks up a device by serial number, creating it if it's new, and records the path it was seen on
+pub fn find_or_create_device(conn: &Connection, serial_number: &str, path: &str) -> rusqlite::Result<models::Device> {
+    let sql = "SELECT id, serial_number, path, created_at FROM devices WHERE serial_number = $1";
+    let result = conn.query_row(sql, &[&serial_number], device_from_row);
+
+    match result {
+        Ok(mut d) => {
+            if d.path.as_ref().map(|p| p.as_str()) != Some(path) {
+                try!(conn.execute("UPDATE devices SET path = $1 WHERE id = $2", &[&path, &d.id]));
+                d.path = Some(path.to_string());
+            }
+            Ok(d)
+        },
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            let mut d = models::Device::new(serial_number, Some(path.to_string()));
+            try!(conn.execute("INSERT INTO devices (serial_number, path, created_at) VALUES ($1, $2, $3)",
+                         &[&d.serial_number, &d.path, &to_epoch_ms(&d.created_at)]));
+            d.id = conn.last_insert_rowid();
+            Ok(d)
+        },
+        Err(e) => Err(e)
+    }
+}
+
+fn connection_status_from_row(row: &rusqlite::Row) -> models::ConnectionStatus {
+    models::ConnectionStatus {
+        id: row.get(0),
+        device_id: row.get(1),
+        is_connect: row.get(2),
+        is_disconnect: row.get(3),
+        info: row.get(4),
+        created_at: from_epoch_ms(row.get(5))
+    }
+}
+
 pub fn insert_connection_status(conn: &Connection, status: &mut models::ConnectionStatus) -> rusqlite::Result<()> {
-    try!(conn.execute("INSERT INTO connection_statuses (is_connect, is_disconnect, info, created_at) VALUES ($1, $2, $3, $4)",
-                 &[&status.is_connect, &status.is_disconnect, &status.info, &status.created_at]));
+    try!(conn.execute("INSERT INTO connection_statuses (device_id, is_connect, is_disconnect, info, created_at) VALUES ($1, $2, $3, $4, $5)",
+                 &[&status.device_id, &status.is_connect, &status.is_disconnect, &status.info, &to_epoch_ms(&status.created_at)]));
 
      status.id = conn.last_insert_rowid();
      Ok(())
 }
 
 pub fn get_latest_connection_status(conn: &Connection) -> rusqlite::Result<Option<models::ConnectionStatus>> {
-    let sql = "SELECT id, is_connect, is_disconnect, info, created_at FROM connection_statuses ORDER BY created_at DESC LIMIT 1";
-    let result = conn.query_row(sql, &[], |row| {
-        models::ConnectionStatus {
-            id: row.get(0),
-            is_connect: row.get(1),
-            is_disconnect: row.get(2),
-            info: row.get(3),
-            created_at: row.get(4)
-        }
-    });
+    let sql = "SELECT id, device_id, is_connect, is_disconnect, info, created_at FROM connection_statuses ORDER BY created_at DESC LIMIT 1";
+    let result = conn.query_row(sql, &[], connection_status_from_row);
 
     match result {
         Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
@@ -38,79 +134,470 @@ pub fn get_latest_connection_status(conn: &Connection) -> rusqlite::Result<Optio
     }
 }
 
+// A page of the connection history, like get_readings_between
+pub fn get_connection_statuses(conn: &Connection, from: Option<DateTime<UTC>>, to: Option<DateTime<UTC>>, after_id: i64, limit: u32) -> rusqlite::Result<Vec<models::ConnectionStatus>> {
+    let mut stmt = try!(conn.prepare("SELECT id, device_id, is_connect, is_disconnect, info, created_at FROM connection_statuses \
+                                      WHERE ($1 IS NULL OR created_at >= $1) AND ($2 IS NULL OR created_at < $2) AND id > $3 \
+                                      ORDER BY id LIMIT $4"));
+
+    let from = from.map(|dt| to_epoch_ms(&dt));
+    let to = to.map(|dt| to_epoch_ms(&dt));
+    let limit = limit as i64;
+
+    let status_iter = try!(stmt.query_map(&[&from, &to, &after_id, &limit], connection_status_from_row));
+
+    let mut result = vec![];
+
+    for status_row in status_iter {
+        result.push(try!(status_row));
+    }
+
+    Ok(result)
+}
+
+// The most recent status of each device
+pub fn get_latest_connection_statuses(conn: &Connection) -> rusqlite::Result<Vec<models::ConnectionStatus>> {
+    let mut stmt = try!(conn.prepare("SELECT id, device_id, is_connect, is_disconnect, info, created_at FROM connection_statuses s \
+                                      WHERE s.id = (SELECT s2.id FROM connection_statuses s2 WHERE s2.device_id IS s.device_id ORDER BY s2.created_at DESC LIMIT 1) \
+                                      ORDER BY device_id"));
+    let status_iter = try!(stmt.query_map(&[], connection_status_from_row));
+
+    let mut result = vec![];
+
+    for status_row in status_iter {
+        result.push(try!(status_row));
+    }
+
+    Ok(result)
+}
+
+fn probe_from_row(row: &rusqlite::Row) -> models::Probe {
+    models::Probe {
+        id: row.get(0),
+        device_id: row.get(1),
+        channel: row.get(2),
+        name: row.get(3)
+    }
+}
+
+pub fn get_probes(conn: &Connection) -> rusqlite::Result<Vec<models::Probe>> {
+    let mut stmt = try!(conn.prepare("SELECT id, device_id, channel, name FROM probes ORDER BY device_id, channel"));
+    let probe_iter = try!(stmt.query_map(&[], probe_from_row));
+
+    let mut result = vec![];
+
+    for probe_row in probe_iter {
+        result.push(try!(probe_row));
+    }
+
+    Ok(result)
+}
+
+// Looks up the probe on a device's channel, creating it with the given name if it's new
+pub fn find_or_create_probe(conn: &Connection, device_id: Option<i64>, channel: i64, name: &str) -> rusqlite::Result<models::Probe> {
+    let sql = "SELECT id, device_id, channel, name FROM probes WHERE device_id IS $1 AND channel = $2";
+    let result = conn.query_row(sql, &[&device_id, &channel], probe_from_row);
+
+    match result {
+        Ok(p) => Ok(p),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            let mut p = models::Probe::new(device_id, channel, name);
+            try!(conn.execute("INSERT INTO probes (device_id, channel, name) VALUES ($1, $2, $3)",
+                         &[&p.device_id, &p.channel, &p.name]));
+            p.id = conn.last_insert_rowid();
+            Ok(p)
+        },
+        Err(e) => Err(e)
+    }
+}
+
 pub fn insert_reading(conn: &Connection, reading: &mut models::Reading) -> rusqlite::Result<()> {
-    try!(conn.execute("INSERT INTO readings (value1, value2, timestamp) VALUES ($1, $2, $3)",
-                 &[&reading.value1, &reading.value2, &reading.timestamp]));
+    let id = try!(in_transaction(conn, |conn| -> rusqlite::Result<i64> {
+        try!(conn.execute("INSERT INTO readings (device_id, timestamp) VALUES ($1, $2)",
+                     &[&reading.device_id, &to_epoch_ms(&reading.timestamp)]));
+        let id = conn.last_insert_rowid();
+
+        for v in reading.values.iter() {
+            try!(conn.execute("INSERT INTO probe_readings (reading_id, probe_id, value) VALUES ($1, $2, $3)",
+                         &[&id, &v.probe_id, &v.value]));
+        }
 
-     reading.id = conn.last_insert_rowid();
-     Ok(())
+        Ok(id)
+    }));
+
+    reading.id = id;
+    Ok(())
 }
 
-pub fn get_project_readings(conn: &Connection, project: &models::Project, after: Option<DateTime<Local>>) -> rusqlite::Result<Vec<models::Reading>> {
-    let mut stmt = try!(conn.prepare("SELECT id, value1, value2, timestamp FROM readings WHERE timestamp > $1 AND timestamp < $2 ORDER BY timestamp"));
+// Like insert_reading, but without a transaction of its own so many can go in one, and a reading the
+// device already has at that time (per idx_readings_time) is left alone and false returned
+pub fn insert_reading_if_new(conn: &Connection, reading: &mut models::Reading) -> rusqlite::Result<bool> {
+    let changed = try!(conn.execute("INSERT OR IGNORE INTO readings (device_id, timestamp) VALUES ($1, $2)",
+                                    &[&reading.device_id, &to_epoch_ms(&reading.timestamp)]));
+
+    if changed == 0 {
+        return Ok(false);
+    }
+
+    let id = conn.last_insert_rowid();
+
+    for v in reading.values.iter() {
+        try!(conn.execute("INSERT INTO probe_readings (reading_id, probe_id, value) VALUES ($1, $2, $3)",
+                     &[&id, &v.probe_id, &v.value]));
+    }
+
+    reading.id = id;
+    Ok(true)
+}
+
+// Id of the newest reading, or 0 if there are none
+pub fn get_latest_reading_id(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COALESCE(MAX(id), 0) FROM readings", &[], |row| row.get(0))
+}
+
+// Gathers readings joined with their values (ordered by reading id) into one Reading each
+fn collect_readings(stmt: &mut rusqlite::Statement, params: &[&rusqlite::types::ToSql]) -> rusqlite::Result<Vec<models::Reading>> {
+    let row_iter = try!(stmt.query_map(params, |row| {
+        let reading = models::Reading {
+            id: row.get(0),
+            device_id: row.get(1),
+            values: vec![],
+            timestamp: from_epoch_ms(row.get(2))
+        };
+        let probe_id: Option<i64> = row.get(3);
+        let value = probe_id.map(|id| models::ProbeValue { probe_id: id, value: row.get(4) });
+        (reading, value)
+    }));
+
+    let mut result: Vec<models::Reading> = vec![];
+
+    for row in row_iter {
+        let (reading, value) = try!(row);
+
+        let is_new = result.last().map(|r| r.id != reading.id).unwrap_or(true);
+        if is_new {
+            result.push(reading);
+        }
+
+        if let Some(v) = value {
+            result.last_mut().unwrap().values.push(v);
+        }
+    }
+
+    Ok(result)
+}
+
+// Up to limit readings after the given id, in id order
+pub fn get_readings_after(conn: &Connection, reading_id: i64, limit: u32) -> rusqlite::Result<Vec<models::Reading>> {
+    let mut stmt = try!(conn.prepare("SELECT r.id, r.device_id, r.timestamp, pr.probe_id, pr.value FROM readings r \
+                                      LEFT JOIN probe_readings pr ON pr.reading_id = r.id \
+                                      WHERE r.id IN (SELECT id FROM readings WHERE id > $1 ORDER BY id LIMIT $2) \
+                                      ORDER BY r.id, pr.probe_id"));
+
+    collect_readings(&mut stmt, &[&reading_id, &(limit as i64)])
+}
+
+// Like get_readings_after, but only readings within the project's window that have a value for one of its probes
+pub fn get_project_readings_after(conn: &Connection, project: &models::Project, reading_id: i64, limit: u32) -> rusqlite::Result<Vec<models::Reading>> {
+    if project.probes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let probe_ids: Vec<String> = project.probes.iter().map(|p| p.probe_id.to_string()).collect();
+    let mut stmt = try!(conn.prepare(&format!("SELECT r.id, r.device_id, r.timestamp, pr.probe_id, pr.value FROM readings r \
+                                               LEFT JOIN probe_readings pr ON pr.reading_id = r.id \
+                                               WHERE r.id IN (SELECT r2.id FROM readings r2 \
+                                                              WHERE r2.id > $1 AND r2.timestamp >= $2 AND r2.timestamp <= $3 \
+                                                              AND EXISTS (SELECT 1 FROM probe_readings x WHERE x.reading_id = r2.id AND x.probe_id IN ({})) \
+                                                              ORDER BY r2.id LIMIT $4) \
+                                               ORDER BY r.id, pr.probe_id", probe_ids.join(", "))));
+
+    collect_readings(&mut stmt, &[&reading_id, &to_epoch_ms(&project.start), &to_epoch_ms(&project.end), &(limit as i64)])
+}
+
+// A page of the readings from `from` up to (not including) `to`, either of which may be left open.
+// Pages are in id order; pass the last id of one page as after_id to get the next.
+pub fn get_readings_between(conn: &Connection, from: Option<DateTime<UTC>>, to: Option<DateTime<UTC>>, after_id: i64, limit: u32) -> rusqlite::Result<Vec<models::Reading>> {
+    let mut stmt = try!(conn.prepare("SELECT r.id, r.device_id, r.timestamp, pr.probe_id, pr.value FROM readings r \
+                                      LEFT JOIN probe_readings pr ON pr.reading_id = r.id \
+                                      WHERE r.id IN (SELECT id FROM readings \
+                                                     WHERE ($1 IS NULL OR timestamp >= $1) AND ($2 IS NULL OR timestamp < $2) AND id > $3 \
+                                                     ORDER BY id LIMIT $4) \
+                                      ORDER BY r.id, pr.probe_id"));
+
+    let from = from.map(|dt| to_epoch_ms(&dt));
+    let to = to.map(|dt| to_epoch_ms(&dt));
+    let limit = limit as i64;
+
+    collect_readings(&mut stmt, &[&from, &to, &after_id, &limit])
+}
+
+// When the device last sent a reading
+pub fn get_latest_reading_time(conn: &Connection, device_id: i64) -> rusqlite::Result<Option<DateTime<UTC>>> {
+    let latest: Option<i64> = try!(conn.query_row("SELECT MAX(timestamp) FROM readings WHERE device_id = $1", &[&device_id], |row| row.get(0)));
+    Ok(latest.map(from_epoch_ms))
+}
+
+// One series per probe in the project, in the project's probe order
+pub fn get_project_readings(conn: &Connection, project: &models::Project, after: Option<DateTime<UTC>>) -> rusqlite::Result<Vec<models::ProbeSeries>> {
+    let mut stmt = try!(conn.prepare("SELECT pr.probe_id, r.timestamp, pr.value FROM probe_readings pr \
+                                      INNER JOIN readings r ON r.id = pr.reading_id \
+                                      WHERE pr.probe_id = $1 AND r.timestamp > $2 AND r.timestamp < $3 \
+                                      ORDER BY r.timestamp"));
 
     let start_date = match after {
         Some(dt) => dt,
         None => project.start
     };
 
-    let reading_iter = try!(stmt.query_map(&[&start_date, &project.end], |row| {
-        models::Reading {
-            id: row.get(0),
-            value1: row.get(1),
-            value2: row.get(2),
-            timestamp: row.get(3)
+    let mut result = vec![];
+
+    for probe in project.probes.iter() {
+        let mut series = models::ProbeSeries::new(probe.probe_id, &probe.name);
+
+        let reading_iter = try!(stmt.query_map(&[&probe.probe_id, &to_epoch_ms(&start_date), &to_epoch_ms(&project.end)], |row| {
+            models::SeriesPoint::raw(from_epoch_ms(row.get(1)), row.get(2))
+        }));
+
+        for reading_row in reading_iter {
+            series.readings.push(try!(reading_row));
         }
-    }));
+
+        series.newest = series.readings.last().map(|p| p.timestamp);
+        result.push(series);
+    }
+
+    Ok(result)
+}
+
+// Like get_project_readings, but averages readings into at most max_points buckets per probe, keeping each bucket's min and max.
+// Buckets are aligned to the project start so they don't shift as readings arrive. Where they're wide enough, whole buckets of
+// a rollup table are used in place of their readings; readings not rolled up yet, and the partial buckets at either end, are read raw.
+pub fn get_project_readings_downsampled(conn: &Connection, project: &models::Project, after: Option<DateTime<UTC>>, max_points: u32) -> rusqlite::Result<Vec<models::ProbeSeries>> {
+    let project_start = to_epoch_ms(&project.start);
+    let project_end = to_epoch_ms(&project.end);
+    let bucket_ms = max(1, (project_end - project_start) / max(1, max_points as i64) + 1);
+
+    let start_date = match after {
+        Some(dt) => to_epoch_ms(&dt),
+        None => project_start
+    };
+
+    // rollup buckets are placed by their middle, so they're only used when ten fit in a point; everything before `rolled` has been rolled up
+    let rollup = ROLLUPS.iter().rev().find(|&&(_, width)| width * 10 <= bucket_ms).cloned();
+    let (table, width) = rollup.unwrap_or(ROLLUPS[0]);
+    let rolled = match rollup {
+        None => 0,
+        Some(_) => try!(get_rolled_up_until(conn, width))
+    };
+    let rollup_from = (start_date / width + 1) * width;
+    let rollup_to = min(rolled, (project_end / width) * width);
+
+    let mut stmt = try!(conn.prepare(&format!("SELECT CAST(AVG(t) AS INTEGER), SUM(v * n) / SUM(n), MIN(lo), MAX(hi), MAX(newest) FROM ( \
+                                                   SELECT bucket + {1} / 2 AS b, bucket + {1} / 2 AS t, avg AS v, count AS n, min AS lo, max AS hi, NULL AS newest \
+                                                   FROM {0} WHERE probe_id = $1 AND bucket >= $2 AND bucket < $3 \
+                                                   UNION ALL \
+                                                   SELECT r.timestamp, r.timestamp, pr.value, pr.value IS NOT NULL, pr.value, pr.value, r.timestamp \
+                                                   FROM probe_readings pr INNER JOIN readings r ON r.id = pr.reading_id \
+                                                   WHERE pr.probe_id = $1 AND r.timestamp > $4 AND r.timestamp < $5 \
+                                                   AND NOT (r.timestamp >= $2 AND r.timestamp < $3)) \
+                                               GROUP BY (b - $6) / $7 \
+                                               ORDER BY 1", table, width)));
 
     let mut result = vec![];
 
-    for reading_row in reading_iter {
-        let reading = try!(reading_row);
-        result.push(reading);
+    for probe in project.probes.iter() {
+        let mut series = models::ProbeSeries::new(probe.probe_id, &probe.name);
+
+        let bucket_iter = try!(stmt.query_map(&[&probe.probe_id, &rollup_from, &rollup_to, &start_date, &project_end, &project_start, &bucket_ms], |row| {
+            let point = models::SeriesPoint {
+                timestamp: from_epoch_ms(row.get(0)),
+                value: row.get(1),
+                min: row.get(2),
+                max: row.get(3)
+            };
+            let newest: Option<i64> = row.get(4);
+            (point, newest)
+        }));
+
+        for bucket_row in bucket_iter {
+            let (point, newest) = try!(bucket_row);
+            series.readings.push(point);
+            if let Some(t) = newest {
+                series.newest = Some(from_epoch_ms(t));
+            }
+        }
+
+        // the newest point may be all rollups, which don't say when their last reading was
+        if rollup_to > rollup_from {
+            let newest: Option<i64> = try!(conn.query_row("SELECT MAX(r.timestamp) FROM probe_readings pr INNER JOIN readings r ON r.id = pr.reading_id \
+                                                           WHERE pr.probe_id = $1 AND r.timestamp > $2 AND r.timestamp < $3",
+                                                          &[&probe.probe_id, &start_date, &project_end], |row| row.get(0)));
+            series.newest = newest.map(from_epoch_ms);
+        }
+
+        result.push(series);
     }
 
     Ok(result)
 }
 
-pub fn insert_project(conn: &Connection, project: &mut models::Project) -> rusqlite::Result<()> {
-    try!(conn.execute("INSERT INTO projects (name, start, end, sensor1_name, sensor2_name, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
-                 &[&project.name, &project.start, &project.end, &project.sensor1_name, &project.sensor2_name, &project.created_at, &project.updated_at]));
+// The start of the first bucket of the given width with a reading not yet rolled up in it; everything before is in the rollups
+fn get_rolled_up_until(conn: &Connection, width: i64) -> rusqlite::Result<i64> {
+    let done = try!(get_rollup_progress(conn));
 
-     project.id = conn.last_insert_rowid();
-     Ok(())
+    let pending: Option<i64> = try!(conn.query_row("SELECT MIN(timestamp) FROM readings WHERE id > $1", &[&done], |row| row.get(0)));
+    Ok(match pending {
+        Some(t) => (t / width) * width,
+        None => i64::max_value()
+    })
 }
 
-pub fn update_project(conn: &Connection, project: &mut models::Project) -> rusqlite::Result<()> {
-    let changed = try!(conn.execute("UPDATE projects SET name = $1, start = $2, end = $3, sensor1_name = $4, sensor2_name = $5, created_at = $6, updated_at = $7 WHERE id = $8",
-                 &[&project.name, &project.start, &project.end, &project.sensor1_name, &project.sensor2_name, &project.created_at, &project.updated_at, &project.id]));
+// The newest reading added to the rollup tables, or 0 before the first pass
+fn get_rollup_progress(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COALESCE(MAX(reading_id), 0) FROM rollup_progress", &[], |row| row.get(0))
+}
 
-    if changed == 1 {
+// Rollup tables and their bucket widths, in ms
+const ROLLUPS: [(&'static str, i64); 2] = [("readings_1m", 60_000), ("readings_15m", 900_000)];
+
+// Brings the rollup tables up to date with readings. Readings are added to their buckets by id, so ones that arrive
+// late or are imported into the past are counted too, without needing the raw readings already summarized.
+pub fn update_rollups(conn: &Connection) -> rusqlite::Result<()> {
+    in_transaction(conn, |conn| {
+        let done = try!(get_rollup_progress(conn));
+        let newest = try!(get_latest_reading_id(conn));
+
+        for &(table, width) in ROLLUPS.iter() {
+            try!(add_to_rollup(conn, table, width, done, newest));
+        }
+
+        try!(conn.execute("DELETE FROM rollup_progress", &[]));
+        try!(conn.execute("INSERT INTO rollup_progress (reading_id) VALUES ($1)", &[&newest]));
         Ok(())
-    } else {
-        Err(rusqlite::Error::StatementChangedRows(changed))
-    }
+    })
 }
 
-pub fn get_projects(conn: &Connection) -> rusqlite::Result<Vec<models::Project>> {
-    let mut stmt = try!(conn.prepare("SELECT id, name, start, end, sensor1_name, sensor2_name, created_at, updated_at FROM projects ORDER BY created_at DESC"));
-    let project_iter = try!(stmt.query_map(&[], |row| {
-        models::Project {
-            id: row.get(0),
+// Adds the readings after `after_id`, up to `last_id`, to the buckets they fall in
+fn add_to_rollup(conn: &Connection, table: &str, width: i64, after_id: i64, last_id: i64) -> rusqlite::Result<()> {
+    try!(conn.execute(&format!("INSERT OR REPLACE INTO {0} (probe_id, bucket, count, avg, min, max) \
+                                SELECT n.probe_id, n.bucket, n.count + COALESCE(o.count, 0), \
+                                       CASE WHEN COALESCE(o.count, 0) = 0 THEN n.avg \
+                                            WHEN n.count = 0 THEN o.avg \
+                                            ELSE (n.avg * n.count + o.avg * o.count) / (n.count + o.count) END, \
+                                       COALESCE(MIN(n.min, o.min), n.min, o.min), COALESCE(MAX(n.max, o.max), n.max, o.max) \
+                                FROM (SELECT pr.probe_id, (r.timestamp / {1}) * {1} AS bucket, COUNT(pr.value) AS count, \
+                                             AVG(pr.value) AS avg, MIN(pr.value) AS min, MAX(pr.value) AS max \
+                                      FROM probe_readings pr INNER JOIN readings r ON r.id = pr.reading_id \
+                                      WHERE r.id > $1 AND r.id <= $2 \
+                                      GROUP BY pr.probe_id, r.timestamp / {1}) n \
+                                LEFT JOIN {0} o ON o.probe_id = n.probe_id AND o.bucket = n.bucket", table, width),
+                      &[&after_id, &last_id]));
+
+    Ok(())
+}
+
+// Deletes raw readings taken before the given time that fall outside every project's window.
+// Only readings already added to the rollup tables are removed. Returns the number of readings deleted.
+pub fn prune_readings(conn: &Connection, before: DateTime<UTC>) -> rusqlite::Result<i32> {
+    in_transaction(conn, |conn| {
+        let done = try!(get_rollup_progress(conn));
+
+        let outside_projects = "r.timestamp < $1 AND r.id <= $2 AND NOT EXISTS (SELECT 1 FROM projects p WHERE r.timestamp >= p.start AND r.timestamp <= p.end)";
+
+        try!(conn.execute(&format!("DELETE FROM probe_readings WHERE reading_id IN (SELECT r.id FROM readings r WHERE {})", outside_projects),
+                          &[&to_epoch_ms(&before), &done]));
+        conn.execute(&format!("DELETE FROM readings WHERE id IN (SELECT r.id FROM readings r WHERE {})", outside_projects),
+                     &[&to_epoch_ms(&before), &done])
+    })
+}
+
+fn get_project_probes(conn: &Connection, project_id: i64) -> rusqlite::Result<Vec<models::ProjectProbe>> {
+    let mut stmt = try!(conn.prepare("SELECT probe_id, name, position, role FROM project_probes WHERE project_id = $1 ORDER BY position"));
+    let probe_iter = try!(stmt.query_map(&[&project_id], |row| {
+        let role: String = row.get(3);
+
+        models::ProjectProbe {
+            probe_id: row.get(0),
             name: row.get(1),
-            start: row.get(2),
-            end: row.get(3),
-            sensor1_name: row.get(4),
-            sensor2_name: row.get(5),
-            created_at: row.get(6),
-            updated_at: row.get(7)
+            position: row.get(2),
+            role: models::ProbeRole::from_str(&role).unwrap()
         }
     }));
 
     let mut result = vec![];
 
+    for probe_row in probe_iter {
+        result.push(try!(probe_row));
+    }
+
+    Ok(result)
+}
+
+// Replaces the project's probe list with project.probes
+fn save_project_probes(conn: &Connection, project: &models::Project) -> rusqlite::Result<()> {
+    try!(conn.execute("DELETE FROM project_probes WHERE project_id = $1", &[&project.id]));
+
+    for probe in project.probes.iter() {
+        try!(conn.execute("INSERT INTO project_probes (project_id, probe_id, name, position, role) VALUES ($1, $2, $3, $4, $5)",
+                     &[&project.id, &probe.probe_id, &probe.name, &probe.position, &probe.role.as_str()]));
+    }
+
+    Ok(())
+}
+
+fn project_from_row(row: &rusqlite::Row) -> models::Project {
+    let units: Option<String> = row.get(6);
+
+    models::Project {
+        id: row.get(0),
+        name: row.get(1),
+        start: from_epoch_ms(row.get(2)),
+        end: from_epoch_ms(row.get(3)),
+        probes: vec![],
+        units: units.and_then(|u| models::TemperatureUnit::from_str(&u)),
+        created_at: from_epoch_ms(row.get(4)),
+        updated_at: from_epoch_ms(row.get(5))
+    }
+}
+
+pub fn insert_project(conn: &Connection, project: &mut models::Project) -> rusqlite::Result<()> {
+    in_transaction(conn, |conn| insert_project_in_transaction(conn, project))
+}
+
+// Like insert_project, for use inside a transaction the caller already has open
+pub fn insert_project_in_transaction(conn: &Connection, project: &mut models::Project) -> rusqlite::Result<()> {
+    let units = project.units.map(|u| u.as_str());
+    try!(conn.execute("INSERT INTO projects (name, start, end, created_at, updated_at, units) VALUES ($1, $2, $3, $4, $5, $6)",
+                 &[&project.name, &to_epoch_ms(&project.start), &to_epoch_ms(&project.end), &to_epoch_ms(&project.created_at), &to_epoch_ms(&project.updated_at), &units]));
+
+    project.id = conn.last_insert_rowid();
+    save_project_probes(conn, project)
+}
+
+pub fn update_project(conn: &Connection, project: &mut models::Project) -> rusqlite::Result<()> {
+    in_transaction(conn, |conn| {
+        let units = project.units.map(|u| u.as_str());
+        let changed = try!(conn.execute("UPDATE projects SET name = $1, start = $2, end = $3, created_at = $4, updated_at = $5, units = $6 WHERE id = $7",
+                     &[&project.name, &to_epoch_ms(&project.start), &to_epoch_ms(&project.end), &to_epoch_ms(&project.created_at), &to_epoch_ms(&project.updated_at), &units, &project.id]));
+
+        if changed != 1 {
+            return Err(rusqlite::Error::StatementChangedRows(changed));
+        }
+
+        save_project_probes(conn, project)
+    })
+}
+
+pub fn get_projects(conn: &Connection) -> rusqlite::Result<Vec<models::Project>> {
+    let mut stmt = try!(conn.prepare("SELECT id, name, start, end, created_at, updated_at, units FROM projects ORDER BY created_at DESC"));
+    let project_iter = try!(stmt.query_map(&[], project_from_row));
+
+    let mut result = vec![];
+
     for project_row in project_iter {
-        let project = try!(project_row);
+        let mut project = try!(project_row);
+        project.probes = try!(get_project_probes(conn, project.id));
         result.push(project);
     }
 
@@ -118,27 +605,312 @@ pub fn get_projects(conn: &Connection) -> rusqlite::Result<Vec<models::Project>>
 }
 
 pub fn get_project(conn: &Connection, id: i64) -> rusqlite::Result<Option<models::Project>> {
-    let sql = "SELECT id, name, start, end, sensor1_name, sensor2_name, created_at, updated_at FROM projects WHERE id = $1";
-    let result = conn.query_row(sql, &[&id], |row| {
-        models::Project {
-            id: row.get(0),
-            name: row.get(1),
-            start: row.get(2),
-            end: row.get(3),
-            sensor1_name: row.get(4),
-            sensor2_name: row.get(5),
-            created_at: row.get(6),
-            updated_at: row.get(7)
+    let sql = "SELECT id, name, start, end, created_at, updated_at, units FROM projects WHERE id = $1";
+    let result = conn.query_row(sql, &[&id], project_from_row);
+
+    match result {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Ok(mut p) => {
+            p.probes = try!(get_project_probes(conn, p.id));
+            Ok(Some(p))
+        },
+        Err(e) => Err(e)
+    }
+}
+
+// Deletes the project along with everything hanging off it; the readings stay
+pub fn delete_project(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    in_transaction(conn, |conn| {
+        try!(conn.execute("DELETE FROM alarm_events WHERE rule_id IN (SELECT id FROM alarm_rules WHERE project_id = $1)", &[&id]));
+        try!(conn.execute("DELETE FROM alarm_rules WHERE project_id = $1", &[&id]));
+        try!(conn.execute("DELETE FROM project_notifiers WHERE project_id = $1", &[&id]));
+        try!(conn.execute("DELETE FROM project_events WHERE project_id = $1", &[&id]));
+        try!(conn.execute("DELETE FROM annotations WHERE project_id = $1", &[&id]));
+        try!(conn.execute("DELETE FROM project_probes WHERE project_id = $1", &[&id]));
+
+        let changed = try!(conn.execute("DELETE FROM projects WHERE id = $1", &[&id]));
+
+        if changed != 1 {
+            return Err(rusqlite::Error::StatementChangedRows(changed));
         }
-    });
+
+        Ok(())
+    })
+}
+
+// Projects whose window includes the given time
+pub fn get_active_projects(conn: &Connection, at: DateTime<UTC>) -> rusqlite::Result<Vec<models::Project>> {
+    let mut stmt = try!(conn.prepare("SELECT id, name, start, end, created_at, updated_at, units FROM projects WHERE start <= $1 AND end >= $1 ORDER BY id"));
+    let project_iter = try!(stmt.query_map(&[&to_epoch_ms(&at)], project_from_row));
+
+    let mut result = vec![];
+
+    for project_row in project_iter {
+        let mut project = try!(project_row);
+        project.probes = try!(get_project_probes(conn, project.id));
+        result.push(project);
+    }
+
+    Ok(result)
+}
+
+const ALARM_RULE_COLUMNS: &'static str = "ar.id, ar.project_id, ar.probe_id, ar.kind, ar.threshold, ar.created_at, \
+                                          (SELECT ae.state FROM alarm_events ae WHERE ae.rule_id = ar.id ORDER BY ae.id DESC LIMIT 1)";
+
+fn alarm_rule_from_row(row: &rusqlite::Row) -> models::AlarmRule {
+    let kind: String = row.get(3);
+    let state: Option<String> = row.get(6);
+
+    models::AlarmRule {
+        id: row.get(0),
+        project_id: row.get(1),
+        probe_id: row.get(2),
+        // the table's CHECK constraints keep these parseable
+        kind: models::AlarmKind::from_str(&kind).unwrap(),
+        threshold: row.get(4),
+        state: state.and_then(|s| models::AlarmState::from_str(&s)).unwrap_or(models::AlarmState::Cleared),
+        created_at: from_epoch_ms(row.get(5))
+    }
+}
+
+fn query_alarm_rules(conn: &Connection, sql: &str, params: &[&rusqlite::types::ToSql]) -> rusqlite::Result<Vec<models::AlarmRule>> {
+    let mut stmt = try!(conn.prepare(sql));
+    let rule_iter = try!(stmt.query_map(params, alarm_rule_from_row));
+
+    let mut result = vec![];
+
+    for rule_row in rule_iter {
+        result.push(try!(rule_row));
+    }
+
+    Ok(result)
+}
+
+pub fn get_project_alarm_rules(conn: &Connection, project_id: i64) -> rusqlite::Result<Vec<models::AlarmRule>> {
+    let sql = format!("SELECT {} FROM alarm_rules ar WHERE ar.project_id = $1 ORDER BY ar.id", ALARM_RULE_COLUMNS);
+    query_alarm_rules(conn, &sql, &[&project_id])
+}
+
+// Rules of every project running at the given time
+pub fn get_active_alarm_rules(conn: &Connection, at: DateTime<UTC>) -> rusqlite::Result<Vec<models::AlarmRule>> {
+    let sql = format!("SELECT {} FROM alarm_rules ar INNER JOIN projects p ON p.id = ar.project_id \
+                       WHERE p.start <= $1 AND p.end >= $1 ORDER BY ar.id", ALARM_RULE_COLUMNS);
+    query_alarm_rules(conn, &sql, &[&to_epoch_ms(&at)])
+}
+
+pub fn get_alarm_rule(conn: &Connection, id: i64) -> rusqlite::Result<Option<models::AlarmRule>> {
+    let sql = format!("SELECT {} FROM alarm_rules ar WHERE ar.id = $1", ALARM_RULE_COLUMNS);
+    let result = conn.query_row(&sql, &[&id], alarm_rule_from_row);
 
     match result {
         Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Ok(p) => Ok(Some(p)),
+        Ok(r) => Ok(Some(r)),
         Err(e) => Err(e)
     }
 }
 
+pub fn insert_alarm_rule(conn: &Connection, rule: &mut models::AlarmRule) -> rusqlite::Result<()> {
+    try!(conn.execute("INSERT INTO alarm_rules (project_id, probe_id, kind, threshold, created_at) VALUES ($1, $2, $3, $4, $5)",
+                 &[&rule.project_id, &rule.probe_id, &rule.kind.as_str(), &rule.threshold, &to_epoch_ms(&rule.created_at)]));
+
+    rule.id = conn.last_insert_rowid();
+    Ok(())
+}
+
+pub fn delete_alarm_rule(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    in_transaction(conn, |conn| {
+        try!(conn.execute("DELETE FROM alarm_events WHERE rule_id = $1", &[&id]));
+        let changed = try!(conn.execute("DELETE FROM alarm_rules WHERE id = $1", &[&id]));
+
+        if changed != 1 {
+            return Err(rusqlite::Error::StatementChangedRows(changed));
+        }
+
+        Ok(())
+    })
+}
+
+fn alarm_event_from_row(row: &rusqlite::Row) -> models::AlarmEvent {
+    let state: String = row.get(2);
+
+    models::AlarmEvent {
+        id: row.get(0),
+        rule_id: row.get(1),
+        state: models::AlarmState::from_str(&state).unwrap(),
+        value: row.get(3),
+        created_at: from_epoch_ms(row.get(4))
+    }
+}
+
+pub fn insert_alarm_event(conn: &Connection, event: &mut models::AlarmEvent) -> rusqlite::Result<()> {
+    try!(conn.execute("INSERT INTO alarm_events (rule_id, state, value, created_at) VALUES ($1, $2, $3, $4)",
+                 &[&event.rule_id, &event.state.as_str(), &event.value, &to_epoch_ms(&event.created_at)]));
+
+    event.id = conn.last_insert_rowid();
+    Ok(())
+}
+
+// Id of the newest alarm event, or 0 if there are none
+pub fn get_latest_alarm_event_id(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COALESCE(MAX(id), 0) FROM alarm_events", &[], |row| row.get(0))
+}
+
+pub fn get_alarm_events_after(conn: &Connection, event_id: i64) -> rusqlite::Result<Vec<models::AlarmEvent>> {
+    let mut stmt = try!(conn.prepare("SELECT id, rule_id, state, value, created_at FROM alarm_events WHERE id > $1 ORDER BY id"));
+    let event_iter = try!(stmt.query_map(&[&event_id], alarm_event_from_row));
+
+    let mut result = vec![];
+
+    for event_row in event_iter {
+        result.push(try!(event_row));
+    }
+
+    Ok(result)
+}
+
+fn project_notifier_from_row(row: &rusqlite::Row) -> models::ProjectNotifier {
+    let kind: String = row.get(2);
+
+    models::ProjectNotifier {
+        id: row.get(0),
+        project_id: row.get(1),
+        kind: models::NotifierKind::from_str(&kind).unwrap(),
+        target: row.get(3),
+        created_at: from_epoch_ms(row.get(4))
+    }
+}
+
+pub fn get_project_notifiers(conn: &Connection, project_id: i64) -> rusqlite::Result<Vec<models::ProjectNotifier>> {
+    let mut stmt = try!(conn.prepare("SELECT id, project_id, kind, target, created_at FROM project_notifiers WHERE project_id = $1 ORDER BY id"));
+    let notifier_iter = try!(stmt.query_map(&[&project_id], project_notifier_from_row));
+
+    let mut result = vec![];
+
+    for notifier_row in notifier_iter {
+        result.push(try!(notifier_row));
+    }
+
+    Ok(result)
+}
+
+pub fn insert_project_notifier(conn: &Connection, notifier: &mut models::ProjectNotifier) -> rusqlite::Result<()> {
+    try!(conn.execute("INSERT INTO project_notifiers (project_id, kind, target, created_at) VALUES ($1, $2, $3, $4)",
+                 &[&notifier.project_id, &notifier.kind.as_str(), &notifier.target, &to_epoch_ms(&notifier.created_at)]));
+
+    notifier.id = conn.last_insert_rowid();
+    Ok(())
+}
+
+// Deletes one of a project's notifiers
+pub fn delete_project_notifier(conn: &Connection, project_id: i64, id: i64) -> rusqlite::Result<()> {
+    let changed = try!(conn.execute("DELETE FROM project_notifiers WHERE id = $1 AND project_id = $2", &[&id, &project_id]));
+
+    if changed == 1 {
+        Ok(())
+    } else {
+        Err(rusqlite::Error::StatementChangedRows(changed))
+    }
+}
+
+fn project_event_from_row(row: &rusqlite::Row) -> models::ProjectEvent {
+    let kind: String = row.get(3);
+    let ended_at: Option<i64> = row.get(5);
+
+    models::ProjectEvent {
+        id: row.get(0),
+        project_id: row.get(1),
+        probe_id: row.get(2),
+        kind: models::ProjectEventKind::from_str(&kind).unwrap(),
+        started_at: from_epoch_ms(row.get(4)),
+        ended_at: ended_at.map(from_epoch_ms)
+    }
+}
+
+pub fn get_project_events(conn: &Connection, project_id: i64) -> rusqlite::Result<Vec<models::ProjectEvent>> {
+    let mut stmt = try!(conn.prepare("SELECT id, project_id, probe_id, kind, started_at, ended_at FROM project_events WHERE project_id = $1 ORDER BY started_at"));
+    let event_iter = try!(stmt.query_map(&[&project_id], project_event_from_row));
+
+    let mut result = vec![];
+
+    for event_row in event_iter {
+        result.push(try!(event_row));
+    }
+
+    Ok(result)
+}
+
+// Records the start of an event, unless one of its kind is already going on for the probe.
+// An event that's already over is recorded with its end. Returns whether it was recorded.
+pub fn start_project_event(conn: &Connection, event: &mut models::ProjectEvent) -> rusqlite::Result<bool> {
+    let changed = try!(conn.execute("INSERT INTO project_events (project_id, probe_id, kind, started_at, ended_at) \
+                                     SELECT $1, $2, $3, $4, $5 WHERE NOT EXISTS \
+                                     (SELECT 1 FROM project_events WHERE project_id = $1 AND probe_id = $2 AND kind = $3 AND ended_at IS NULL)",
+                                    &[&event.project_id, &event.probe_id, &event.kind.as_str(), &to_epoch_ms(&event.started_at),
+                                      &event.ended_at.map(|dt| to_epoch_ms(&dt))]));
+
+    if changed == 0 {
+        return Ok(false);
+    }
+
+    event.id = conn.last_insert_rowid();
+    Ok(true)
+}
+
+// Ends the probe's event of the given kind, if one is going on. Returns whether one was.
+pub fn end_project_event(conn: &Connection, project_id: i64, probe_id: i64, kind: models::ProjectEventKind, at: DateTime<UTC>) -> rusqlite::Result<bool> {
+    let changed = try!(conn.execute("UPDATE project_events SET ended_at = $1 WHERE project_id = $2 AND probe_id = $3 AND kind = $4 AND ended_at IS NULL",
+                                    &[&to_epoch_ms(&at), &project_id, &probe_id, &kind.as_str()]));
+
+    Ok(changed > 0)
+}
+
+fn annotation_from_row(row: &rusqlite::Row) -> models::Annotation {
+    models::Annotation {
+        id: row.get(0),
+        project_id: row.get(1),
+        timestamp: from_epoch_ms(row.get(2)),
+        text: row.get(3),
+        created_at: from_epoch_ms(row.get(4))
+    }
+}
+
+pub fn get_project_annotations(conn: &Connection, project_id: i64) -> rusqlite::Result<Vec<models::Annotation>> {
+    let mut stmt = try!(conn.prepare("SELECT id, project_id, timestamp, text, created_at FROM annotations WHERE project_id = $1 ORDER BY timestamp, id"));
+    let annotation_iter = try!(stmt.query_map(&[&project_id], annotation_from_row));
+
+    let mut result = vec![];
+
+    for annotation_row in annotation_iter {
+        result.push(try!(annotation_row));
+    }
+
+    Ok(result)
+}
+
+pub fn insert_annotation(conn: &Connection, annotation: &mut models::Annotation) -> rusqlite::Result<()> {
+    try!(conn.execute("INSERT INTO annotations (project_id, timestamp, text, created_at) VALUES ($1, $2, $3, $4)",
+                 &[&annotation.project_id, &to_epoch_ms(&annotation.timestamp), &annotation.text, &to_epoch_ms(&annotation.created_at)]));
+
+    annotation.id = conn.last_insert_rowid();
+    Ok(())
+}
+
+// Deletes one of a project's annotations
+pub fn delete_annotation(conn: &Connection, project_id: i64, id: i64) -> rusqlite::Result<()> {
+    let changed = try!(conn.execute("DELETE FROM annotations WHERE id = $1 AND project_id = $2", &[&id, &project_id]));
+
+    if changed == 1 {
+        Ok(())
+    } else {
+        Err(rusqlite::Error::StatementChangedRows(changed))
+    }
+}
+
+// Rebuilds the database file to reclaim the space left by deleted readings
+pub fn vacuum(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("VACUUM")
+}
+
 pub fn get_pool(path: &str, size: Option<u32>) -> r2d2::Pool<pool::SqliteConnectionManager> {
     let manager = pool::SqliteConnectionManager::new(path);
     let size = match size {
@@ -155,22 +927,32 @@ pub fn get_pool(path: &str, size: Option<u32>) -> r2d2::Pool<pool::SqliteConnect
 }
 
 // returns a Connection
-// if migrate is a Some, migrations are run
-pub fn get_connection(path: &str, migrate: Option<String>) -> rusqlite::Result<Connection> {
+// if migrate is true, the database is created if need be and migrations are run
+pub fn get_connection(path: &str, migrate: bool) -> Result<Connection, migrations::MigrationError> {
 
     let mut flags = SQLITE_OPEN_READ_WRITE;
 
-    if migrate.is_some() {
+    if migrate {
         flags = flags | SQLITE_OPEN_CREATE;
     }
 
     let path = Path::new(path);
     let conn = try!(Connection::open_with_flags(path, flags));
+    try!(set_busy_timeout(&conn));
 
-    match migrate {
-        Some(p) => try!(migrations::perform_migration(&conn, &p)),
-        None => {}
+    if migrate {
+        try!(migrations::perform_migration(&conn));
     }
 
     Ok(conn)
 }
+
+#[test]
+fn test_epoch_ms() {
+    for &ms in [0i64, 1, 999, 1000, 1466258402500, -1, -999, -1000, -1001, -1466258402500].iter() {
+        assert_eq!(ms, to_epoch_ms(&from_epoch_ms(ms)));
+    }
+
+    assert_eq!(UTC.ymd(1969, 12, 31).and_hms_milli(23, 59, 59, 999), from_epoch_ms(-1));
+    assert_eq!(UTC.ymd(1969, 12, 31).and_hms_milli(23, 59, 58, 999), from_epoch_ms(-1001));
+}
@@ -38,6 +38,7 @@ impl r2d2::ManageConnection for SqliteConnectionManager {
                     let flags = SQLITE_OPEN_READ_WRITE;
                     let path = Path::new(path);
                     let mut conn = try!(Connection::open_with_flags(path, flags));
+                    try!(super::set_busy_timeout(&conn));
 
                     add_trace(&mut conn);
 
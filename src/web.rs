@@ -19,6 +19,7 @@ mod weblib;
 use getopts::Options;
 use handlebars_iron::{HandlebarsEngine, DirectorySource};
 use iron::{AfterMiddleware};
+use iron::mime::Mime;
 use iron::prelude::*;
 use mount::Mount;
 use router::Router;
@@ -27,34 +28,52 @@ use std::env;
 use std::path::Path;
 
 
+use pibq::config::Config;
 use pibq::sql;
 use pibq::sql::pool::{SqlitePool};
-use weblib::AppDb;
-use weblib::web_handlers;
+use weblib::{AppConfig, AppDb, AppLive};
+use weblib::live::Broadcaster;
+use weblib::{api_handlers, web_handlers};
 
 struct ErrorHandler;
 
 impl AfterMiddleware for ErrorHandler {
-    fn catch(&self, _: &mut Request, err: IronError) -> IronResult<Response> {
+    fn catch(&self, request: &mut Request, err: IronError) -> IronResult<Response> {
         println!("Error Encountered: {:?}", err.error);
+
+        let is_api = match url::Url::parse(&format!("{}", request.url)) {
+            Ok(u) => u.path().starts_with("/api/"),
+            Err(_) => false
+        };
+
+        // API clients get the error as JSON rather than whatever body the handler left
+        if is_api {
+            let status = err.response.status.unwrap_or(iron::status::InternalServerError);
+            let content_type: Mime = "application/json".parse().unwrap();
+            let body = api_handlers::error_json(status, &err.error.to_string()).to_string();
+            return Ok(Response::with((status, content_type, body)));
+        }
+
         Ok(err.response)
     }
 }
 
 struct WebServer {
     sql_pool: SqlitePool,
+    config: Config,
     asset_path: String,
     template_path: String,
-    port: String
+    binding: String
 }
 
 impl WebServer {
-    pub fn new(sql_pool: SqlitePool, web_root: &str, port: &str) -> Self {
+    pub fn new(sql_pool: SqlitePool, config: Config, web_root: &str, bind: &str, port: u16) -> Self {
         WebServer {
             sql_pool: sql_pool,
+            config: config,
             asset_path: web_root.to_string() + "/assets/",
             template_path: web_root.to_string() + "/templates/",
-            port: port.to_string()
+            binding: format!("{}:{}", bind, port)
         }
     }
 
@@ -67,6 +86,31 @@ impl WebServer {
         router.get("/projects/:id/edit", |request: &mut Request| { web_handlers::edit_project(request) }, "edit_project");
         router.post("/projects/:id", |request: &mut Request| { web_handlers::update_project(request) }, "update_project");
         router.get("/projects/:id/data.json", |request: &mut Request| { web_handlers::project_data(request) }, "project_data");
+        router.get("/projects/:id/stream", |request: &mut Request| { web_handlers::project_stream(request) }, "project_stream");
+        router.get("/projects/:id/export.csv", |request: &mut Request| { web_handlers::export_csv(request) }, "export_csv");
+        router.get("/projects/:id/export.json", |request: &mut Request| { web_handlers::export_json(request) }, "export_json");
+        router.get("/projects/:id/eta.json", |request: &mut Request| { web_handlers::project_etas(request) }, "project_etas");
+        router.get("/projects/:id/events.json", |request: &mut Request| { web_handlers::project_events(request) }, "project_events");
+        router.post("/projects/:id/annotations", |request: &mut Request| { web_handlers::create_annotation(request) }, "create_annotation");
+        router.post("/projects/:id/annotations/:annotation_id/delete", |request: &mut Request| { web_handlers::delete_annotation(request) }, "delete_annotation");
+        router.get("/projects/:id/alarms.json", |request: &mut Request| { web_handlers::project_alarms(request) }, "project_alarms");
+        router.post("/projects/:id/alarms", |request: &mut Request| { web_handlers::create_alarm(request) }, "create_alarm");
+        router.post("/projects/:id/alarms/:alarm_id/acknowledge", |request: &mut Request| { web_handlers::acknowledge_alarm(request) }, "acknowledge_alarm");
+        router.post("/projects/:id/alarms/:alarm_id/delete", |request: &mut Request| { web_handlers::delete_alarm(request) }, "delete_alarm");
+        router.post("/projects/:id/notifiers", |request: &mut Request| { web_handlers::create_notifier(request) }, "create_notifier");
+        router.post("/projects/:id/notifiers/:notifier_id/delete", |request: &mut Request| { web_handlers::delete_notifier(request) }, "delete_notifier");
+
+        router.get("/api/v1/projects", |request: &mut Request| { api_handlers::projects(request) }, "api_projects");
+        router.post("/api/v1/projects", |request: &mut Request| { api_handlers::create_project(request) }, "api_create_project");
+        router.get("/api/v1/projects/:id", |request: &mut Request| { api_handlers::project(request) }, "api_project");
+        router.put("/api/v1/projects/:id", |request: &mut Request| { api_handlers::update_project(request) }, "api_update_project");
+        router.delete("/api/v1/projects/:id", |request: &mut Request| { api_handlers::delete_project(request) }, "api_delete_project");
+        router.get("/api/v1/projects/:id/annotations", |request: &mut Request| { api_handlers::project_annotations(request) }, "api_project_annotations");
+        router.post("/api/v1/projects/:id/annotations", |request: &mut Request| { api_handlers::create_annotation(request) }, "api_create_annotation");
+        router.delete("/api/v1/projects/:id/annotations/:annotation_id", |request: &mut Request| { api_handlers::delete_annotation(request) }, "api_delete_annotation");
+        router.get("/api/v1/readings", |request: &mut Request| { api_handlers::readings(request) }, "api_readings");
+        router.get("/api/v1/connection_statuses", |request: &mut Request| { api_handlers::connection_statuses(request) }, "api_connection_statuses");
+        router.get("/api/v1/devices", |request: &mut Request| { api_handlers::devices(request) }, "api_devices");
 
         let mut mount = Mount::new();
         mount
@@ -84,14 +128,14 @@ impl WebServer {
 
         let mut chain = Chain::new(mount);
         chain.link(persistent::Read::<AppDb>::both(self.sql_pool.clone()));
+        chain.link_before(persistent::Read::<AppConfig>::one(self.config.clone()));
+        chain.link_before(persistent::Read::<AppLive>::one(Broadcaster::start(self.sql_pool.clone())));
         chain.link_after(template_engine);
         chain.link_after(ErrorHandler);
         chain.link_before(logger_before);
         chain.link_after(logger_after);
 
-        let binding = "0.0.0.0:".to_string() + &self.port;
-
-        Iron::new(chain).http(binding.as_str()).unwrap();
+        Iron::new(chain).http(self.binding.as_str()).unwrap();
     }
 }
 
@@ -100,13 +144,35 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
+// Loads the config file, then applies any command line overrides
+fn load_config(matches: &getopts::Matches) -> Result<Config, String> {
+    let mut config = match Config::load(matches.opt_str("c").as_ref().map(|s| s.as_str())) {
+        Ok(c) => c,
+        Err(e) => return Err(e.to_string())
+    };
+
+    if let Some(v) = matches.opt_str("d") { config.database.path = v; }
+    if let Some(v) = matches.opt_str("w") { config.web.webroot = v; }
+    if let Some(v) = matches.opt_str("b") { config.web.bind = v; }
+    if let Some(v) = matches.opt_str("p") {
+        config.web.port = match v.parse::<u16>() {
+            Ok(p) => p,
+            Err(e) => return Err(format!("invalid port: {}", e))
+        };
+    }
+
+    Ok(config)
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
+    opts.optopt("c", "config", &format!("config file (default {})", pibq::config::DEFAULT_PATH), "FILE");
     opts.optopt("d", "dbfile", "sqlite DB file", "FILE");
     opts.optopt("w", "webroot", "root of web files", "DIR");
+    opts.optopt("b", "bind", "address to listen on", "ADDR");
     opts.optopt("p", "port", "port to listen on", "PORT");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
@@ -122,12 +188,17 @@ fn main() {
         return;
     }
 
-    let dbfile = matches.opt_str("d").unwrap_or("pibq.sqlite".to_string());
-    let webroot = matches.opt_str("w").unwrap_or("web".to_string());
-    let port = matches.opt_str("p").unwrap_or("3000".to_string());
+    let config = match load_config(&matches) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(&program, opts);
+            return;
+        }
+    };
 
-    let db_pool = sql::get_pool(&dbfile, Some(5));
+    let db_pool = sql::get_pool(&config.database.path, Some(config.web.pool_size));
 
-    let mut w = WebServer::new(db_pool, &webroot, &port);
+    let mut w = WebServer::new(db_pool, config.clone(), &config.web.webroot, &config.web.bind, config.web.port);
     w.start();
 }
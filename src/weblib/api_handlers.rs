@@ -0,0 +1,319 @@
+use chrono::datetime::DateTime;
+use chrono::offset::utc::UTC;
+use iron::headers;
+use iron::mime::Mime;
+use iron::prelude::*;
+use iron::status;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+
+use pibq::models::{Probe, ProbeRole, Project, ProjectProbe, TemperatureUnit};
+use pibq::sql;
+use super::web_handlers::{self, WebError, db_unwrap, get_config, get_connection, get_project_from_route, parse_date, parse_json_body, parse_query};
+
+// Page sizes for readings and statuses
+const DEFAULT_LIMIT: u32 = 1000;
+const MAX_LIMIT: u32 = 10000;
+
+// The body the ErrorHandler gives failed /api requests
+pub fn error_json(status: status::Status, message: &str) -> Json {
+    let mut e: BTreeMap<String, Json> = BTreeMap::new();
+    e.insert("status".to_string(), status.to_u16().to_json());
+    e.insert("message".to_string(), message.to_json());
+
+    let mut m: BTreeMap<String, Json> = BTreeMap::new();
+    m.insert("error".to_string(), e.to_json());
+    m.to_json()
+}
+
+fn json_response(status: status::Status, json: Json) -> IronResult<Response> {
+    let content_type: Mime = "application/json".parse().unwrap();
+    Ok(Response::with((status, content_type, json.to_string())))
+}
+
+fn bad_request(msg: &str) -> IronError {
+    IronError::new(WebError::new(msg), status::BadRequest)
+}
+
+fn parse_optional_date(query: &HashMap<String, String>, name: &str) -> IronResult<Option<DateTime<UTC>>> {
+    match query.get(name) {
+        Some(str) if str.len() > 0 => parse_date(str).map(Some),
+        _ => Ok(None)
+    }
+}
+
+// after_id and limit, for paging through readings and statuses
+fn parse_page(query: &HashMap<String, String>) -> IronResult<(i64, u32)> {
+    let after_id = match query.get("after_id").map(|s| s.parse::<i64>()) {
+        None => 0,
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Err(bad_request("after_id must be a reading id"))
+    };
+
+    let limit = match query.get("limit").map(|s| s.parse::<u32>()) {
+        None => DEFAULT_LIMIT,
+        Some(Ok(n)) if n > 0 && n <= MAX_LIMIT => n,
+        _ => return Err(bad_request(&format!("limit must be from 1 to {}", MAX_LIMIT)))
+    };
+
+    Ok((after_id, limit))
+}
+
+// ?units=C or F, defaulting to the configured units
+fn parse_units(request: &mut Request, query: &HashMap<String, String>) -> IronResult<TemperatureUnit> {
+    match query.get("units") {
+        None => Ok(try!(get_config(request)).units),
+        Some(str) => match TemperatureUnit::from_str(str) {
+            Some(u) => Ok(u),
+            None => Err(bad_request("units must be C or F"))
+        }
+    }
+}
+
+// The JSON's fields onto the project; anything left out stays as it was.
+// probes is a list of {"probe_id", "name", "role"}, charted in that order.
+fn assign_project_json(project: &mut Project, json: &Json, known_probes: &[Probe], errors: &mut Vec<String>) {
+    if let Some(name) = json.find("name") {
+        match name.as_string() {
+            Some(str) if str.trim().len() > 0 => { project.name = str.trim().to_string(); },
+            _ => { errors.push("name must be a non-empty string".to_string()); }
+        }
+    }
+
+    for &(field, is_start) in [("start", true), ("end", false)].iter() {
+        if let Some(value) = json.find(field) {
+            match value.as_string().map(|s| DateTime::parse_from_rfc3339(s)) {
+                Some(Ok(dt)) => {
+                    if is_start { project.start = dt.with_timezone(&UTC); } else { project.end = dt.with_timezone(&UTC); }
+                },
+                _ => { errors.push(format!("{} must be an RFC 3339 time", field)); }
+            }
+        }
+    }
+
+    // null goes back to the configured units
+    if let Some(units) = json.find("units") {
+        match units {
+            &Json::Null => { project.units = None; },
+            u => match u.as_string().and_then(TemperatureUnit::from_str) {
+                Some(u) => { project.units = Some(u); },
+                None => { errors.push("units must be \"C\", \"F\" or null".to_string()); }
+            }
+        }
+    }
+
+    if let Some(probes) = json.find("probes") {
+        let list = match probes.as_array() {
+            Some(l) => l,
+            None => {
+                errors.push("probes must be a list".to_string());
+                return;
+            }
+        };
+
+        let mut result = vec![];
+
+        for (i, p) in list.iter().enumerate() {
+            let probe_id = p.find("probe_id").and_then(|id| id.as_i64());
+            let name = p.find("name").and_then(|n| n.as_string());
+            let role = match p.find("role") {
+                None => Some(ProbeRole::Meat),
+                Some(r) => r.as_string().and_then(ProbeRole::from_str)
+            };
+
+            match (probe_id, name, role) {
+                (Some(id), Some(name), Some(role)) if known_probes.iter().any(|k| k.id == id) && name.trim().len() > 0 => {
+                    let mut pp = ProjectProbe::new(id, name.trim(), i as i64 + 1);
+                    pp.role = role;
+                    result.push(pp);
+                },
+                _ => { errors.push(format!("probes[{}] needs a known probe_id, a name, and a role of meat or pit", i)); }
+            }
+        }
+
+        project.probes = result;
+    }
+
+    if project.probes.is_empty() {
+        errors.push("a project needs at least one probe".to_string());
+    }
+
+    if project.end <= project.start {
+        errors.push("end must be after start".to_string());
+    }
+}
+
+fn save_project_json(request: &mut Request, mut project: Project) -> IronResult<Project> {
+    let json = try!(parse_json_body(request));
+    let conn = try!(get_connection(request));
+    let known_probes = try!(db_unwrap(sql::get_probes(&conn)));
+
+    let mut errors = vec![];
+    assign_project_json(&mut project, &json, &known_probes, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(IronError::new(WebError::new(&errors.join("; ")), status::UnprocessableEntity));
+    }
+
+    if project.id == 0 {
+        try!(db_unwrap(sql::insert_project(&conn, &mut project)));
+    } else {
+        project.updated_at = UTC::now();
+        try!(db_unwrap(sql::update_project(&conn, &mut project)));
+    }
+
+    Ok(project)
+}
+
+pub fn projects(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let projects = try!(db_unwrap(sql::get_projects(&conn)));
+
+    let mut m = BTreeMap::new();
+    m.insert("projects".to_string(), projects.to_json());
+    json_response(status::Ok, m.to_json())
+}
+
+pub fn project(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    json_response(status::Ok, project.to_json())
+}
+
+pub fn create_project(request: &mut Request) -> IronResult<Response> {
+    let project = try!(save_project_json(request, Project::default()));
+
+    let mut response = try!(json_response(status::Created, project.to_json()));
+    response.headers.set(headers::Location(format!("/api/v1/projects/{}", project.id)));
+    Ok(response)
+}
+
+pub fn update_project(request: &mut Request) -> IronResult<Response> {
+    let project = {
+        let conn = try!(get_connection(request));
+        try!(get_project_from_route(request, &conn))
+    };
+
+    let project = try!(save_project_json(request, project));
+    json_response(status::Ok, project.to_json())
+}
+
+pub fn delete_project(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    try!(db_unwrap(sql::delete_project(&conn, project.id)));
+
+    Ok(Response::with(status::NoContent))
+}
+
+pub fn project_annotations(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let annotations = try!(db_unwrap(sql::get_project_annotations(&conn, project.id)));
+
+    let mut m = BTreeMap::new();
+    m.insert("annotations".to_string(), annotations.to_json());
+    json_response(status::Ok, m.to_json())
+}
+
+// Takes {"text": ..., "at": ...}, where no time means now
+pub fn create_annotation(request: &mut Request) -> IronResult<Response> {
+    let json = try!(parse_json_body(request));
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    let text = json.find("text").and_then(|j| j.as_string());
+    let at = json.find("at").and_then(|j| j.as_string());
+
+    let mut annotation = try!(web_handlers::build_annotation(&project, text, at));
+    try!(db_unwrap(sql::insert_annotation(&conn, &mut annotation)));
+
+    let mut response = try!(json_response(status::Created, annotation.to_json()));
+    response.headers.set(headers::Location(format!("/api/v1/projects/{}/annotations/{}", project.id, annotation.id)));
+    Ok(response)
+}
+
+pub fn delete_annotation(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    try!(web_handlers::remove_annotation(request, &conn, &project));
+
+    Ok(Response::with(status::NoContent))
+}
+
+// Readings from any time range: ?from=&to= (RFC 3339, either left open), paged with ?after_id=&limit=,
+// in ?units=
+pub fn readings(request: &mut Request) -> IronResult<Response> {
+    let query = try!(parse_query(request));
+    let from = try!(parse_optional_date(&query, "from"));
+    let to = try!(parse_optional_date(&query, "to"));
+    let (after_id, limit) = try!(parse_page(&query));
+    let units = try!(parse_units(request, &query));
+
+    let conn = try!(get_connection(request));
+    let mut readings = try!(db_unwrap(sql::get_readings_between(&conn, from, to, after_id, limit)));
+
+    for reading in readings.iter_mut() {
+        for v in reading.values.iter_mut() {
+            v.value = v.value.map(|c| units.from_celsius(c));
+        }
+    }
+
+    // a full page means there may be more
+    let next = if readings.len() as u32 == limit { readings.last().map(|r| r.id) } else { None };
+
+    let mut m = BTreeMap::new();
+    m.insert("readings".to_string(), readings.to_json());
+    m.insert("units".to_string(), units.as_str().to_json());
+    m.insert("next_after_id".to_string(), next.to_json());
+    json_response(status::Ok, m.to_json())
+}
+
+// The connection history, with the same parameters as readings
+pub fn connection_statuses(request: &mut Request) -> IronResult<Response> {
+    let query = try!(parse_query(request));
+    let from = try!(parse_optional_date(&query, "from"));
+    let to = try!(parse_optional_date(&query, "to"));
+    let (after_id, limit) = try!(parse_page(&query));
+
+    let conn = try!(get_connection(request));
+    let statuses = try!(db_unwrap(sql::get_connection_statuses(&conn, from, to, after_id, limit)));
+
+    let next = if statuses.len() as u32 == limit { statuses.last().map(|s| s.id) } else { None };
+
+    let mut m = BTreeMap::new();
+    m.insert("connection_statuses".to_string(), statuses.to_json());
+    m.insert("next_after_id".to_string(), next.to_json());
+    json_response(status::Ok, m.to_json())
+}
+
+// Each device as it is now: whether it's connected, its probes, and when it last sent a reading
+pub fn devices(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let devices = try!(db_unwrap(sql::get_devices(&conn)));
+    let probes = try!(db_unwrap(sql::get_probes(&conn)));
+    let statuses = try!(db_unwrap(sql::get_latest_connection_statuses(&conn)));
+
+    let mut list = vec![];
+
+    for device in devices.iter() {
+        let status = statuses.iter().find(|s| s.device_id == Some(device.id));
+        let device_probes: Vec<&Probe> = probes.iter().filter(|p| p.device_id == Some(device.id)).collect();
+        let last_reading = try!(db_unwrap(sql::get_latest_reading_time(&conn, device.id)));
+
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("device".to_string(), device.to_json());
+        m.insert("connected".to_string(), status.map(|s| s.is_connect).unwrap_or(false).to_json());
+        m.insert("status".to_string(), status.map(|s| s.to_json()).unwrap_or(Json::Null));
+        m.insert("probes".to_string(), device_probes.iter().map(|p| p.to_json()).collect::<Vec<Json>>().to_json());
+        m.insert("last_reading_at".to_string(), last_reading.map(|dt| dt.to_rfc3339().to_json()).unwrap_or(Json::Null));
+        list.push(m.to_json());
+    }
+
+    let mut m = BTreeMap::new();
+    m.insert("devices".to_string(), list.to_json());
+    json_response(status::Ok, m.to_json())
+}
@@ -0,0 +1,350 @@
+use iron::response::{ResponseBody, WriteBody};
+use rusqlite::{self, Connection};
+use rustc_serialize::json::{Json, ToJson};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use pibq::models;
+use pibq::sql;
+use pibq::sql::pool::SqlitePool;
+
+// How often the database is checked for new readings and statuses, in ms
+const POLL_INTERVAL: u64 = 1000;
+// Idle time before a comment is sent down a stream, so dead clients are noticed, in seconds
+const KEEPALIVE_INTERVAL: u64 = 15;
+// Most readings read at once, by the poller or for a reconnecting client's backlog
+pub const READING_PAGE: u32 = 1000;
+
+pub enum LiveEvent {
+    Reading(models::Reading),
+    Connected(bool),
+    // the alarm's project id, and its new state
+    Alarm(i64, models::AlarmEvent)
+}
+
+struct PollState {
+    reading_id: Option<i64>,
+    alarm_event_id: Option<i64>,
+    status_id: Option<i64>,
+    connected: Option<bool>
+}
+
+// Watches the database for what the harvesters write and fans it out to every open stream,
+// so each watching client costs a channel rather than its own queries
+#[derive(Clone)]
+pub struct Broadcaster {
+    subscribers: Arc<Mutex<Vec<Sender<Arc<LiveEvent>>>>>
+}
+
+impl Broadcaster {
+    pub fn start(pool: SqlitePool) -> Broadcaster {
+        let broadcaster = Broadcaster {
+            subscribers: Arc::new(Mutex::new(vec![]))
+        };
+
+        let b = broadcaster.clone();
+        thread::Builder::new().name("live".to_string()).spawn(move || {
+            b.run(pool);
+        }).unwrap();
+
+        broadcaster
+    }
+
+    pub fn subscribe(&self) -> Receiver<Arc<LiveEvent>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn publish(&self, event: LiveEvent) {
+        let event = Arc::new(event);
+        // a failed send means the stream has closed
+        self.subscribers.lock().unwrap().retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    fn run(&self, pool: SqlitePool) {
+        let mut state = PollState {
+            reading_id: None,
+            alarm_event_id: None,
+            status_id: None,
+            connected: None
+        };
+
+        // the first poll is straight away, so the ids are taken before the server is listening
+        loop {
+            match pool.get() {
+                Err(e) => println!("live updates: {}", e),
+                Ok(conn) => {
+                    if let Err(e) = self.poll(&conn, &mut state) {
+                        println!("live updates: {}", e);
+                    }
+                }
+            }
+
+            thread::sleep(Duration::from_millis(POLL_INTERVAL));
+        }
+    }
+
+    // Nobody is watching (or this is the first poll), so move up to the newest ids without reading what's between.
+    // The subscriber lock is held throughout, so anyone subscribing afterwards reads their backlog past where publishing resumes.
+    fn skip_ahead(&self, conn: &Connection, state: &mut PollState) -> rusqlite::Result<bool> {
+        let subscribers = self.subscribers.lock().unwrap();
+        if !subscribers.is_empty() && state.reading_id.is_some() && state.alarm_event_id.is_some() {
+            return Ok(false);
+        }
+
+        state.reading_id = Some(try!(sql::get_latest_reading_id(conn)));
+        state.alarm_event_id = Some(try!(sql::get_latest_alarm_event_id(conn)));
+        Ok(true)
+    }
+
+    fn poll(&self, conn: &Connection, state: &mut PollState) -> rusqlite::Result<()> {
+        if try!(self.skip_ahead(conn, state)) {
+            return Ok(());
+        }
+
+        if let Some(mut id) = state.reading_id {
+            loop {
+                let page = try!(sql::get_readings_after(conn, id, READING_PAGE));
+                let full = page.len() as u32 == READING_PAGE;
+
+                for reading in page {
+                    id = reading.id;
+                    state.reading_id = Some(id);
+                    self.publish(LiveEvent::Reading(reading));
+                }
+
+                if !full {
+                    break;
+                }
+            }
+        }
+
+        if let Some(id) = state.alarm_event_id {
+            for event in try!(sql::get_alarm_events_after(conn, id)) {
+                state.alarm_event_id = Some(event.id);
+
+                // the rule may have been deleted since
+                if let Some(rule) = try!(sql::get_alarm_rule(conn, event.rule_id)) {
+                    self.publish(LiveEvent::Alarm(rule.project_id, event));
+                }
+            }
+        }
+
+        let status_id = try!(sql::get_latest_connection_status(conn)).map(|s| s.id);
+
+        if status_id != state.status_id {
+            state.status_id = status_id;
+
+            let connected = try!(sql::get_latest_connection_statuses(conn)).iter().any(|s| s.is_connect);
+            if state.connected != Some(connected) {
+                state.connected = Some(connected);
+                self.publish(LiveEvent::Connected(connected));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// A text/event-stream body for one project. Reading events carry the reading id as their
+// event id, so a reconnecting browser's Last-Event-ID says where to resume.
+pub struct EventStream {
+    project: models::Project,
+    connected: bool,
+    last_id: i64,
+    backlog: Vec<models::Reading>,
+    // the backlog was cut short at READING_PAGE, so the client is told to reload instead
+    truncated: bool,
+    events: Receiver<Arc<LiveEvent>>
+}
+
+impl EventStream {
+    // events must be subscribed before the backlog is read, so nothing falls between them
+    pub fn new(project: models::Project, connected: bool, last_id: i64, backlog: Vec<models::Reading>, events: Receiver<Arc<LiveEvent>>) -> EventStream {
+        EventStream {
+            project: project,
+            connected: connected,
+            last_id: last_id,
+            truncated: backlog.len() as u32 >= READING_PAGE,
+            backlog: backlog,
+            events: events
+        }
+    }
+
+    fn write_reading(&mut self, out: &mut Write, reading: &models::Reading) -> io::Result<()> {
+        if reading.id <= self.last_id {
+            return Ok(());
+        }
+
+        self.last_id = reading.id;
+
+        if reading.timestamp < self.project.start || reading.timestamp > self.project.end {
+            return Ok(());
+        }
+
+        let values: Vec<Json> = reading.values.iter()
+            .filter(|v| self.project.probes.iter().any(|p| p.probe_id == v.probe_id))
+            .map(|v| (v.probe_id, v.value).to_json())
+            .collect();
+
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let mut json = reading.to_json();
+        if let Json::Object(ref mut m) = json {
+            m.insert("values".to_string(), values.to_json());
+        }
+
+        write!(out, "id: {}\nevent: reading\ndata: {}\n\n", reading.id, json)
+    }
+}
+
+fn write_status(out: &mut Write, connected: bool) -> io::Result<()> {
+    write!(out, "event: status\ndata: {{\"connected\":{}}}\n\n", connected)
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        try!(write_status(res, self.connected));
+
+        // too far behind to catch up here; the page fetches what it's missing as data.json and reconnects
+        if self.truncated {
+            try!(res.write_all(b"event: reload\ndata: {}\n\n"));
+            return res.flush();
+        }
+
+        let backlog = mem::replace(&mut self.backlog, vec![]);
+        for reading in backlog.iter() {
+            try!(self.write_reading(res, reading));
+        }
+
+        try!(res.flush());
+
+        // runs until a write fails because the client has gone
+        loop {
+            match self.events.recv_timeout(Duration::from_secs(KEEPALIVE_INTERVAL)) {
+                Ok(event) => {
+                    match *event {
+                        LiveEvent::Reading(ref reading) => { try!(self.write_reading(res, reading)); },
+                        LiveEvent::Connected(connected) => { try!(write_status(res, connected)); },
+                        LiveEvent::Alarm(project_id, ref event) => {
+                            if project_id == self.project.id {
+                                try!(write!(res, "event: alarm\ndata: {}\n\n", event.to_json()));
+                            }
+                        }
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => { try!(res.write_all(b": keepalive\n\n")); },
+                Err(RecvTimeoutError::Disconnected) => { return Ok(()); }
+            }
+
+            try!(res.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_reading(id: i64, probe_ids: &[i64], timestamp: ::chrono::DateTime<::chrono::offset::utc::UTC>) -> models::Reading {
+    let mut reading = models::Reading::new();
+    reading.id = id;
+    reading.timestamp = timestamp;
+    for &probe_id in probe_ids {
+        reading.values.push(models::ProbeValue { probe_id: probe_id, value: Some(probe_id as f64) });
+    }
+    reading
+}
+
+#[test]
+fn test_event_stream_format() {
+    use chrono::duration::Duration;
+
+    let mut project = models::Project::default();
+    project.probes.push(models::ProjectProbe::new(1, "Brisket", 1));
+    let start = project.start;
+    let (_, rx) = mpsc::channel();
+    let mut stream = EventStream::new(project, true, 5, vec![], rx);
+    assert!(!stream.truncated);
+
+    let mut out: Vec<u8> = vec![];
+    write_status(&mut out, false).unwrap();
+    assert_eq!("event: status\ndata: {\"connected\":false}\n\n", String::from_utf8(out).unwrap());
+
+    // only the project's probes are sent
+    let mut out: Vec<u8> = vec![];
+    stream.write_reading(&mut out, &test_reading(6, &[1, 2], start + Duration::minutes(1))).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with("id: 6\nevent: reading\ndata: {"));
+    assert!(text.ends_with("\n\n"));
+    let data = Json::from_str(&text.lines().nth(2).unwrap()["data: ".len()..]).unwrap();
+    assert_eq!("[[1,1.0]]", data.find("values").unwrap().to_string());
+
+    // already sent, outside the window, or none of the project's probes
+    let mut out: Vec<u8> = vec![];
+    stream.write_reading(&mut out, &test_reading(6, &[1], start + Duration::minutes(1))).unwrap();
+    stream.write_reading(&mut out, &test_reading(7, &[1], start - Duration::minutes(1))).unwrap();
+    stream.write_reading(&mut out, &test_reading(8, &[2], start + Duration::minutes(2))).unwrap();
+    assert!(out.is_empty());
+    assert_eq!(8, stream.last_id);
+
+    let (_, rx) = mpsc::channel();
+    let backlog = (0..READING_PAGE as i64).map(|i| test_reading(i + 1, &[1], start)).collect();
+    assert!(EventStream::new(models::Project::default(), true, 0, backlog, rx).truncated);
+}
+
+#[test]
+fn test_broadcaster_poll() {
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let device = sql::find_or_create_device(&conn, "1234", "/dev/null").unwrap();
+    let probe = sql::find_or_create_probe(&conn, Some(device.id), 1, "Probe 1").unwrap();
+    let insert = |value: f64| {
+        let mut reading = models::Reading::new();
+        reading.device_id = Some(device.id);
+        reading.values.push(models::ProbeValue { probe_id: probe.id, value: Some(value) });
+        sql::insert_reading(&conn, &mut reading).unwrap();
+        reading.id
+    };
+
+    let broadcaster = Broadcaster { subscribers: Arc::new(Mutex::new(vec![])) };
+    let mut state = PollState { reading_id: None, alarm_event_id: None, status_id: None, connected: None };
+    let readings = |rx: &Receiver<Arc<LiveEvent>>| {
+        let mut ids = vec![];
+        while let Ok(event) = rx.try_recv() {
+            if let LiveEvent::Reading(ref r) = *event {
+                ids.push(r.id);
+            }
+        }
+        ids
+    };
+
+    // nobody is watching, so this is skipped rather than published
+    let skipped = insert(1.0);
+    broadcaster.poll(&conn, &mut state).unwrap();
+    assert_eq!(Some(skipped), state.reading_id);
+
+    let rx = broadcaster.subscribe();
+    let first = insert(2.0);
+    let second = insert(3.0);
+    broadcaster.poll(&conn, &mut state).unwrap();
+    assert_eq!(vec![first, second], readings(&rx));
+
+    // the cursor survives polls with nothing new
+    broadcaster.poll(&conn, &mut state).unwrap();
+    let third = insert(4.0);
+    broadcaster.poll(&conn, &mut state).unwrap();
+    assert_eq!(vec![third], readings(&rx));
+
+    // a closed stream is dropped when it's next sent to, and then the poller goes back to skipping
+    drop(rx);
+    insert(5.0);
+    broadcaster.poll(&conn, &mut state).unwrap();
+    assert!(broadcaster.subscribers.lock().unwrap().is_empty());
+    let idle = insert(6.0);
+    broadcaster.poll(&conn, &mut state).unwrap();
+    assert_eq!(Some(idle), state.reading_id);
+}
@@ -1,8 +1,17 @@
 use iron::typemap::Key;
+use pibq::config::Config;
 use pibq::sql::pool;
 
+pub mod api_handlers;
+pub mod live;
 pub mod view_models;
 pub mod web_handlers;
 
 pub struct AppDb;
 impl Key for AppDb { type Value = pool::SqlitePool; }
+
+pub struct AppLive;
+impl Key for AppLive { type Value = live::Broadcaster; }
+
+pub struct AppConfig;
+impl Key for AppConfig { type Value = Config; }
@@ -1,6 +1,7 @@
 use rustc_serialize::json::{Json, ToJson};
 use std::collections::BTreeMap;
 
+use pibq::analytics::Eta;
 use pibq::models::{self, DbObject};
 
 #[derive(RustcEncodable, RustcDecodable)]
@@ -31,11 +32,12 @@ impl ToJson for ProjectIndex {
 pub struct ProjectEdit {
     pub title: String,
     pub project: models::Project,
+    pub probes: Vec<models::Probe>,
     pub errors: Vec<String>
 }
 
 impl ProjectEdit {
-    pub fn new(title: &str, project: Option<models::Project>, errors: Vec<String>) -> ProjectEdit {
+    pub fn new(title: &str, project: Option<models::Project>, probes: Vec<models::Probe>, errors: Vec<String>) -> ProjectEdit {
         let project = match project {
             Some(p) => p,
             None => models::Project::default()
@@ -44,6 +46,7 @@ impl ProjectEdit {
         ProjectEdit {
             title: title.to_string(),
             project: project,
+            probes: probes,
             errors: errors
         }
     }
@@ -55,6 +58,25 @@ impl ProjectEdit {
     pub fn has_errors(&self) -> bool {
         self.errors.len() > 0
     }
+
+    // Every known probe, with the name the project gives it (blank if it isn't part of the project)
+    fn probe_choices(&self) -> Json {
+        let choices: Vec<Json> = self.probes.iter().map(|probe| {
+            let (name, role) = match self.project.probes.iter().find(|pp| pp.probe_id == probe.id) {
+                Some(pp) => (pp.name.clone(), pp.role),
+                None => ("".to_string(), models::ProbeRole::Meat)
+            };
+
+            let mut m: BTreeMap<String, Json> = BTreeMap::new();
+            m.insert("id".to_string(), probe.id.to_json());
+            m.insert("label".to_string(), probe.name.to_json());
+            m.insert("name".to_string(), name.to_json());
+            m.insert("is_pit".to_string(), (role == models::ProbeRole::Pit).to_json());
+            m.to_json()
+        }).collect();
+
+        choices.to_json()
+    }
 }
 
 impl ToJson for ProjectEdit {
@@ -62,6 +84,9 @@ impl ToJson for ProjectEdit {
         let mut m: BTreeMap<String, Json> = BTreeMap::new();
         m.insert("title".to_string(), self.title.to_json());
         m.insert("project".to_string(), self.project.to_json());
+        m.insert("probes".to_string(), self.probe_choices());
+        m.insert("units_c".to_string(), (self.project.units == Some(models::TemperatureUnit::Celsius)).to_json());
+        m.insert("units_f".to_string(), (self.project.units == Some(models::TemperatureUnit::Fahrenheit)).to_json());
         m.insert("errors".to_string(), self.errors.to_json());
         m.insert("is_new".to_string(), self.is_new().to_json());
         m.insert("has_errors".to_string(), self.has_errors().to_json());
@@ -69,19 +94,123 @@ impl ToJson for ProjectEdit {
     }
 }
 
+// Alarm thresholds are stored in C; the pages show the project's units
+pub fn threshold_to_display(kind: models::AlarmKind, units: models::TemperatureUnit, c: f64) -> f64 {
+    match kind {
+        models::AlarmKind::Rate => units.delta_from_celsius(c),
+        _ => units.from_celsius(c)
+    }
+}
+
+pub fn threshold_from_display(kind: models::AlarmKind, units: models::TemperatureUnit, v: f64) -> f64 {
+    match kind {
+        models::AlarmKind::Rate => units.delta_to_celsius(v),
+        _ => units.to_celsius(v)
+    }
+}
+
+fn describe_alarm(rule: &models::AlarmRule, units: models::TemperatureUnit) -> String {
+    let t = threshold_to_display(rule.kind, units, rule.threshold);
+    let symbol = units.symbol();
+
+    match rule.kind {
+        models::AlarmKind::Above => format!("goes above {:.1}{}", t, symbol),
+        models::AlarmKind::Below => format!("goes below {:.1}{}", t, symbol),
+        models::AlarmKind::Target => format!("reaches {:.1}{}", t, symbol),
+        models::AlarmKind::Rate => format!("changes faster than {:.1}{}/min", t, symbol)
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct ProjectShow {
+    pub title: String,
+    pub project: models::Project,
+    pub alarms: Vec<models::AlarmRule>,
+    pub notifiers: Vec<models::ProjectNotifier>,
+    pub annotations: Vec<models::Annotation>,
+    // the project's units, or the configured ones
+    pub units: models::TemperatureUnit,
+    // whether email can be chosen, and which commands can be
+    pub email_enabled: bool,
+    pub commands: Vec<String>
+}
+
+impl ProjectShow {
+    pub fn new(title: &str, project: models::Project, alarms: Vec<models::AlarmRule>, notifiers: Vec<models::ProjectNotifier>,
+               annotations: Vec<models::Annotation>, units: models::TemperatureUnit, email_enabled: bool, commands: Vec<String>) -> Self {
+        ProjectShow {
+            title: title.to_string(),
+            project: project,
+            alarms: alarms,
+            notifiers: notifiers,
+            annotations: annotations,
+            units: units,
+            email_enabled: email_enabled,
+            commands: commands
+        }
+    }
+
+    fn alarm_json(&self, rule: &models::AlarmRule) -> Json {
+        let probe_name = match self.project.probes.iter().find(|p| p.probe_id == rule.probe_id) {
+            Some(p) => p.name.clone(),
+            None => "".to_string()
+        };
+
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), rule.id.to_json());
+        m.insert("project_id".to_string(), rule.project_id.to_json());
+        m.insert("probe_name".to_string(), probe_name.to_json());
+        m.insert("kind".to_string(), rule.kind.as_str().to_json());
+        m.insert("threshold".to_string(), format!("{:.1}", threshold_to_display(rule.kind, self.units, rule.threshold)).to_json());
+        m.insert("description".to_string(), describe_alarm(rule, self.units).to_json());
+        m.insert("state".to_string(), rule.state.as_str().to_json());
+        m.insert("is_triggered".to_string(), (rule.state == models::AlarmState::Triggered).to_json());
+        m.to_json()
+    }
+}
+
+impl ToJson for ProjectShow {
+    fn to_json(&self) -> Json {
+        let alarms: Vec<Json> = self.alarms.iter().map(|a| self.alarm_json(a)).collect();
+
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("title".to_string(), self.title.to_json());
+        m.insert("project".to_string(), self.project.to_json());
+        m.insert("alarms".to_string(), alarms.to_json());
+        m.insert("any_triggered".to_string(), self.alarms.iter().any(|a| a.state == models::AlarmState::Triggered).to_json());
+        m.insert("notifiers".to_string(), self.notifiers.to_json());
+        m.insert("annotations".to_string(), self.annotations.to_json());
+        m.insert("units".to_string(), self.units.as_str().to_json());
+        m.insert("email_enabled".to_string(), self.email_enabled.to_json());
+        m.insert("commands".to_string(), self.commands.to_json());
+        m.to_json()
+    }
+}
+
 #[derive(RustcEncodable, RustcDecodable)]
 pub struct ProjectReadings {
     project: models::Project,
     connected: bool,
-    readings: Vec<models::Reading>
+    last_reading_id: i64,
+    probes: Vec<models::ProbeSeries>,
+    etas: Vec<Eta>,
+    events: Vec<models::ProjectEvent>,
+    annotations: Vec<models::Annotation>,
+    units: models::TemperatureUnit
 }
 
 impl ProjectReadings {
-    pub fn new(project: models::Project, connected: bool, readings: Vec<models::Reading>) -> Self {
+    pub fn new(project: models::Project, connected: bool, last_reading_id: i64, probes: Vec<models::ProbeSeries>,
+               etas: Vec<Eta>, events: Vec<models::ProjectEvent>, annotations: Vec<models::Annotation>, units: models::TemperatureUnit) -> Self {
         ProjectReadings {
             project: project,
             connected: connected,
-            readings: readings
+            last_reading_id: last_reading_id,
+            probes: probes,
+            etas: etas,
+            events: events,
+            annotations: annotations,
+            units: units
         }
     }
 }
@@ -90,7 +219,13 @@ impl ToJson for ProjectReadings {
     fn to_json(&self) -> Json {
         let mut m: BTreeMap<String, Json> = BTreeMap::new();
         m.insert("connected".to_string(), self.connected.to_json());
-        m.insert("readings".to_string(), self.readings.to_json());
+        m.insert("last_reading_id".to_string(), self.last_reading_id.to_json());
+        m.insert("probes".to_string(), self.probes.to_json());
+        m.insert("etas".to_string(), self.etas.to_json());
+        m.insert("events".to_string(), self.events.to_json());
+        m.insert("annotations".to_string(), self.annotations.to_json());
+        // the series and ETAs are in C; this is what the page shows them in
+        m.insert("units".to_string(), self.units.as_str().to_json());
         m.to_json()
     }
 }
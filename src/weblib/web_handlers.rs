@@ -1,36 +1,46 @@
 use chrono::datetime::DateTime;
-use chrono::offset::TimeZone;
-use chrono::offset::local::Local;
+use chrono::duration::Duration;
+use chrono::offset::utc::UTC;
 use handlebars_iron::Template;
 use iron::headers;
+use iron::mime::Mime;
 use iron::modifiers::{Header};
+use iron::response::{ResponseBody, WriteBody};
 use iron::prelude::*;
 use iron::status;
 use persistent::{self};
 use router::Router;
 use rusqlite;
 use rustc_serialize;
-use rustc_serialize::json::{ToJson};
-use std::collections::HashMap;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt;
-use std::io::Read;
+use std::io::{self, Read};
+use std::str;
+use std::sync::Arc;
 use url;
 
 
+use pibq::analytics::{self, Eta};
+use pibq::config::Config;
+use pibq::export::ProjectExport;
+use pibq::notify;
 use pibq::sql;
 use pibq::sql::pool::{SqlitePooledConnection};
-use pibq::models::{Project};
+use pibq::models::{Annotation, AlarmEvent, AlarmKind, AlarmRule, AlarmState, NotifierKind, ProbeRole, Project, ProjectNotifier, ProjectProbe, TemperatureUnit};
 use super::view_models;
-use super::AppDb;
+use super::live::{self, EventStream};
+use super::{AppConfig, AppDb, AppLive};
 
 #[derive(Clone, Debug)]
-struct WebError {
+pub struct WebError {
     msg: String
 }
 
 impl WebError {
-    fn new(msg: &str) -> WebError {
+    pub fn new(msg: &str) -> WebError {
         WebError {
             msg: msg.to_string()
         }
@@ -50,7 +60,7 @@ impl fmt::Display for WebError {
 }
 
 
-fn get_connection(request: &mut Request) -> IronResult<SqlitePooledConnection> {
+pub fn get_connection(request: &mut Request) -> IronResult<SqlitePooledConnection> {
     let pool = match request.get::<persistent::Read<AppDb>>() {
         Err(e) => return Err(IronError::new(e, status::InternalServerError)),
         Ok(p) => p
@@ -62,17 +72,25 @@ fn get_connection(request: &mut Request) -> IronResult<SqlitePooledConnection> {
     }
 }
 
-fn db_unwrap<T>(result: rusqlite::Result<T>) -> IronResult<T> {
+pub fn get_config(request: &mut Request) -> IronResult<Arc<Config>> {
+    match request.get::<persistent::Read<AppConfig>>() {
+        Err(e) => Err(IronError::new(e, status::InternalServerError)),
+        Ok(c) => Ok(c)
+    }
+}
+
+pub fn db_unwrap<T>(result: rusqlite::Result<T>) -> IronResult<T> {
     match result {
         Err(e) => Err(IronError::new(e, status::InternalServerError)),
         Ok(r) => Ok(r)
     }
 }
 
-fn parse_date(str: &str) -> IronResult<DateTime<Local>> {
-    match Local.datetime_from_str(str, "%Y-%m-%d %H:%M:%S") {
-        Err(e) => Err(IronError::new(e, status::InternalServerError)),
-        Ok(dt) => Ok(dt)
+// Dates from the client are RFC 3339 with an offset, so they mean the same instant whatever the server's timezone
+pub fn parse_date(str: &str) -> IronResult<DateTime<UTC>> {
+    match DateTime::parse_from_rfc3339(str) {
+        Err(e) => Err(IronError::new(e, status::BadRequest)),
+        Ok(dt) => Ok(dt.with_timezone(&UTC))
     }
 }
 
@@ -101,7 +119,20 @@ fn parse_body(request: &mut Request) -> IronResult<HashMap<String, String>> {
     Ok(map)
 }
 
-fn parse_query(request: &mut Request) -> IronResult<HashMap<String, String>> {
+pub fn parse_json_body(request: &mut Request) -> IronResult<Json> {
+    let mut body = String::new();
+    match request.body.read_to_string(&mut body) {
+        Err(e) => return Err(IronError::new(e, status::BadRequest)),
+        Ok(_) => {}
+    };
+
+    match Json::from_str(&body) {
+        Err(e) => Err(IronError::new(e, status::BadRequest)),
+        Ok(json) => Ok(json)
+    }
+}
+
+pub fn parse_query(request: &mut Request) -> IronResult<HashMap<String, String>> {
     let mut map = HashMap::new();
 
     let url = match url::Url::parse(&format!("{}", request.url)) {
@@ -117,12 +148,12 @@ fn parse_query(request: &mut Request) -> IronResult<HashMap<String, String>> {
     Ok(map)
 }
 
-fn get_project_from_route(request: &mut Request, conn: &rusqlite::Connection) -> IronResult<Project> {
+pub fn get_project_from_route(request: &mut Request, conn: &rusqlite::Connection) -> IronResult<Project> {
     let mut project = None;
     match request.extensions.get::<Router>().unwrap().find("id") {
         Some(id) => {
             match id.parse::<i64>() {
-                Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+                Err(e) => return Err(IronError::new(e, status::NotFound)),
                 Ok(id) => {
                     project = match sql::get_project(&conn, id) {
                         Err(e) => return Err(IronError::new(e, status::InternalServerError)),
@@ -144,6 +175,48 @@ fn get_project_from_route(request: &mut Request, conn: &rusqlite::Connection) ->
     }
 }
 
+fn get_alarm_from_route(request: &mut Request, conn: &rusqlite::Connection, project: &Project) -> IronResult<AlarmRule> {
+    let rule = match request.extensions.get::<Router>().unwrap().find("alarm_id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) => try!(db_unwrap(sql::get_alarm_rule(conn, id))),
+        _ => None
+    };
+
+    match rule {
+        Some(r) if r.project_id == project.id => Ok(r),
+        _ => Err(IronError::new(WebError::new("not found"), status::NotFound))
+    }
+}
+
+// ETAs are estimated from this many hours of readings, which covers the stall and the climb into it
+const ETA_HISTORY_HOURS: i64 = 6;
+
+// When each probe with a "reaches" alarm should get there
+fn get_project_etas(conn: &rusqlite::Connection, project: &Project) -> IronResult<Vec<Eta>> {
+    let now = UTC::now();
+
+    let rules = try!(db_unwrap(sql::get_project_alarm_rules(conn, project.id)));
+    let targets: Vec<AlarmRule> = rules.into_iter().filter(|r| r.kind == AlarmKind::Target).collect();
+
+    if targets.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let after = max(project.start, now - Duration::hours(ETA_HISTORY_HOURS));
+    let series = try!(db_unwrap(sql::get_project_readings(conn, project, Some(after))));
+
+    let mut etas = vec![];
+
+    for rule in targets.iter() {
+        if let Some(s) = series.iter().find(|s| s.probe_id == rule.probe_id) {
+            if let Some(eta) = analytics::estimate(s, rule.threshold, now) {
+                etas.push(eta);
+            }
+        }
+    }
+
+    Ok(etas)
+}
+
 fn assign_project_fields(project: &mut Project, data: &mut HashMap<String, String>, errors: &mut Vec<String>) {
     match data.remove("name") {
         Some(ref str) if str.len() > 0 => {
@@ -154,24 +227,38 @@ fn assign_project_fields(project: &mut Project, data: &mut HashMap<String, Strin
         }
     }
 
-    match data.remove("sensor1_name") {
-        Some(ref str) if str.len() > 0 => {
-            project.sensor1_name = str.to_string();
+    // probes come in as probe_<id>=name; a blank name leaves the probe out of the project
+    let mut probes: Vec<ProjectProbe> = vec![];
+    for (key, value) in data.iter() {
+        if !key.starts_with("probe_") {
+            continue;
         }
-        _ => {
-            errors.push("Invalid sensor1_name".to_string());
+
+        match key["probe_".len()..].parse::<i64>() {
+            Err(_) => { errors.push(format!("Invalid probe {}", key)); },
+            Ok(_) if value.trim().len() == 0 => {},
+            Ok(id) => { probes.push(ProjectProbe::new(id, value.trim(), 0)); }
         }
     }
 
-    match data.remove("sensor2_name") {
-        Some(ref str) if str.len() > 0 => {
-            project.sensor2_name = str.to_string();
-        }
-        _ => {
-            errors.push("Invalid sensor2_name".to_string());
+    probes.sort_by_key(|p| p.probe_id);
+    for (i, probe) in probes.iter_mut().enumerate() {
+        probe.position = i as i64 + 1;
+
+        // role_<id> says whether the probe is in the meat or the pit
+        match data.get(&format!("role_{}", probe.probe_id)).map(|r| ProbeRole::from_str(r)) {
+            None => {},
+            Some(Some(role)) => { probe.role = role; },
+            Some(None) => { errors.push(format!("Invalid role for {}", probe.name)); }
         }
     }
 
+    if probes.len() == 0 {
+        errors.push("Name at least one probe".to_string());
+    }
+
+    project.probes = probes;
+
     match data.remove("start") {
         None => { errors.push("Invalid start".to_string()); },
         Some(str) => {
@@ -192,6 +279,18 @@ fn assign_project_fields(project: &mut Project, data: &mut HashMap<String, Strin
         }
     }
 
+    // blank uses the configured units
+    match data.remove("units") {
+        None => {},
+        Some(ref str) if str.len() == 0 => { project.units = None; },
+        Some(str) => {
+            match TemperatureUnit::from_str(&str) {
+                None => { errors.push("Invalid units".to_string()); },
+                Some(u) => { project.units = Some(u); }
+            }
+        }
+    }
+
 }
 
 pub fn projects_index(request: &mut Request) -> IronResult<Response> {
@@ -202,8 +301,10 @@ pub fn projects_index(request: &mut Request) -> IronResult<Response> {
     render_template("projects", model)
 }
 
-pub fn new_project(_: &mut Request) -> IronResult<Response> {
-    let model = view_models::ProjectEdit::new("Create Project", None, vec![]);
+pub fn new_project(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let probes = try!(db_unwrap(sql::get_probes(&conn)));
+    let model = view_models::ProjectEdit::new("Create Project", None, probes, vec![]);
 
     render_template("edit_project", model)
 }
@@ -216,11 +317,13 @@ pub fn create_project(request: &mut Request) -> IronResult<Response> {
 
     assign_project_fields(&mut project, &mut data, &mut errors);
 
+    let conn = try!(get_connection(request));
+
     if errors.len() > 0 {
-        let model = view_models::ProjectEdit::new("Create Project", Some(project), errors);
+        let probes = try!(db_unwrap(sql::get_probes(&conn)));
+        let model = view_models::ProjectEdit::new("Create Project", Some(project), probes, errors);
         return render_template("edit_project", model);
     } else {
-        let conn = try!(get_connection(request));
         try!(db_unwrap(sql::insert_project(&conn, &mut project)));
         return redirect("/");
     }
@@ -230,7 +333,16 @@ pub fn show_project(request: &mut Request) -> IronResult<Response> {
     let conn = try!(get_connection(request));
     let project = try!(get_project_from_route(request, &conn));
 
-    let model = view_models::ProjectEdit::new("Project", Some(project), vec![]);
+    let config = try!(get_config(request));
+
+    let alarms = try!(db_unwrap(sql::get_project_alarm_rules(&conn, project.id)));
+    let notifiers = try!(db_unwrap(sql::get_project_notifiers(&conn, project.id)));
+    let annotations = try!(db_unwrap(sql::get_project_annotations(&conn, project.id)));
+
+    let units = project.units.unwrap_or(config.units);
+
+    let model = view_models::ProjectShow::new("Project", project, alarms, notifiers, annotations, units,
+                                              config.alerts.smtp_server.is_some(), config.alerts.commands.clone());
     render_template("show_project", model)
 }
 
@@ -238,7 +350,8 @@ pub fn edit_project(request: &mut Request) -> IronResult<Response> {
     let conn = try!(get_connection(request));
     let project = try!(get_project_from_route(request, &conn));
 
-    let model = view_models::ProjectEdit::new("Edit Project", Some(project), vec![]);
+    let probes = try!(db_unwrap(sql::get_probes(&conn)));
+    let model = view_models::ProjectEdit::new("Edit Project", Some(project), probes, vec![]);
     render_template("edit_project", model)
 }
 
@@ -252,10 +365,11 @@ pub fn update_project(request: &mut Request) -> IronResult<Response> {
     assign_project_fields(&mut project, &mut data, &mut errors);
 
     if errors.len() > 0 {
-        let model = view_models::ProjectEdit::new("Edit Project", Some(project), errors);
+        let probes = try!(db_unwrap(sql::get_probes(&conn)));
+        let model = view_models::ProjectEdit::new("Edit Project", Some(project), probes, errors);
         return render_template("edit_project", model);
     } else {
-        project.updated_at = Local::now();
+        project.updated_at = UTC::now();
         try!(db_unwrap(sql::update_project(&conn, &mut project)));
         return redirect("/");
     }
@@ -280,15 +394,38 @@ pub fn project_data(request: &mut Request) -> IronResult<Response> {
         }
     };
 
-    let readings = try!(db_unwrap(sql::get_project_readings(&conn, &project, after)));
-    let status = try!(db_unwrap(sql::get_latest_connection_status(&conn)));
+    // max_points asks for the readings averaged down to about that many points per probe
+    let max_points = match query.remove("max_points") {
+        None => { None },
+        Some(ref str) if str.len() == 0 => { None },
+        Some(str) => {
+            match str.parse::<u32>() {
+                Err(e) => { return Err(IronError::new(e, status::BadRequest)) },
+                Ok(0) => { return Err(IronError::new(WebError::new("max_points must be positive"), status::BadRequest)) },
+                Ok(n) => { Some(n) }
+            }
+        }
+    };
+
+    // read first, so a stream opened after this picks up anything that arrives while the series are read
+    let last_reading_id = try!(db_unwrap(sql::get_latest_reading_id(&conn)));
 
-    let connected = match status {
-        Some(s) => s.is_connect,
-        None => false
+    let readings = match max_points {
+        None => try!(db_unwrap(sql::get_project_readings(&conn, &project, after))),
+        Some(n) => try!(db_unwrap(sql::get_project_readings_downsampled(&conn, &project, after, n)))
     };
+    let statuses = try!(db_unwrap(sql::get_latest_connection_statuses(&conn)));
+    let etas = try!(get_project_etas(&conn, &project));
+    let events = try!(db_unwrap(sql::get_project_events(&conn, project.id)));
+    let annotations = try!(db_unwrap(sql::get_project_annotations(&conn, project.id)));
+
+    // connected as long as any thermometer is
+    let connected = statuses.iter().any(|s| s.is_connect);
 
-    let model = view_models::ProjectReadings::new(project, connected, readings);
+    let config = try!(get_config(request));
+    let units = project.units.unwrap_or(config.units);
+
+    let model = view_models::ProjectReadings::new(project, connected, last_reading_id, readings, etas, events, annotations, units);
     let jsonstr = match rustc_serialize::json::encode(&model.to_json()) {
         Err(e) => return Err(IronError::new(e, status::InternalServerError)),
         Ok(str) => str
@@ -297,3 +434,313 @@ pub fn project_data(request: &mut Request) -> IronResult<Response> {
     resp.set_mut(jsonstr).set_mut(status::Ok);
     Ok(resp)
 }
+
+// Server-sent events for a project: its new readings and connection changes, as they're recorded
+pub fn project_stream(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    let broadcaster = match request.get::<persistent::Read<AppLive>>() {
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        Ok(b) => b
+    };
+
+    // a reconnecting browser sends Last-Event-ID; the page's first connection passes the id its data.json ended at
+    let header_id = request.headers.get_raw("Last-Event-ID")
+        .and_then(|raw| raw.first())
+        .and_then(|v| str::from_utf8(v).ok())
+        .and_then(|s| s.trim().parse::<i64>().ok());
+
+    let last_id = match header_id {
+        Some(id) => Some(id),
+        None => {
+            let mut query = try!(parse_query(request));
+            match query.remove("last_id") {
+                None => None,
+                Some(str) => match str.parse::<i64>() {
+                    Err(e) => return Err(IronError::new(e, status::BadRequest)),
+                    Ok(id) => Some(id)
+                }
+            }
+        }
+    };
+
+    let events = broadcaster.subscribe();
+
+    let backlog = match last_id {
+        Some(id) => try!(db_unwrap(sql::get_project_readings_after(&conn, &project, id, live::READING_PAGE))),
+        None => vec![]
+    };
+
+    let statuses = try!(db_unwrap(sql::get_latest_connection_statuses(&conn)));
+    let connected = statuses.iter().any(|s| s.is_connect);
+
+    let stream = EventStream::new(project, connected, last_id.unwrap_or(0), backlog, events);
+    let content_type: Mime = "text/event-stream".parse().unwrap();
+
+    Ok(Response::with((status::Ok,
+                       content_type,
+                       Header(headers::CacheControl(vec![headers::CacheDirective::NoCache])),
+                       Box::new(stream) as Box<WriteBody + Send>)))
+}
+
+// The export is loaded before the response starts; its CSV is then written straight to the response rather than built into a string
+struct CsvExport {
+    export: ProjectExport,
+    units: TemperatureUnit
+}
+
+impl WriteBody for CsvExport {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        self.export.write_csv(res, self.units)
+    }
+}
+
+// The project's export, and the units asked for with ?units= or else the project's own
+fn load_export(request: &mut Request) -> IronResult<(ProjectExport, TemperatureUnit)> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let config = try!(get_config(request));
+
+    let units = match try!(parse_query(request)).remove("units") {
+        None => project.units.unwrap_or(config.units),
+        Some(str) => match TemperatureUnit::from_str(&str) {
+            Some(u) => u,
+            None => return Err(IronError::new(WebError::new("units must be C or F"), status::BadRequest))
+        }
+    };
+
+    let export = try!(db_unwrap(ProjectExport::load(&conn, project)));
+    Ok((export, units))
+}
+
+// e.g. "attachment; filename=\"pork-butt.csv\""
+fn attachment(project: &Project, extension: &str) -> Vec<u8> {
+    let name: String = project.name.to_lowercase().chars()
+        .map(|c| if (c as u32) < 128 && c.is_alphanumeric() { c } else { '-' })
+        .collect();
+
+    format!("attachment; filename=\"{}.{}\"", name, extension).into_bytes()
+}
+
+// All of a project's readings, one row per time with a column per probe, plus its notes and connection gaps
+pub fn export_csv(request: &mut Request) -> IronResult<Response> {
+    let (export, units) = try!(load_export(request));
+    let disposition = attachment(&export.project, "csv");
+    let content_type: Mime = "text/csv; charset=utf-8".parse().unwrap();
+
+    let mut response = Response::with((status::Ok, content_type, Box::new(CsvExport { export: export, units: units }) as Box<WriteBody + Send>));
+    response.headers.set_raw("Content-Disposition", vec![disposition]);
+    Ok(response)
+}
+
+pub fn export_json(request: &mut Request) -> IronResult<Response> {
+    let (export, units) = try!(load_export(request));
+    let disposition = attachment(&export.project, "json");
+    let content_type: Mime = "application/json".parse().unwrap();
+
+    let mut response = Response::with((status::Ok, content_type, export.as_json(units).to_string()));
+    response.headers.set_raw("Content-Disposition", vec![disposition]);
+    Ok(response)
+}
+
+// Just the ETAs, for pages following readings over the stream
+pub fn project_etas(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let etas = try!(get_project_etas(&conn, &project));
+
+    let mut m = BTreeMap::new();
+    m.insert("etas".to_string(), etas.to_json());
+
+    let jsonstr = match rustc_serialize::json::encode(&m.to_json()) {
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        Ok(str) => str
+    };
+
+    Ok(Response::with((status::Ok, jsonstr)))
+}
+
+// The stalls and lid openings noticed so far, for pages following readings over the stream
+pub fn project_events(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let events = try!(db_unwrap(sql::get_project_events(&conn, project.id)));
+
+    let mut m = BTreeMap::new();
+    m.insert("events".to_string(), events.to_json());
+
+    let jsonstr = match rustc_serialize::json::encode(&m.to_json()) {
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        Ok(str) => str
+    };
+
+    Ok(Response::with((status::Ok, jsonstr)))
+}
+
+pub fn project_alarms(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let alarms = try!(db_unwrap(sql::get_project_alarm_rules(&conn, project.id)));
+    let notifiers = try!(db_unwrap(sql::get_project_notifiers(&conn, project.id)));
+
+    let mut m = BTreeMap::new();
+    m.insert("alarms".to_string(), alarms.to_json());
+    m.insert("notifiers".to_string(), notifiers.to_json());
+
+    let jsonstr = match rustc_serialize::json::encode(&m.to_json()) {
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        Ok(str) => str
+    };
+
+    Ok(Response::with((status::Ok, jsonstr)))
+}
+
+pub fn create_alarm(request: &mut Request) -> IronResult<Response> {
+    let mut data = try!(parse_body(request));
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    let probe_id = match data.remove("probe_id").map(|s| s.parse::<i64>()) {
+        Some(Ok(id)) if project.probes.iter().any(|p| p.probe_id == id) => id,
+        _ => return Err(IronError::new(WebError::new("invalid probe"), status::BadRequest))
+    };
+
+    let kind = match data.remove("kind").and_then(|s| AlarmKind::from_str(&s)) {
+        Some(k) => k,
+        None => return Err(IronError::new(WebError::new("invalid alarm kind"), status::BadRequest))
+    };
+
+    let config = try!(get_config(request));
+    let units = project.units.unwrap_or(config.units);
+
+    let threshold = match data.remove("threshold").map(|s| s.trim().parse::<f64>()) {
+        Some(Ok(t)) => view_models::threshold_from_display(kind, units, t),
+        _ => return Err(IronError::new(WebError::new("invalid threshold"), status::BadRequest))
+    };
+
+    let mut rule = AlarmRule::new(project.id, probe_id, kind, threshold);
+    try!(db_unwrap(sql::insert_alarm_rule(&conn, &mut rule)));
+
+    redirect(&format!("/projects/{}", project.id))
+}
+
+pub fn acknowledge_alarm(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let rule = try!(get_alarm_from_route(request, &conn, &project));
+
+    if rule.state == AlarmState::Triggered {
+        let mut event = AlarmEvent::new(rule.id, AlarmState::Acknowledged, None);
+        try!(db_unwrap(sql::insert_alarm_event(&conn, &mut event)));
+    }
+
+    redirect(&format!("/projects/{}", project.id))
+}
+
+pub fn delete_alarm(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+    let rule = try!(get_alarm_from_route(request, &conn, &project));
+
+    try!(db_unwrap(sql::delete_alarm_rule(&conn, rule.id)));
+
+    redirect(&format!("/projects/{}", project.id))
+}
+
+pub fn create_notifier(request: &mut Request) -> IronResult<Response> {
+    let mut data = try!(parse_body(request));
+    let conn = try!(get_connection(request));
+    let config = try!(get_config(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    let kind = match data.remove("kind").and_then(|s| NotifierKind::from_str(&s)) {
+        Some(k) => k,
+        None => return Err(IronError::new(WebError::new("invalid notifier kind"), status::BadRequest))
+    };
+
+    // commands come from their own select
+    let field = if kind == NotifierKind::Command { "command" } else { "target" };
+    let target = match data.remove(field) {
+        Some(ref str) if str.trim().len() > 0 => str.trim().to_string(),
+        _ => return Err(IronError::new(WebError::new("missing notifier target"), status::BadRequest))
+    };
+
+    let mut notifier = ProjectNotifier::new(project.id, kind, &target);
+
+    // refuses bad urls and addresses, email without a mail server, and commands that aren't allowed
+    if let Err(e) = notify::from_setting(&notifier, &config.alerts) {
+        return Err(IronError::new(e, status::BadRequest));
+    }
+
+    try!(db_unwrap(sql::insert_project_notifier(&conn, &mut notifier)));
+
+    redirect(&format!("/projects/{}", project.id))
+}
+
+pub fn delete_notifier(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    let id = match request.extensions.get::<Router>().unwrap().find("notifier_id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) => id,
+        _ => return Err(IronError::new(WebError::new("not found"), status::NotFound))
+    };
+
+    match sql::delete_project_notifier(&conn, project.id, id) {
+        Ok(_) => {},
+        Err(rusqlite::Error::StatementChangedRows(_)) => return Err(IronError::new(WebError::new("not found"), status::NotFound)),
+        Err(e) => return Err(IronError::new(e, status::InternalServerError))
+    }
+
+    redirect(&format!("/projects/{}", project.id))
+}
+
+// A note for the project from the submitted text and time; no time means now
+pub fn build_annotation(project: &Project, text: Option<&str>, at: Option<&str>) -> IronResult<Annotation> {
+    let text = match text {
+        Some(str) if str.trim().len() > 0 => str.trim(),
+        _ => return Err(IronError::new(WebError::new("missing annotation text"), status::BadRequest))
+    };
+
+    let timestamp = match at {
+        Some(str) if str.len() > 0 => try!(parse_date(str)),
+        _ => UTC::now()
+    };
+
+    Ok(Annotation::new(project.id, timestamp, text))
+}
+
+pub fn create_annotation(request: &mut Request) -> IronResult<Response> {
+    let data = try!(parse_body(request));
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    let mut annotation = try!(build_annotation(&project, data.get("text").map(|s| s.as_str()), data.get("at").map(|s| s.as_str())));
+    try!(db_unwrap(sql::insert_annotation(&conn, &mut annotation)));
+
+    redirect(&format!("/projects/{}", project.id))
+}
+
+// Deletes the project's annotation named in the route; the page and the API only differ in how they answer
+pub fn remove_annotation(request: &mut Request, conn: &rusqlite::Connection, project: &Project) -> IronResult<()> {
+    let id = match request.extensions.get::<Router>().unwrap().find("annotation_id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) => id,
+        _ => return Err(IronError::new(WebError::new("not found"), status::NotFound))
+    };
+
+    match sql::delete_annotation(conn, project.id, id) {
+        Ok(_) => Ok(()),
+        Err(rusqlite::Error::StatementChangedRows(_)) => Err(IronError::new(WebError::new("not found"), status::NotFound)),
+        Err(e) => Err(IronError::new(e, status::InternalServerError))
+    }
+}
+
+pub fn delete_annotation(request: &mut Request) -> IronResult<Response> {
+    let conn = try!(get_connection(request));
+    let project = try!(get_project_from_route(request, &conn));
+
+    try!(remove_annotation(request, &conn, &project));
+
+    redirect(&format!("/projects/{}", project.id))
+}
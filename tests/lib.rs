@@ -1,3 +1,5 @@
+extern crate chrono;
+extern crate rustc_serialize;
 extern crate pibq;
 use pibq::bluetherm;
 
@@ -47,3 +49,1384 @@ fn test_example_packet_crc() {
     let p = bluetherm::Packet { data: data };
     assert!(p.is_checksum_valid());
 }
+
+#[test]
+fn test_sensor_settings() {
+    let mut p = bluetherm::Packet::new();
+    p.set_sensor1_name("Pit");
+    p.set_sensor2_name("Brisket");
+    p.set_sensor1_high_limit(Some(135.0f64));
+    p.set_sensor1_low_limit(None);
+    p.set_sensor2_trim(-1.25f64);
+
+    assert_eq!("Pit", p.get_sensor1_name());
+    assert_eq!("Brisket", p.get_sensor2_name());
+    assert_eq!(Some(135.0f64), p.get_sensor1_high_limit());
+    assert_eq!(None, p.get_sensor1_low_limit());
+    assert_eq!(-1.25f64, p.get_sensor2_trim());
+
+    // neighbouring fields must not overlap
+    assert_eq!(None, p.get_sensor1_reading());
+    assert_eq!("", p.get_serial_number());
+}
+
+#[test]
+fn test_decode_honors_flags() {
+    let mut p = bluetherm::Packet::new();
+    p.set_data_flags(bluetherm::data_flags::SERIAL_NUMBER | bluetherm::data_flags::SENSOR_1_TEMPERATURE | bluetherm::data_flags::FIRMWARE_VERSION);
+    p.set_serial_number("1234567");
+    p.set_sensor1_reading(Some(50.0f64));
+    p.set_sensor2_reading(Some(75.0f64));
+    p.set_firmware_version(0x0102);
+
+    let info = p.decode();
+    assert_eq!(Some("1234567".to_string()), info.serial_number);
+    assert_eq!(Some(50.0f64), info.sensor1.temperature);
+    assert_eq!(None, info.sensor2.temperature);
+    assert_eq!(None, info.sensor1.name);
+    assert_eq!(None, info.battery_volts);
+    assert_eq!(Some(0x0102u16), info.firmware_version);
+}
+
+#[test]
+fn test_set_info_builder() {
+    let p = bluetherm::Packet::set_info()
+        .probe_names("Pit", "Butt")
+        .sensor2_high_limit(Some(95.0f64))
+        .build();
+
+    assert_eq!(bluetherm::message_type::SET_INFO, p.get_command_id());
+    assert_eq!(bluetherm::data_flags::PROBE_NAMES | bluetherm::data_flags::SENSOR_2_HIGH_LIMIT, p.get_data_flags());
+    assert_eq!("Butt", p.get_sensor2_name());
+    assert_eq!(Some(95.0f64), p.get_sensor2_high_limit());
+    assert!(p.is_checksum_valid());
+
+    let mut ack = bluetherm::Packet::new();
+    ack.set_command_id(bluetherm::message_type::SET_INFO);
+    ack.set_data_flags(p.get_data_flags());
+    assert!(ack.is_set_info_ack_for(&p));
+
+    ack.set_data_flags(bluetherm::data_flags::PROBE_NAMES);
+    assert!(!ack.is_set_info_ack_for(&p));
+}
+
+#[test]
+fn test_connection_over_pipe() {
+    use bluetherm::transport::{self, Transport};
+    use std::time::Duration;
+
+    let (host, mut device) = transport::pipe();
+    let mut conn = bluetherm::Connection::new(host, None).unwrap();
+
+    conn.send(bluetherm::Packet::temp_packet()).unwrap();
+
+    device.set_timeout(Duration::from_millis(2000)).unwrap();
+    let mut request = vec![];
+    let mut buf = [0u8; 128];
+    while request.len() < 128 {
+        let count = device.read(&mut buf).unwrap();
+        request.extend_from_slice(&buf[0 .. count]);
+    }
+    let request = bluetherm::Packet::from_bytes(&request);
+    assert!(request.is_checksum_valid());
+    assert_eq!(bluetherm::data_flags::TEMPS, request.get_data_flags());
+
+    let mut response = bluetherm::Packet::new();
+    response.set_command_id(bluetherm::message_type::RETRIEVE_INFO);
+    response.set_data_flags(bluetherm::data_flags::TEMPS);
+    response.set_sensor1_reading(Some(107.5f64));
+    response.apply_checksum();
+    device.write_all(&response.data).unwrap();
+
+    match conn.wait().unwrap() {
+        bluetherm::ConnectionEvent::Packet(p) => { assert_eq!(Some(107.5f64), p.get_sensor1_reading()); },
+        e => panic!("unexpected event: {}", e)
+    }
+}
+
+#[test]
+fn test_configure_over_pipe() {
+    use bluetherm::transport::{self, Transport};
+    use std::thread;
+    use std::time::Duration;
+
+    let (host, mut device) = transport::pipe();
+    let mut conn = bluetherm::Connection::new(host, None).unwrap();
+
+    // plays the device: reads the request, acknowledges it, and hands it back to be checked
+    let device_thread = thread::spawn(move || {
+        device.set_timeout(Duration::from_millis(2000)).unwrap();
+        let mut request = vec![];
+        let mut buf = [0u8; 128];
+        while request.len() < 128 {
+            let count = device.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[0 .. count]);
+        }
+        let request = bluetherm::Packet::from_bytes(&request);
+
+        let mut ack = bluetherm::Packet::new();
+        ack.set_command_id(bluetherm::message_type::SET_INFO);
+        ack.set_data_flags(request.get_data_flags());
+        ack.apply_checksum();
+        device.write_all(&ack.data).unwrap();
+        request
+    });
+
+    let mut settings = bluetherm::DeviceSettings::default();
+    settings.names = Some(("Pit".to_string(), "Butt".to_string()));
+    settings.sensor1.high_limit = Some(Some(135.0));
+    settings.sensor1.low_limit = Some(None);
+    settings.sensor2.trim = Some(-1.25);
+
+    conn.configure(&settings, Duration::from_millis(2000)).unwrap();
+
+    let request = device_thread.join().unwrap();
+    assert!(request.is_checksum_valid());
+    assert_eq!(bluetherm::data_flags::PROBE_NAMES | bluetherm::data_flags::SENSOR_1_HIGH_LIMIT |
+               bluetherm::data_flags::SENSOR_1_LOW_LIMIT | bluetherm::data_flags::SENSOR_2_TRIM, request.get_data_flags());
+    let info = request.decode();
+    assert_eq!(Some("Pit".to_string()), info.sensor1.name);
+    assert_eq!(Some("Butt".to_string()), info.sensor2.name);
+    assert_eq!(Some(135.0), info.sensor1.high_limit);
+    assert_eq!(None, info.sensor1.low_limit);
+    assert_eq!(Some(-1.25), info.sensor2.trim);
+
+    // settings the packet can't hold are refused before anything is sent
+    let mut bad = bluetherm::DeviceSettings::default();
+    assert!(bad.to_packet().is_err());
+    bad.names = Some(("a name much too long for the device".to_string(), "Butt".to_string()));
+    assert!(bad.to_packet().is_err());
+    bad.names = None;
+    bad.sensor2.high_limit = Some(Some(50.0));
+    bad.sensor2.low_limit = Some(Some(60.0));
+    assert!(bad.to_packet().is_err());
+    bad.sensor2 = bluetherm::SensorSettings { trim: Some(400.0), ..Default::default() };
+    assert_eq!(std::io::ErrorKind::InvalidInput, conn.configure(&bad, Duration::from_millis(10)).unwrap_err().kind());
+}
+
+#[test]
+fn test_curve_segments() {
+    use pibq::sim::curve::Curve;
+
+    let c = Curve::parse("20,ramp:120:100,hold:50,dip:40:10,unplug:5").unwrap();
+    assert_eq!(Some(20f64), c.value_at(0f64));
+    assert_eq!(Some(70f64), c.value_at(50f64));
+    assert_eq!(Some(120f64), c.value_at(125f64));
+    assert_eq!(Some(80f64), c.value_at(152f64));
+    assert_eq!(None, c.value_at(162f64));
+    assert_eq!(Some(120f64), c.value_at(1000f64));
+    assert!(Curve::parse("20,bogus:1").is_err());
+}
+
+#[test]
+fn test_sim_respond() {
+    use bluetherm::{data_flags, message_type, Packet};
+    use pibq::sim::curve::Curve;
+    use pibq::sim::device::SimDevice;
+    use pibq::sim::faults::FaultPlan;
+
+    // probe 2 is unplugged for the length of the test
+    let curves = [Curve::parse("60").unwrap(), Curve::parse("80,unplug:100000").unwrap()];
+    let mut device = SimDevice::new("1234567", curves, FaultPlan::parse("").unwrap(), 1.0);
+
+    let reply = Packet::from_bytes(&device.respond(&Packet::temp_packet()).unwrap());
+    assert!(reply.is_checksum_valid());
+    assert_eq!(message_type::RETRIEVE_INFO, reply.get_command_id());
+    assert_eq!(data_flags::TEMPS, reply.get_data_flags());
+    assert_eq!("1234567", reply.get_serial_number());
+    assert_eq!(Some(60.0), reply.get_sensor1_reading());
+    assert_eq!(None, reply.get_sensor2_reading());
+
+    let request = Packet::set_info().probe_names("Pit", "Butt").sensor1_high_limit(Some(135.0)).build();
+    let ack = Packet::from_bytes(&device.respond(&request).unwrap());
+    assert!(ack.is_set_info_ack_for(&request));
+    assert_eq!(["Pit", "Butt"], [&device.names[0][..], &device.names[1][..]]);
+    assert_eq!(Some(135.0), device.high_limits[0]);
+
+    // a request with a bad checksum gets no answer
+    let mut bad = Packet::temp_packet();
+    bad.data[0x7E] = bad.data[0x7E] ^ 0xFF;
+    assert!(device.respond(&bad).is_none());
+
+    let mut faulty = SimDevice::new("1234567", [Curve::parse("60").unwrap(), Curve::parse("60").unwrap()], FaultPlan::parse("crc:2").unwrap(), 1.0);
+    assert!(Packet::from_bytes(&faulty.respond(&Packet::temp_packet()).unwrap()).is_checksum_valid());
+    assert!(!Packet::from_bytes(&faulty.respond(&Packet::temp_packet()).unwrap()).is_checksum_valid());
+}
+
+#[test]
+fn test_framer_resynchronizes() {
+    let p = bluetherm::Packet::temp_packet();
+
+    let mut framer = bluetherm::Framer::new();
+    framer.push(&[0xAAu8, 0xBB, 0xCC]);
+    framer.push(&p.data);
+    framer.push(&p.data[0 .. 50]);
+
+    match framer.next_frame() {
+        Some(bluetherm::Frame::Discarded(bytes)) => { assert_eq!(vec![0xAAu8, 0xBB, 0xCC], bytes); },
+        _ => panic!("expected discarded bytes")
+    }
+
+    match framer.next_frame() {
+        Some(bluetherm::Frame::Packet(packet)) => { assert_eq!(p.data.to_vec(), packet.data.to_vec()); },
+        _ => panic!("expected a packet")
+    }
+
+    // the partial packet is kept until the rest arrives
+    assert!(framer.next_frame().is_none());
+    framer.push(&p.data[50 ..]);
+
+    match framer.next_frame() {
+        Some(bluetherm::Frame::Packet(_)) => {},
+        _ => panic!("expected a packet")
+    }
+    assert!(framer.is_empty());
+}
+
+#[test]
+fn test_framer_drops_garbage() {
+    let mut framer = bluetherm::Framer::new();
+    framer.push(&[0x55u8; 200]);
+
+    match framer.next_frame() {
+        Some(bluetherm::Frame::Discarded(bytes)) => { assert_eq!(200 - (bluetherm::PACKET_SIZE - 1), bytes.len()); },
+        _ => panic!("expected discarded bytes")
+    }
+    assert!(framer.next_frame().is_none());
+}
+
+struct BrokenTransport;
+
+impl bluetherm::transport::Transport for BrokenTransport {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        panic!("device exploded")
+    }
+
+    fn write_all(&mut self, _: &[u8]) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, _: std::time::Duration) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_worker_panic_is_reported() {
+    let mut conn = bluetherm::Connection::new(BrokenTransport, None).unwrap();
+
+    match conn.wait() {
+        Err(bluetherm::ConnectionError::ThreadPanicked(msg)) => { assert_eq!("device exploded", msg); },
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(e) => panic!("unexpected event: {}", e)
+    }
+}
+
+#[test]
+fn test_backoff() {
+    use pibq::reconnect::ReconnectPolicy;
+    use std::time::Duration;
+
+    let mut policy = ReconnectPolicy::default();
+    policy.initial_backoff = 100;
+    policy.max_backoff = 500;
+    policy.jitter = 0f64;
+    policy.give_up_after = Some(4);
+
+    let mut backoff = policy.backoff();
+    assert_eq!(Some(Duration::from_millis(100)), backoff.next_delay());
+    assert_eq!(Some(Duration::from_millis(200)), backoff.next_delay());
+    assert_eq!(Some(Duration::from_millis(400)), backoff.next_delay());
+    assert_eq!(Some(Duration::from_millis(500)), backoff.next_delay());
+    assert_eq!(None, backoff.next_delay());
+
+    backoff.reset();
+    assert_eq!(Some(Duration::from_millis(100)), backoff.next_delay());
+}
+
+#[test]
+fn test_config_file() {
+    use pibq::config::Config;
+    use pibq::models::TemperatureUnit;
+
+    let config = Config::parse(r#"
+        units = "C"
+
+        [harvester]
+        devices = ["/dev/rfcomm0", "tcp://pi2:4000"]
+        query_interval = 2000
+
+        [harvester.reconnect]
+        give_up_after = 10
+
+        [web]
+        port = 8080
+    "#).unwrap();
+
+    assert_eq!(TemperatureUnit::Celsius, config.units);
+    assert_eq!(TemperatureUnit::Celsius, config.harvester.device_units);
+    assert_eq!(vec!["/dev/rfcomm0".to_string(), "tcp://pi2:4000".to_string()], config.harvester.devices);
+    assert_eq!(2000, config.harvester.query_interval);
+    assert_eq!(7500, config.harvester.timeout_interval);
+    assert_eq!(Some(10), config.harvester.reconnect.give_up_after);
+    assert_eq!(8080, config.web.port);
+    assert_eq!("pibq.sqlite", config.database.path);
+
+    assert!(Config::parse("units = \"K\"").is_err());
+    assert!(Config::parse("[harvester]\ndevice_units = \"K\"").is_err());
+    assert!(Config::parse("[web\nport = 1").is_err());
+    assert!(Config::parse("[harvester.reconnect]\njitter = 1.5").is_err());
+    assert!(Config::parse("[harvester.reconnect]\njitter = -0.1").is_err());
+    assert!(Config::parse("[harvester.reconnect]\njitter = 1.0").is_ok());
+    assert!(Config::parse("[web]\npool_size = 0").is_err());
+}
+
+#[test]
+fn test_find_or_create_device() {
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+
+    let first = sql::find_or_create_device(&conn, "1234567", "/dev/rfcomm0").unwrap();
+    let again = sql::find_or_create_device(&conn, "1234567", "/dev/rfcomm1").unwrap();
+    assert_eq!(first.id, again.id);
+    assert_eq!(Some("/dev/rfcomm1".to_string()), again.path);
+
+    let other = sql::find_or_create_device(&conn, "7654321", "/dev/rfcomm0").unwrap();
+    assert!(other.id != first.id);
+    assert_eq!(Some(other.id), sql::get_device_by_path(&conn, "/dev/rfcomm0").unwrap().map(|d| d.id));
+}
+
+#[test]
+fn test_project_readings_by_probe() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ProbeValue, Project, ProjectProbe, Reading};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+
+    let device = sql::find_or_create_device(&conn, "1234567", "/dev/rfcomm0").unwrap();
+    let pit = sql::find_or_create_probe(&conn, Some(device.id), 1, "1234567 #1").unwrap();
+    let meat = sql::find_or_create_probe(&conn, Some(device.id), 2, "1234567 #2").unwrap();
+    assert_eq!(pit.id, sql::find_or_create_probe(&conn, Some(device.id), 1, "other").unwrap().id);
+
+    let mut project = Project::new("Brisket".to_string(), UTC::now() - Duration::hours(1), UTC::now() + Duration::hours(1),
+                                   vec![ProjectProbe::new(meat.id, "Brisket", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    let mut reading = Reading::new();
+    reading.device_id = Some(device.id);
+    reading.values.push(ProbeValue { probe_id: pit.id, value: Some(110.0) });
+    reading.values.push(ProbeValue { probe_id: meat.id, value: None });
+    sql::insert_reading(&conn, &mut reading).unwrap();
+
+    let project = sql::get_project(&conn, project.id).unwrap().unwrap();
+    assert_eq!(1, project.probes.len());
+
+    let series = sql::get_project_readings(&conn, &project, None).unwrap();
+    assert_eq!(1, series.len());
+    assert_eq!(meat.id, series[0].probe_id);
+    assert_eq!("Brisket", series[0].name);
+    assert_eq!(1, series[0].readings.len());
+    assert_eq!(None, series[0].readings[0].value);
+    assert_eq!(reading.timestamp.timestamp(), series[0].readings[0].timestamp.timestamp());
+}
+
+#[test]
+fn test_project_readings_downsampled() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ProbeValue, Project, ProjectProbe, Reading};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+
+    let start = UTC::now();
+    let mut project = Project::new("Ribs".to_string(), start, start + Duration::seconds(100), vec![ProjectProbe::new(probe.id, "Ribs", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    for i in 0..10 {
+        let mut reading = Reading::new();
+        reading.timestamp = start + Duration::seconds(1 + 10 * i);
+        reading.values.push(ProbeValue { probe_id: probe.id, value: Some(i as f64) });
+        sql::insert_reading(&conn, &mut reading).unwrap();
+    }
+
+    let series = sql::get_project_readings_downsampled(&conn, &project, None, 2).unwrap();
+    let points = &series[0].readings;
+    assert_eq!(2, points.len());
+    assert_eq!((Some(2.0), Some(0.0), Some(4.0)), (points[0].value, points[0].min, points[0].max));
+    assert_eq!((Some(7.0), Some(5.0), Some(9.0)), (points[1].value, points[1].min, points[1].max));
+    assert_eq!(Some((start + Duration::seconds(91)).timestamp()), series[0].newest.map(|dt| dt.timestamp()));
+}
+
+#[test]
+fn test_rollups_and_retention() {
+    use chrono::duration::Duration;
+    use chrono::offset::TimeZone;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ProbeValue, Project, ProjectProbe, Reading};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+
+    // ten days ago, on a 15 minute boundary
+    let base = UTC.timestamp((UTC::now().timestamp() / 900 - 960) * 900, 0);
+    let times = vec![base, base + Duration::seconds(10), base + Duration::seconds(20), UTC::now()];
+
+    for (i, t) in times.iter().enumerate() {
+        let mut reading = Reading::new();
+        reading.timestamp = *t;
+        reading.values.push(ProbeValue { probe_id: probe.id, value: Some(i as f64 + 1.0) });
+        sql::insert_reading(&conn, &mut reading).unwrap();
+    }
+
+    // keeps the third reading
+    let mut project = Project::new("Old".to_string(), base + Duration::seconds(15), base + Duration::seconds(25), vec![ProjectProbe::new(probe.id, "Old", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    // nothing is pruned before it's rolled up
+    assert_eq!(0, sql::prune_readings(&conn, UTC::now() - Duration::days(5)).unwrap());
+
+    sql::update_rollups(&conn).unwrap();
+    sql::update_rollups(&conn).unwrap();
+
+    let (count, avg): (i64, f64) = conn.query_row("SELECT count, avg FROM readings_1m WHERE bucket = $1", &[&(base.timestamp() * 1000)],
+                                                  |row| (row.get(0), row.get(1))).unwrap();
+    assert_eq!((3, 2.0), (count, avg));
+    let count: i64 = conn.query_row("SELECT count FROM readings_15m WHERE bucket = $1", &[&(base.timestamp() * 1000)], |row| row.get(0)).unwrap();
+    assert_eq!(3, count);
+
+    assert_eq!(2, sql::prune_readings(&conn, UTC::now() - Duration::days(5)).unwrap());
+    assert_eq!(1, sql::get_project_readings(&conn, &project, None).unwrap()[0].readings.len());
+
+    let remaining: i64 = conn.query_row("SELECT COUNT(*) FROM probe_readings", &[], |row| row.get(0)).unwrap();
+    assert_eq!(2, remaining);
+
+    // a late reading is added to its bucket, though the readings already there have been pruned
+    let mut late = Reading::new();
+    late.timestamp = base + Duration::seconds(30);
+    late.values.push(ProbeValue { probe_id: probe.id, value: Some(10.0) });
+    sql::insert_reading(&conn, &mut late).unwrap();
+    assert_eq!(0, sql::prune_readings(&conn, UTC::now() - Duration::days(5)).unwrap());
+    sql::update_rollups(&conn).unwrap();
+
+    let (count, avg, max): (i64, f64, f64) = conn.query_row("SELECT count, avg, max FROM readings_1m WHERE bucket = $1", &[&(base.timestamp() * 1000)],
+                                                            |row| (row.get(0), row.get(1), row.get(2))).unwrap();
+    assert_eq!((4, 4.0, 10.0), (count, avg, max));
+    assert_eq!(1, sql::prune_readings(&conn, UTC::now() - Duration::days(5)).unwrap());
+}
+
+#[test]
+fn test_downsampled_from_rollups() {
+    use chrono::duration::Duration;
+    use chrono::offset::TimeZone;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ProbeValue, Project, ProjectProbe, Reading};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+
+    // two hours, starting on a 15 minute boundary, with a reading each minute
+    let base = UTC.timestamp((UTC::now().timestamp() / 900 - 12) * 900, 0);
+    let mut project = Project::new("Ribs".to_string(), base, base + Duration::hours(2), vec![ProjectProbe::new(probe.id, "Ribs", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    let insert = |minutes: i64| {
+        for i in minutes..minutes + 60 {
+            let mut reading = Reading::new();
+            reading.timestamp = base + Duration::seconds(i * 60 + 30);
+            reading.values.push(ProbeValue { probe_id: probe.id, value: Some(i as f64) });
+            sql::insert_reading(&conn, &mut reading).unwrap();
+        }
+    };
+
+    // the first hour is rolled up, the second isn't yet
+    insert(0);
+    sql::update_rollups(&conn).unwrap();
+    insert(60);
+
+    // a rollup standing in for its readings shows in the chart
+    conn.execute("UPDATE readings_1m SET max = 1000 WHERE bucket = $1", &[&((base.timestamp() + 60) * 1000)]).unwrap();
+
+    let series = sql::get_project_readings_downsampled(&conn, &project, None, 4).unwrap();
+    let points: Vec<(Option<f64>, Option<f64>, Option<f64>)> = series[0].readings.iter().map(|p| (p.value, p.min, p.max)).collect();
+    assert_eq!(vec![(Some(14.5), Some(0.0), Some(1000.0)), (Some(44.5), Some(30.0), Some(59.0)),
+                    (Some(74.5), Some(60.0), Some(89.0)), (Some(104.5), Some(90.0), Some(119.0))], points);
+    assert_eq!(Some((base + Duration::seconds(119 * 60 + 30)).timestamp()), series[0].newest.map(|dt| dt.timestamp()));
+
+    // once everything is rolled up, the newest reading still comes from the readings
+    sql::update_rollups(&conn).unwrap();
+    let series = sql::get_project_readings_downsampled(&conn, &project, None, 4).unwrap();
+    assert_eq!(4, series[0].readings.len());
+    assert_eq!(Some((base + Duration::seconds(119 * 60 + 30)).timestamp()), series[0].newest.map(|dt| dt.timestamp()));
+}
+
+#[test]
+fn test_alarm_evaluator() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::alarms::Evaluator;
+    use pibq::models::{AlarmKind, AlarmRule, AlarmState, ProbeValue, Reading};
+
+    fn reading(at: chrono::datetime::DateTime<UTC>, value: f64) -> Reading {
+        let mut r = Reading::new();
+        r.timestamp = at;
+        r.values.push(ProbeValue { probe_id: 1, value: Some(value) });
+        r
+    }
+
+    let mut above = AlarmRule::new(1, 1, AlarmKind::Above, 120.0);
+    above.id = 1;
+    let mut target = AlarmRule::new(1, 1, AlarmKind::Target, 95.0);
+    target.id = 2;
+    let mut rate = AlarmRule::new(1, 1, AlarmKind::Rate, 5.0);
+    rate.id = 3;
+    let mut other_probe = AlarmRule::new(1, 2, AlarmKind::Below, 200.0);
+    other_probe.id = 4;
+
+    let mut e = Evaluator::new();
+    let start = UTC::now();
+
+    let events = e.evaluate(&[above.clone(), target.clone(), rate.clone(), other_probe.clone()], &reading(start, 100.0));
+    assert!(events.is_empty());
+
+    // 20 degrees in two minutes: rate and target trip, above doesn't
+    let events = e.evaluate(&[above.clone(), target.clone(), rate.clone()], &reading(start + Duration::seconds(120), 120.0));
+    let ids: Vec<(i64, AlarmState)> = events.iter().map(|ev| (ev.rule_id, ev.state)).collect();
+    assert_eq!(vec![(2, AlarmState::Triggered), (3, AlarmState::Triggered)], ids);
+    assert_eq!(Some(10.0), events[1].value);
+
+    above.state = AlarmState::Triggered;
+    target.state = AlarmState::Acknowledged;
+
+    // within the hysteresis band nothing clears; target never clears on its own
+    let events = e.evaluate(&[above.clone(), target.clone()], &reading(start + Duration::seconds(125), 119.8));
+    assert!(events.is_empty());
+
+    let events = e.evaluate(&[above.clone(), target.clone()], &reading(start + Duration::seconds(130), 90.0));
+    let ids: Vec<(i64, AlarmState)> = events.iter().map(|ev| (ev.rule_id, ev.state)).collect();
+    assert_eq!(vec![(1, AlarmState::Cleared)], ids);
+}
+
+fn test_alert() -> pibq::notify::Alert {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{AlarmEvent, AlarmKind, AlarmRule, AlarmState, Project, ProjectProbe, TemperatureUnit};
+
+    let mut project = Project::new("Brisket".to_string(), UTC::now() - Duration::hours(1), UTC::now() + Duration::hours(12),
+                                   vec![ProjectProbe::new(3, "Flat", 0)]);
+    project.id = 7;
+    let mut rule = AlarmRule::new(7, 3, AlarmKind::Above, 95.0);
+    rule.id = 11;
+    let event = AlarmEvent::new(11, AlarmState::Triggered, Some(96.5));
+
+    pibq::notify::Alert::new(&project, &rule, &event, TemperatureUnit::Fahrenheit)
+}
+
+#[test]
+fn test_notify_rate_limiter() {
+    use pibq::models::AlarmState;
+    use pibq::notify::RateLimiter;
+    use std::time::{Duration, Instant};
+
+    let mut limiter = RateLimiter::new(Duration::from_secs(300));
+    let now = Instant::now();
+
+    assert!(limiter.allow(1, AlarmState::Triggered, now));
+    assert!(!limiter.allow(1, AlarmState::Triggered, now + Duration::from_secs(10)));
+    // a different rule or state is its own alert
+    assert!(limiter.allow(2, AlarmState::Triggered, now + Duration::from_secs(10)));
+    assert!(limiter.allow(1, AlarmState::Cleared, now + Duration::from_secs(10)));
+    assert!(limiter.allow(1, AlarmState::Triggered, now + Duration::from_secs(300)));
+}
+
+#[test]
+fn test_webhook_notifier() {
+    use pibq::notify::{Notifier, WebhookNotifier};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+
+    // answers two requests: the first accepted, the second refused
+    thread::spawn(move || {
+        for status in ["200 OK", "500 Internal Server Error"].iter() {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() { break; }
+                if line.to_lowercase().starts_with("content-length:") {
+                    length = line[15..].trim().parse::<usize>().unwrap();
+                }
+            }
+
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            tx.send((request_line, String::from_utf8(body).unwrap())).unwrap();
+
+            let mut stream = stream;
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+        }
+    });
+
+    let alert = test_alert();
+    let notifier = WebhookNotifier::new(&format!("http://127.0.0.1:{}/hooks/pibq?key=abc", port)).unwrap();
+
+    notifier.notify(&alert).unwrap();
+    let (request_line, body) = rx.recv().unwrap();
+    assert_eq!("POST /hooks/pibq?key=abc HTTP/1.1", request_line.trim());
+
+    let json = rustc_serialize::json::Json::from_str(&body).unwrap();
+    assert_eq!(Some("Brisket"), json.find("project").and_then(|j| j.as_string()));
+    assert_eq!(Some("Flat"), json.find("probe").and_then(|j| j.as_string()));
+    assert_eq!(Some("triggered"), json.find("state").and_then(|j| j.as_string()));
+
+    assert!(notifier.notify(&alert).is_err());
+
+    assert!(WebhookNotifier::new("https://example.com/hook").is_err());
+    assert!(WebhookNotifier::new("not a url").is_err());
+}
+
+#[test]
+fn test_smtp_notifier() {
+    use pibq::notify::{Notifier, SmtpNotifier};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    // a fake relay that records the commands and message it's given
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut commands = vec![];
+        let mut message = vec![];
+        let mut in_data = false;
+
+        write!(writer, "220 fake ESMTP\r\n").unwrap();
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 { break; }
+            let line = line.trim_right().to_string();
+
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    write!(writer, "250 queued\r\n").unwrap();
+                } else {
+                    message.push(line);
+                }
+                continue;
+            }
+
+            commands.push(line.clone());
+            if line.starts_with("HELO") {
+                write!(writer, "250-fake\r\n250 HELP\r\n").unwrap();
+            } else if line == "DATA" {
+                in_data = true;
+                write!(writer, "354 go ahead\r\n").unwrap();
+            } else if line == "QUIT" {
+                write!(writer, "221 bye\r\n").unwrap();
+                break;
+            } else {
+                write!(writer, "250 ok\r\n").unwrap();
+            }
+        }
+
+        tx.send((commands, message)).unwrap();
+    });
+
+    let notifier = SmtpNotifier::new(&addr.to_string(), "pibq@localhost", "cook@example.com");
+    notifier.notify(&test_alert()).unwrap();
+
+    let (commands, message) = rx.recv().unwrap();
+    assert_eq!(vec!["HELO pibq", "MAIL FROM:<pibq@localhost>", "RCPT TO:<cook@example.com>", "DATA", "QUIT"], commands);
+    assert!(message.contains(&"To: cook@example.com".to_string()));
+    assert!(message.iter().any(|l| l.starts_with("Subject: [pibq] Brisket: Flat goes above 203.0")));
+}
+
+#[test]
+fn test_smtp_hostile_target() {
+    use pibq::config::Config;
+    use pibq::models::{NotifierKind, ProjectNotifier};
+    use pibq::notify::{self, Notifier, NotifyError, SmtpNotifier};
+
+    let mut config = Config::default().alerts;
+    config.smtp_server = Some("127.0.0.1:25".to_string());
+
+    assert!(notify::from_setting(&ProjectNotifier::new(1, NotifierKind::Email, "cook@example.com"), &config).is_ok());
+
+    for target in ["cook@example.com>\r\nRCPT TO:<evil@example.com", "cook@example.com\nBcc: evil@example.com",
+                   "<cook@example.com>", "nobody"].iter() {
+        assert!(notify::from_setting(&ProjectNotifier::new(1, NotifierKind::Email, target), &config).is_err());
+        // nothing is sent even if a bad address gets past the web form
+        match SmtpNotifier::new("127.0.0.1:1", "pibq@localhost", target).notify(&test_alert()) {
+            Err(NotifyError::Config(_)) => {},
+            r => panic!("expected {:?} to be refused, got {:?}", target, r)
+        }
+    }
+}
+
+#[test]
+fn test_command_notifier() {
+    use pibq::notify::{CommandNotifier, Notifier};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("pibq_test_command_{}", chrono::offset::utc::UTC::now().timestamp()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("notify.sh");
+    let output = dir.join("out.txt");
+
+    {
+        let mut f = File::create(&script).unwrap();
+        write!(f, "#!/bin/sh\necho \"$PIBQ_PROJECT|$PIBQ_PROBE|$PIBQ_KIND|$PIBQ_STATE|$PIBQ_VALUE\" > {}\n", output.display()).unwrap();
+    }
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let notifier = CommandNotifier::new(script.to_str().unwrap());
+    notifier.notify(&test_alert()).unwrap();
+
+    let mut written = String::new();
+    File::open(&output).unwrap().read_to_string(&mut written).unwrap();
+    assert_eq!("Brisket|Flat|above|triggered|96.5", written.trim());
+
+    // a failing command is an error
+    assert!(CommandNotifier::new("/bin/false").notify(&test_alert()).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_eta_estimate() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::analytics;
+    use pibq::models::{ProbeSeries, SeriesPoint};
+
+    let now = UTC::now();
+
+    // a reading a minute over the given minutes before now
+    fn series(now: chrono::datetime::DateTime<UTC>, from: i64, to: i64, f: &Fn(i64) -> f64) -> ProbeSeries {
+        let mut s = ProbeSeries::new(1, "Flat");
+        for m in from..(to + 1) {
+            s.readings.push(SeriesPoint::raw(now + Duration::minutes(m), Some(f(m))));
+        }
+        s
+    }
+
+    fn minutes_until(eta: &analytics::Eta, now: chrono::datetime::DateTime<UTC>) -> i64 {
+        (eta.at.unwrap() - now).num_minutes()
+    }
+
+    // a steady 0.2C/min, now at 46C
+    let climbing = series(now, -30, 0, &|m| 46.0 + 0.2 * m as f64);
+
+    let eta = analytics::estimate(&climbing, 60.0, now).unwrap();
+    assert!(!eta.stalled);
+    assert!((eta.rate - 0.2).abs() < 0.001);
+    assert!(minutes_until(&eta, now) >= 69 && minutes_until(&eta, now) <= 70);
+    assert_eq!("high", eta.confidence_label());
+
+    // past the stall band, so a stall is expected on the way
+    let eta = analytics::estimate(&climbing, 95.0, now).unwrap();
+    assert!(minutes_until(&eta, now) >= 394 && minutes_until(&eta, now) <= 395);
+    assert_eq!("medium", eta.confidence_label());
+
+    // climbed at 0.2C/min, then flat at 68C for the last half hour
+    let stalled = series(now, -120, 0, &|m| (50.0 + 0.2 * (m + 120) as f64).min(68.0));
+
+    let eta = analytics::estimate(&stalled, 95.0, now).unwrap();
+    assert!(eta.stalled);
+    // 107 more minutes of stall, then 27 degrees at 0.2C/min
+    assert!(minutes_until(&eta, now) >= 241 && minutes_until(&eta, now) <= 242);
+    assert_eq!("low", eta.confidence_label());
+
+    // already there
+    let eta = analytics::estimate(&climbing, 45.0, now).unwrap();
+    assert_eq!(Some(now), eta.at);
+
+    // cooling never gets there
+    let cooling = series(now, -30, 0, &|m| 46.0 - 0.2 * m as f64);
+    assert_eq!(None, analytics::estimate(&cooling, 60.0, now).unwrap().at);
+
+    // readings stopped an hour ago, or there are too few to go on
+    assert!(analytics::estimate(&series(now, -90, -60, &|m| 46.0 + 0.2 * m as f64), 60.0, now).is_none());
+    assert!(analytics::estimate(&series(now, -2, 0, &|m| 46.0 + 0.2 * m as f64), 60.0, now).is_none());
+}
+
+#[test]
+fn test_event_detector() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::analytics::{Detection, Detector};
+    use pibq::models::{ProbeRole, ProbeValue, Project, ProjectEventKind, ProjectProbe, Reading};
+
+    let start = UTC::now();
+
+    let mut pit = ProjectProbe::new(2, "Pit", 2);
+    pit.role = ProbeRole::Pit;
+    let mut project = Project::new("Brisket".to_string(), start, start + Duration::hours(12),
+                                   vec![ProjectProbe::new(1, "Flat", 1), pit]);
+    project.id = 1;
+    let projects = vec![project];
+
+    let mut detector = Detector::new();
+    let mut seen = vec![];
+
+    for m in 0..71 {
+        // the meat climbs into the band, sits at 67C for a while, then climbs again
+        let meat = if m < 20 { 55.0 + 0.6 * m as f64 } else if m < 60 { 67.0 } else { 67.0 + 0.6 * (m - 60) as f64 };
+        // the lid comes off at 30 minutes and the pit is back by 32
+        let pit = match m { 30 => 95.0, 31 => 100.0, 32 => 108.5, _ => 110.0 };
+
+        let mut reading = Reading::new();
+        reading.timestamp = start + Duration::minutes(m);
+        reading.values.push(ProbeValue { probe_id: 1, value: Some(meat) });
+        reading.values.push(ProbeValue { probe_id: 2, value: Some(pit) });
+
+        for d in detector.observe(&projects, &reading) {
+            match d {
+                Detection::Started(e) => seen.push((e.kind, true, (e.started_at - start).num_minutes(), m)),
+                Detection::Occurred(e) => {
+                    seen.push((e.kind, true, (e.started_at - start).num_minutes(), m));
+                    seen.push((e.kind, false, (e.ended_at.unwrap() - start).num_minutes(), m));
+                },
+                Detection::Ended(_, _, kind, at) => seen.push((kind, false, (at - start).num_minutes(), m))
+            }
+        }
+    }
+
+    let stalls: Vec<(bool, i64, i64)> = seen.iter().filter(|s| s.0 == ProjectEventKind::Stall).map(|s| (s.1, s.2, s.3)).collect();
+    // not stalled once there's enough history; stalled from 19 minutes, noticed at 39; moving again at 64
+    assert_eq!(vec![(false, 20, 20), (true, 19, 39), (false, 64, 64)], stalls);
+
+    let lids: Vec<(bool, i64, i64)> = seen.iter().filter(|s| s.0 == ProjectEventKind::LidOpen).map(|s| (s.1, s.2, s.3)).collect();
+    // only saved once the pit is back
+    assert_eq!(vec![(true, 29, 32), (false, 32, 32)], lids);
+}
+
+#[test]
+fn test_lid_drop_without_recovery() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::analytics::{Detection, Detector};
+    use pibq::models::{ProbeRole, ProbeValue, Project, ProjectProbe, Reading};
+
+    let start = UTC::now();
+
+    let mut pit = ProjectProbe::new(2, "Pit", 1);
+    pit.role = ProbeRole::Pit;
+    let mut project = Project::new("Brisket".to_string(), start, start + Duration::hours(12), vec![pit]);
+    project.id = 1;
+    let projects = vec![project];
+
+    let mut detector = Detector::new();
+    let mut seen = vec![];
+
+    for m in 0..90 {
+        // the fire goes out at 30 minutes and the pit never comes back
+        let pit = if m < 30 { 110.0 } else { 95.0 - 0.1 * (m - 30) as f64 };
+
+        let mut reading = Reading::new();
+        reading.timestamp = start + Duration::minutes(m);
+        reading.values.push(ProbeValue { probe_id: 2, value: Some(pit) });
+
+        for d in detector.observe(&projects, &reading) {
+            seen.push(match d {
+                Detection::Started(_) => "started",
+                Detection::Occurred(_) => "occurred",
+                Detection::Ended(_, _, _, _) => "ended"
+            });
+        }
+    }
+
+    // a drop the pit never comes back from isn't a lid opening
+    assert!(seen.is_empty(), "{:?}", seen);
+}
+
+#[test]
+fn test_project_events() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ProbeRole, Project, ProjectEvent, ProjectEventKind, ProjectProbe};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+    let mut pp = ProjectProbe::new(probe.id, "Pit", 1);
+    pp.role = ProbeRole::Pit;
+
+    let now = UTC::now();
+    let mut project = Project::new("Ribs".to_string(), now - Duration::hours(1), now + Duration::hours(1), vec![pp]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    let active = sql::get_active_projects(&conn, now).unwrap();
+    assert_eq!(1, active.len());
+    assert_eq!(ProbeRole::Pit, active[0].probes[0].role);
+    assert!(sql::get_active_projects(&conn, now + Duration::hours(2)).unwrap().is_empty());
+
+    let mut event = ProjectEvent::new(project.id, probe.id, ProjectEventKind::LidOpen, now);
+    assert!(sql::start_project_event(&conn, &mut event).unwrap());
+    // already going on
+    let mut again = ProjectEvent::new(project.id, probe.id, ProjectEventKind::LidOpen, now);
+    assert!(!sql::start_project_event(&conn, &mut again).unwrap());
+
+    assert!(sql::end_project_event(&conn, project.id, probe.id, ProjectEventKind::LidOpen, now + Duration::minutes(2)).unwrap());
+    assert!(!sql::end_project_event(&conn, project.id, probe.id, ProjectEventKind::LidOpen, now + Duration::minutes(3)).unwrap());
+
+    let events = sql::get_project_events(&conn, project.id).unwrap();
+    assert_eq!(1, events.len());
+    assert_eq!(event.id, events[0].id);
+    assert_eq!(Some(120), events[0].ended_at.map(|e| (e - events[0].started_at).num_seconds()));
+
+    // one that's already over is saved with its end
+    let mut over = ProjectEvent::new(project.id, probe.id, ProjectEventKind::LidOpen, now + Duration::minutes(10));
+    over.ended_at = Some(now + Duration::minutes(12));
+    assert!(sql::start_project_event(&conn, &mut over).unwrap());
+    let events = sql::get_project_events(&conn, project.id).unwrap();
+    assert_eq!(2, events.len());
+    assert!(events.iter().any(|e| e.id == over.id && e.ended_at.map(|t| t.timestamp()) == over.ended_at.map(|t| t.timestamp())));
+}
+
+#[test]
+fn test_annotations() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{Annotation, Project, ProjectProbe};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+    let now = UTC::now();
+    let mut project = Project::new("Pork Butt".to_string(), now - Duration::hours(4), now + Duration::hours(4),
+                                   vec![ProjectProbe::new(probe.id, "Butt", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    let mut wrapped = Annotation::new(project.id, now, "wrapped");
+    sql::insert_annotation(&conn, &mut wrapped).unwrap();
+    let mut charcoal = Annotation::new(project.id, now - Duration::hours(2), "added charcoal");
+    sql::insert_annotation(&conn, &mut charcoal).unwrap();
+
+    // in timeline order, not the order they were written
+    let notes = sql::get_project_annotations(&conn, project.id).unwrap();
+    let texts: Vec<&str> = notes.iter().map(|a| a.text.as_str()).collect();
+    assert_eq!(vec!["added charcoal", "wrapped"], texts);
+    assert_eq!(charcoal.timestamp.timestamp(), notes[0].timestamp.timestamp());
+
+    // only deletes from its own project
+    assert!(sql::delete_annotation(&conn, project.id + 1, wrapped.id).is_err());
+    sql::delete_annotation(&conn, project.id, wrapped.id).unwrap();
+    assert_eq!(1, sql::get_project_annotations(&conn, project.id).unwrap().len());
+}
+
+#[test]
+fn test_readings_between() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ConnectionStatus, ProbeValue, Reading};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let device = sql::find_or_create_device(&conn, "1234", "/dev/null").unwrap();
+    let probe = sql::find_or_create_probe(&conn, Some(device.id), 1, "Probe 1").unwrap();
+    let base = UTC::now() - Duration::hours(1);
+
+    for i in 0..10 {
+        let mut reading = Reading::new();
+        reading.device_id = Some(device.id);
+        reading.timestamp = base + Duration::minutes(i);
+        reading.values.push(ProbeValue { probe_id: probe.id, value: Some(i as f64) });
+        sql::insert_reading(&conn, &mut reading).unwrap();
+    }
+
+    // minutes 2 to 7, four at a time
+    let from = Some(base + Duration::minutes(2));
+    let to = Some(base + Duration::minutes(8));
+    let first = sql::get_readings_between(&conn, from, to, 0, 4).unwrap();
+    assert_eq!(4, first.len());
+    assert_eq!(Some(2.0), first[0].values[0].value);
+
+    let second = sql::get_readings_between(&conn, from, to, first[3].id, 4).unwrap();
+    let values: Vec<Option<f64>> = second.iter().map(|r| r.values[0].value).collect();
+    assert_eq!(vec![Some(6.0), Some(7.0)], values);
+
+    // open ended
+    assert_eq!(10, sql::get_readings_between(&conn, None, None, 0, 100).unwrap().len());
+
+    let latest = sql::get_latest_reading_time(&conn, device.id).unwrap().unwrap();
+    assert_eq!((base + Duration::minutes(9)).timestamp(), latest.timestamp());
+    assert!(sql::get_latest_reading_time(&conn, device.id + 1).unwrap().is_none());
+
+    for i in 0..3 {
+        let mut status = ConnectionStatus::new();
+        status.device_id = Some(device.id);
+        status.is_connect = i % 2 == 0;
+        status.is_disconnect = i % 2 == 1;
+        status.created_at = base + Duration::minutes(i * 10);
+        sql::insert_connection_status(&conn, &mut status).unwrap();
+    }
+
+    let statuses = sql::get_connection_statuses(&conn, Some(base + Duration::minutes(5)), None, 0, 10).unwrap();
+    assert_eq!(2, statuses.len());
+    assert!(statuses[0].is_disconnect);
+    assert_eq!(1, sql::get_connection_statuses(&conn, None, None, statuses[0].id, 1).unwrap().len());
+}
+
+#[test]
+fn test_readings_after() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{ProbeValue, Project, ProjectProbe, Reading};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let device = sql::find_or_create_device(&conn, "1234", "/dev/null").unwrap();
+    let probe1 = sql::find_or_create_probe(&conn, Some(device.id), 1, "Probe 1").unwrap();
+    let probe2 = sql::find_or_create_probe(&conn, Some(device.id), 2, "Probe 2").unwrap();
+    let base = UTC::now() - Duration::hours(1);
+
+    // minutes 0 to 9; probe 2 only has the even ones
+    for i in 0..10 {
+        let mut reading = Reading::new();
+        reading.device_id = Some(device.id);
+        reading.timestamp = base + Duration::minutes(i);
+        reading.values.push(ProbeValue { probe_id: probe1.id, value: Some(i as f64) });
+        if i % 2 == 0 {
+            reading.values.push(ProbeValue { probe_id: probe2.id, value: Some(i as f64) });
+        }
+        sql::insert_reading(&conn, &mut reading).unwrap();
+    }
+
+    let all = sql::get_readings_after(&conn, 0, 100).unwrap();
+    assert_eq!(10, all.len());
+    assert_eq!(2, all[0].values.len());
+
+    let page = sql::get_readings_after(&conn, all[2].id, 3).unwrap();
+    let ids: Vec<i64> = page.iter().map(|r| r.id).collect();
+    assert_eq!(vec![all[3].id, all[4].id, all[5].id], ids);
+
+    // probe 2 over minutes 3 to 8
+    let project = Project::new("Brisket".to_string(), base + Duration::minutes(3), base + Duration::minutes(8),
+                               vec![ProjectProbe::new(probe2.id, "Brisket", 1)]);
+    let values: Vec<Option<f64>> = sql::get_project_readings_after(&conn, &project, 0, 100).unwrap()
+        .iter().map(|r| r.values.iter().find(|v| v.probe_id == probe2.id).unwrap().value).collect();
+    assert_eq!(vec![Some(4.0), Some(6.0), Some(8.0)], values);
+
+    let page = sql::get_project_readings_after(&conn, &project, all[4].id, 1).unwrap();
+    assert_eq!(1, page.len());
+    assert_eq!(all[6].id, page[0].id);
+
+    let no_probes = Project::new("Empty".to_string(), base, base + Duration::hours(1), vec![]);
+    assert!(sql::get_project_readings_after(&conn, &no_probes, 0, 100).unwrap().is_empty());
+}
+
+#[test]
+fn test_delete_project() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::models::{AlarmKind, AlarmRule, Annotation, Project, ProjectProbe};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+    let now = UTC::now();
+
+    let mut project = Project::new("Brisket".to_string(), now - Duration::hours(4), now + Duration::hours(4),
+                                   vec![ProjectProbe::new(probe.id, "Flat", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    let mut rule = AlarmRule::new(project.id, probe.id, AlarmKind::Target, 93.0);
+    sql::insert_alarm_rule(&conn, &mut rule).unwrap();
+    let mut note = Annotation::new(project.id, now, "on");
+    sql::insert_annotation(&conn, &mut note).unwrap();
+
+    sql::delete_project(&conn, project.id).unwrap();
+
+    assert!(sql::get_project(&conn, project.id).unwrap().is_none());
+    assert!(sql::get_alarm_rule(&conn, rule.id).unwrap().is_none());
+    assert_eq!(0, sql::get_project_annotations(&conn, project.id).unwrap().len());
+    // the probe and its readings stay
+    assert_eq!(1, sql::get_probes(&conn).unwrap().len());
+
+    assert!(sql::delete_project(&conn, project.id).is_err());
+}
+
+#[test]
+fn test_temperature_units() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::bluetherm::{data_flags, Packet, TYPES_FAHRENHEIT};
+    use pibq::models::{Project, ProjectProbe, TemperatureUnit};
+    use pibq::sql;
+
+    let f = TemperatureUnit::Fahrenheit;
+    assert!((f.from_celsius(100.0) - 212.0).abs() < 1e-9);
+    assert!((f.to_celsius(203.0) - 95.0).abs() < 1e-9);
+    assert!((f.delta_from_celsius(1.0) - 1.8).abs() < 1e-9);
+    assert_eq!(20.0, TemperatureUnit::Celsius.to_celsius(20.0));
+    assert_eq!(Some(f), TemperatureUnit::from_str("F"));
+    assert_eq!(None, TemperatureUnit::from_str("K"));
+
+    // the types field says which the device reports in
+    let mut p = Packet::new();
+    p.set_data_flags(data_flags::READINGS);
+    p.set_types(TYPES_FAHRENHEIT);
+    assert_eq!(Some(true), p.decode().is_fahrenheit());
+    p.set_types(0);
+    assert_eq!(Some(false), p.decode().is_fahrenheit());
+    p.set_data_flags(data_flags::TEMPS);
+    assert_eq!(None, p.decode().is_fahrenheit());
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let probe = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+    let now = UTC::now();
+    let mut project = Project::new("Ribs".to_string(), now, now + Duration::hours(6), vec![ProjectProbe::new(probe.id, "Ribs", 1)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+    assert_eq!(None, sql::get_project(&conn, project.id).unwrap().unwrap().units);
+
+    project.units = Some(TemperatureUnit::Celsius);
+    sql::update_project(&conn, &mut project).unwrap();
+    assert_eq!(Some(TemperatureUnit::Celsius), sql::get_project(&conn, project.id).unwrap().unwrap().units);
+}
+
+#[test]
+fn test_alert_units() {
+    use pibq::models::TemperatureUnit;
+
+    let mut alert = test_alert();
+    assert!(alert.subject().contains("goes above 203.0\u{b0}F"));
+
+    alert.units = TemperatureUnit::Celsius;
+    assert!(alert.subject().contains("goes above 95.0\u{b0}C"));
+    assert!(alert.message().contains("96.5\u{b0}C"));
+}
+
+#[test]
+fn test_project_export() {
+    use chrono::duration::Duration;
+    use chrono::offset::utc::UTC;
+    use pibq::export::ProjectExport;
+    use pibq::models::{Annotation, ConnectionStatus, ProbeValue, Project, ProjectProbe, Reading, TemperatureUnit};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+    let flat = sql::find_or_create_probe(&conn, None, 1, "Probe 1").unwrap();
+    let point = sql::find_or_create_probe(&conn, None, 2, "Probe 2").unwrap();
+    let start = UTC::now() - Duration::hours(2);
+
+    let mut project = Project::new("Brisket, packer".to_string(), start, start + Duration::hours(1),
+                                   vec![ProjectProbe::new(flat.id, "Flat", 1), ProjectProbe::new(point.id, "Point", 2)]);
+    sql::insert_project(&conn, &mut project).unwrap();
+
+    for i in 1..4 {
+        let mut reading = Reading::new();
+        reading.timestamp = start + Duration::minutes(i);
+        reading.values.push(ProbeValue { probe_id: flat.id, value: Some(100.0) });
+        reading.values.push(ProbeValue { probe_id: point.id, value: if i == 2 { None } else { Some(0.0) } });
+        sql::insert_reading(&conn, &mut reading).unwrap();
+    }
+
+    let mut note = Annotation::new(project.id, start + Duration::seconds(90), "wrapped, in \"butcher\" paper");
+    sql::insert_annotation(&conn, &mut note).unwrap();
+
+    for &(minutes, connect) in [(5, false), (7, true)].iter() {
+        let mut status = ConnectionStatus::new();
+        status.is_connect = connect;
+        status.is_disconnect = !connect;
+        status.created_at = start + Duration::minutes(minutes);
+        sql::insert_connection_status(&conn, &mut status).unwrap();
+    }
+
+    // a thermometer none of the project's probes are on
+    let other = sql::find_or_create_device(&conn, "other", "/dev/null").unwrap();
+    let mut status = ConnectionStatus::new();
+    status.device_id = Some(other.id);
+    status.is_disconnect = true;
+    status.created_at = start + Duration::minutes(10);
+    sql::insert_connection_status(&conn, &mut status).unwrap();
+
+    let export = ProjectExport::load(&conn, project).unwrap();
+    assert_eq!(1, export.gaps.len());
+    assert!(export.gaps[0].ended_at.is_some());
+
+    let mut out: Vec<u8> = vec![];
+    export.write_csv(&mut out, TemperatureUnit::Fahrenheit).unwrap();
+    let csv = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!("timestamp,Flat (F),Point (F),note", lines[0]);
+    assert!(lines[1].ends_with(",212.00,32.00,"));
+    assert!(lines[2].ends_with(",,,\"wrapped, in \"\"butcher\"\" paper\""));
+    assert!(lines[3].ends_with(",212.00,,"));
+    assert!(lines[5].ends_with(",,,disconnected"));
+    assert!(lines[6].ends_with(",,,reconnected"));
+    assert_eq!(7, lines.len());
+
+    let json = export.as_json(TemperatureUnit::Celsius);
+    assert_eq!(Some("C"), json.find("units").and_then(|u| u.as_string()));
+    assert_eq!(3, json.find("probes").and_then(|p| p.as_array()).unwrap()[0].find("readings").and_then(|r| r.as_array()).unwrap().len());
+}
+
+#[test]
+fn test_import_csv() {
+    use pibq::import::{self, ImportOptions};
+    use pibq::sql;
+
+    let conn = sql::get_connection(":memory:", true).unwrap();
+
+    let csv = "timestamp,Flat (F),Pit,note\r\n\
+               2016-06-18T14:00:00+00:00,212.00,110,\r\n\
+               2016-06-18T14:01:00+00:00,,,\"wrapped, in \"\"butcher\"\" paper\"\r\n\
+               2016-06-18T14:02:00+00:00,213.8,111,\r\n\
+               2016-06-18T14:02:00+00:00,214.0,112,\r\n\
+               not a time,1,2,\r\n\
+               2016-06-18T14:03:00+00:00,hot,2,\r\n\
+               2016-06-18T14:04:00+00:00,,,disconnected\r\n";
+
+    let mut options = ImportOptions::new("Old brisket");
+    options.device_serial = "old-logger".to_string();
+    let report = import::import_csv(&conn, &mut csv.as_bytes(), &options).unwrap();
+
+    assert_eq!(2, report.imported);
+    assert_eq!(1, report.annotations);
+    assert_eq!(vec![5], report.conflicts.iter().map(|c| c.0).collect::<Vec<_>>());
+    assert_eq!(vec![6, 7], report.errors.iter().map(|e| e.0).collect::<Vec<_>>());
+
+    let project = report.project.unwrap();
+    assert_eq!("Old brisket", project.name);
+    assert_eq!(None, project.units);
+    assert_eq!(vec!["Flat", "Pit"], project.probes.iter().map(|p| p.name.as_str()).collect::<Vec<_>>());
+
+    let series = sql::get_project_readings(&conn, &project, None).unwrap();
+    assert_eq!(2, series[0].readings.len());
+    assert!((series[0].readings[0].value.unwrap() - 100.0).abs() < 0.001);
+    assert!((series[1].readings[1].value.unwrap() - 111.0).abs() < 0.001);
+    assert_eq!("wrapped, in \"butcher\" paper", sql::get_project_annotations(&conn, project.id).unwrap()[0].text);
+
+    // importing the same file again finds everything already there
+    let again = import::import_csv(&conn, &mut csv.as_bytes(), &options).unwrap();
+    assert_eq!(0, again.imported);
+    assert_eq!(3, again.conflicts.len());
+    assert!(again.project.is_none());
+    assert_eq!(1, sql::get_projects(&conn).unwrap().len());
+}
+
+#[test]
+fn test_utc_timestamps_migration() {
+    use chrono::offset::TimeZone;
+    use chrono::offset::utc::UTC;
+    use pibq::sql;
+    use pibq::sql::migrations::{self, MIGRATIONS};
+
+    // a database from before 006, with times stored as local TEXT
+    let conn = sql::get_connection(":memory:", false).unwrap();
+    conn.execute_batch("CREATE TABLE schema_migrations (version INTEGER NOT NULL, checksum TEXT)").unwrap();
+    for m in MIGRATIONS.iter().take_while(|m| m.version < 6) {
+        conn.execute_batch(m.sql).unwrap();
+        conn.execute("INSERT INTO schema_migrations (version, checksum) VALUES ($1, $2)", &[&(m.version as i64), &m.checksum()]).unwrap();
+    }
+
+    conn.execute_batch("INSERT INTO devices VALUES (1, '1234', '/dev/rfcomm0', '2016-06-18 09:00:00.250-05:00');
+                        INSERT INTO connection_statuses (id, is_connect, is_disconnect, info, created_at, device_id) \
+                          VALUES (1, 1, 0, NULL, '2016-06-18 09:00:01-05:00', 1);
+                        INSERT INTO readings VALUES (1, 1, '2016-06-18 09:00:02.500-05:00');
+                        INSERT INTO probes VALUES (1, 1, 1, 'Probe 1');
+                        INSERT INTO probe_readings VALUES (1, 1, 100.0);
+                        INSERT INTO projects VALUES (1, 'Brisket', '2016-06-18 08:00:00-05:00', '2016-06-18 20:00:00-05:00', \
+                          '2016-06-18 07:00:00-05:00', '2016-06-18 07:30:00-05:00');
+                        INSERT INTO project_probes VALUES (1, 1, 'Flat', 1);").unwrap();
+
+    migrations::perform_migration(&conn).unwrap();
+
+    let device = &sql::get_devices(&conn).unwrap()[0];
+    assert_eq!(UTC.ymd(2016, 6, 18).and_hms_milli(14, 0, 0, 250), device.created_at);
+
+    let status = &sql::get_connection_statuses(&conn, None, None, 0, 10).unwrap()[0];
+    assert_eq!(UTC.ymd(2016, 6, 18).and_hms(14, 0, 1), status.created_at);
+
+    let project = sql::get_project(&conn, 1).unwrap().unwrap();
+    assert_eq!(UTC.ymd(2016, 6, 18).and_hms(13, 0, 0), project.start);
+    assert_eq!(UTC.ymd(2016, 6, 19).and_hms(1, 0, 0), project.end);
+    assert_eq!(UTC.ymd(2016, 6, 18).and_hms(12, 30, 0), project.updated_at);
+
+    let series = sql::get_project_readings(&conn, &project, None).unwrap();
+    assert_eq!(UTC.ymd(2016, 6, 18).and_hms_milli(14, 0, 2, 500), series[0].readings[0].timestamp);
+    assert_eq!(Some(100.0), series[0].readings[0].value);
+}
+
+#[test]
+fn test_migrations() {
+    use pibq::sql;
+    use pibq::sql::migrations::{self, Migration, MigrationError, MIGRATIONS};
+
+    // a database migrated before checksums were recorded
+    let conn = sql::get_connection(":memory:", false).unwrap();
+    conn.execute_batch("CREATE TABLE schema_migrations (version INTEGER NOT NULL)").unwrap();
+    conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+    conn.execute_batch("INSERT INTO schema_migrations (version) VALUES (1)").unwrap();
+
+    migrations::perform_migration(&conn).unwrap();
+    migrations::perform_migration(&conn).unwrap();
+
+    let states = migrations::get_migration_states(&conn).unwrap();
+    assert_eq!(MIGRATIONS.len(), states.len());
+    assert!(states.iter().all(|s| s.applied && !s.modified));
+
+    // a migration that fails part way leaves neither its changes nor its version behind
+    let mut failing: Vec<Migration> = MIGRATIONS.iter()
+        .map(|m| Migration { version: m.version, name: m.name, sql: m.sql })
+        .collect();
+    failing.push(Migration { version: 100, name: "100__broken.sql",
+                             sql: "CREATE TABLE half_done (id INTEGER); ALTER TABLE readings ADD COLUMN note TEXT; INSERT INTO missing VALUES (1);" });
+    match migrations::apply_migrations(&conn, &failing) {
+        Err(MigrationError::Failed(100, _)) => {},
+        r => panic!("expected migration 100 to fail, got {:?}", r)
+    }
+
+    let tables: i64 = conn.query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'half_done'", &[], |row| row.get(0)).unwrap();
+    assert_eq!(0, tables);
+    assert!(conn.prepare("SELECT note FROM readings").is_err());
+    let versions: i64 = conn.query_row("SELECT COUNT(*) FROM schema_migrations WHERE version = 100", &[], |row| row.get(0)).unwrap();
+    assert_eq!(0, versions);
+    migrations::perform_migration(&conn).unwrap();
+
+    conn.execute_batch("UPDATE schema_migrations SET checksum = 'edited' WHERE version = 3").unwrap();
+    match migrations::perform_migration(&conn) {
+        Err(MigrationError::Modified(3)) => {},
+        r => panic!("expected migration 3 to be modified, got {:?}", r)
+    }
+
+    conn.execute_batch("UPDATE schema_migrations SET checksum = NULL WHERE version = 3; \
+                        INSERT INTO schema_migrations (version, checksum) VALUES (9999, 'future')").unwrap();
+    match migrations::perform_migration(&conn) {
+        Err(MigrationError::Unknown(9999)) => {},
+        r => panic!("expected migration 9999 to be unknown, got {:?}", r)
+    }
+}